@@ -0,0 +1,122 @@
+use duplicarria2::net::{DiscoveryResponder, ServerNetManager, DISCOVERY_PORT};
+use duplicarria2::server::{Server, WorldConfig};
+use duplicarria2::shared::{world_size_is_valid, PlayerToken};
+use once_cell::sync::Lazy;
+use std::path::PathBuf;
+
+const USAGE: &str = "\
+Usage: duplicarria2-server [options]
+  --port <port>          UDP port to bind (default 24420)
+  --name <name>          Name shown to LAN server browsers (default Duplicarria server)
+  --no-discovery         Don't answer LAN discovery queries
+  --world <path>         World file to load/save (default world.dpwd next to the executable)
+  --seed <seed>          Seed used if the world has to be generated
+  --size <w>x<h>         Size used if the world has to be generated (default 8400x2400),
+                         multiples of 8 and at least 64 each way
+  --autosave <secs>      Autosave interval, 0 to disable (default 300)
+  --op <token>           Let the player with this token (see its player.token) run commands,
+                         can be repeated
+Commands typed into the console are run as the server, try help.";
+
+fn main() {
+    // Initialize game start timestamp.
+    lazy_static::initialize(&duplicarria2::time::TIMESTAMP);
+
+    // Get working directory.
+    static PATH: Lazy<PathBuf> = Lazy::new(duplicarria2::exe_dir);
+
+    // Defaults.
+    let mut port = 24420;
+    let mut name = String::from("Duplicarria server");
+    let mut discovery = true;
+    let mut world_config = WorldConfig {
+        path: Some(PATH.join("world.dpwd")),
+        seed: std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos() as u64,
+        width: 8400,
+        height: 2400,
+        autosave_interval: 300 * 1_000_000,
+        operators: Vec::new(),
+    };
+
+    // Parse arguments.
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().unwrap_or_else(|| exit_usage(&arg));
+        match arg.as_str() {
+            "--port" => port = value().parse().unwrap_or_else(|_| exit_usage("--port")),
+            "--name" => name = value(),
+            "--no-discovery" => discovery = false,
+            "--world" => world_config.path = Some(PathBuf::from(value())),
+            "--seed" => {
+                world_config.seed = value().parse().unwrap_or_else(|_| exit_usage("--seed"))
+            }
+            "--size" => {
+                let size = value();
+                let (w, h) = size
+                    .split_once('x')
+                    .and_then(|(w, h)| Some((w.parse().ok()?, h.parse().ok()?)))
+                    .filter(|&(w, h)| world_size_is_valid(w, h))
+                    .unwrap_or_else(|| exit_usage("--size"));
+                world_config.width = w;
+                world_config.height = h;
+            }
+            "--autosave" => {
+                let secs: u64 = value().parse().unwrap_or_else(|_| exit_usage("--autosave"));
+                world_config.autosave_interval = secs * 1_000_000;
+            }
+            "--op" => {
+                let token = PlayerToken::from_hex(&value()).unwrap_or_else(|| exit_usage("--op"));
+                world_config.operators.push(token);
+            }
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ => exit_usage(&arg),
+        }
+    }
+
+    // Start/Run server.
+    let (net_manager, port) = ServerNetManager::new(port);
+    println!("Listening on port {port}.");
+    let mut server = Server::new(&PATH, net_manager, world_config);
+    if discovery {
+        match DiscoveryResponder::bind(("0.0.0.0", DISCOVERY_PORT), &name, port) {
+            Ok(discovery) => server = server.with_discovery(discovery),
+            Err(err) => println!("Not answering LAN discovery: {err}."),
+        }
+    }
+
+    // Read commands from stdin.
+    let (console_send, console_recv) = crossbeam_channel::unbounded();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if !line.trim().is_empty() && console_send.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    // Ctrl+C saves the world before quitting.
+    let (shutdown_send, shutdown_recv) = crossbeam_channel::bounded(1);
+    ctrlc::set_handler(move || {
+        let _ = shutdown_send.try_send(());
+    })
+    .unwrap();
+    server
+        .with_console(console_recv)
+        .with_shutdown(shutdown_recv)
+        .run();
+    println!("Stopped.");
+}
+
+fn exit_usage(arg: &str) -> ! {
+    eprintln!("Bad argument: {arg}\n{USAGE}");
+    std::process::exit(2);
+}
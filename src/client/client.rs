@@ -5,13 +5,74 @@ use crate::time::*;
 use crate::{InputEvent, Window};
 use crossbeam_channel::Receiver;
 use std::path::Path;
+use std::time::Duration;
+
+// Time to wait for the server before giving up (in usecs).
+pub const CONNECT_TIMEOUT: u64 = 10_000_000;
+
+// Time between Connect retries (in usecs).
+const CONNECT_RETRY: u64 = 1_000_000;
+
+#[derive(Debug, PartialEq)]
+pub enum ConnectErr {
+    Timeout,
+    Rejected { version: u32, reason: String },
+    Disconnected,
+    BadWorld,
+    JoinRejected(String),
+    TileMismatch { tiles: u32 },
+}
+
+impl std::fmt::Display for ConnectErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "timed out"),
+            Self::Rejected { version, reason } => write!(
+                f,
+                "rejected by server: {reason} (ours {PROTOCOL_VERSION:08x}, theirs {version:08x})"
+            ),
+            Self::Disconnected => write!(f, "disconnected by server"),
+            Self::BadWorld => write!(f, "server sent an invalid world"),
+            Self::JoinRejected(reason) => write!(f, "join rejected: {reason}"),
+            Self::TileMismatch { tiles } => write!(
+                f,
+                "server has different tiles (ours {:08x}, theirs {tiles:08x})",
+                TILE_REGISTRY.hash()
+            ),
+        }
+    }
+}
+
+// Why a client left the game it joined.
+#[derive(Debug, PartialEq)]
+pub enum LeaveReason {
+    Quit,
+    Kicked(String),
+    Disconnected,
+
+    // Never got in.
+    CouldNotJoin(ConnectErr),
+}
+
+impl std::fmt::Display for LeaveReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Quit => write!(f, "quit"),
+            Self::Kicked(reason) => write!(f, "kicked by server: {reason}"),
+            Self::Disconnected => write!(f, "lost connection to the server"),
+            Self::CouldNotJoin(err) => write!(f, "could not join: {err}"),
+        }
+    }
+}
 
 pub struct Client<'a> {
     // Misc.
     root: &'static Path,
     #[allow(dead_code)]
     window: &'a Window,
-    server_port: u16,
+    net_manager: Option<ClientNetManager>,
+    name: String,
+    token: PlayerToken,
 
     // Update.
     input_events: Vec<InputEvent>,
@@ -34,11 +95,19 @@ pub struct Client<'a> {
 }
 
 impl<'a> Client<'a> {
-    pub fn new(root: &'static Path, window: &'a Window, server_port: u16) -> Self {
+    pub fn new(
+        root: &'static Path,
+        window: &'a Window,
+        net_manager: ClientNetManager,
+        name: String,
+        token: PlayerToken,
+    ) -> Self {
         Self {
             root,
             window,
-            server_port,
+            net_manager: Some(net_manager),
+            name,
+            token,
 
             input_events: vec![],
             update_ts: 0,
@@ -57,53 +126,32 @@ impl<'a> Client<'a> {
         }
     }
 
-    pub fn run(mut self, input_recv: Receiver<InputEvent>) -> ! {
-        let frametime = 16666_u64;
+    // Plays until leaving the game, and returns why.
+    pub fn run(mut self, input_recv: Receiver<InputEvent>) -> LeaveReason {
+        let frametime = FRAMETIME;
 
-        // Start net manager.
-        let server_dst = ("127.0.0.1", self.server_port);
-        let mut net_manager = ClientNetManager::new(server_dst);
+        // Take net manager.
+        let mut net_manager = self.net_manager.take().unwrap();
+        let server_addr = net_manager.server_addr();
 
         // Connect/wait.
-        'start: {
-            // Send reliable Connect.
-            net_manager.send_ru(serialize(&[ClientNetMessage::Connect { version: (0, 0) }]));
-
-            // Begin state sync.
-            loop {
-                // Wait for a net event.
-                net_manager.poll();
-                for net_event in net_manager.recv() {
-                    match net_event.kind {
-                        // Data net events.
-                        NetEventKind::Data(bytes) => {
-                            // Deserialize message.
-                            for net_event in deserialize(bytes).into_vec() {
-                                match net_event {
-                                    // On ConnectAccept, allow client to do client things.
-                                    ServerNetMessage::ConnectAccept => {
-                                        break 'start;
-                                    }
-
-                                    _ => log!("Unhandled event received during join sequence."),
-                                }
-                            }
-                        }
-
-                        // Server booted us, probably for taking too long.
-                        NetEventKind::Disconnect => panic!(),
-
-                        // Connect message, probably ignore it?
-                        _ => {}
-                    }
-                }
-            }
-        };
+        if let Err(err) = connect(&mut net_manager) {
+            log!("Could not connect to {server_addr}: {err}.");
+            return LeaveReason::CouldNotJoin(err);
+        }
 
+        // Join.
+        self.update_state =
+            match GameUpdateState::new(self.root, net_manager, &self.name, self.token) {
+                Ok(update_state) => Some(update_state),
+                Err(err) => {
+                    log!("Could not join {server_addr}: {err}.");
+                    return LeaveReason::CouldNotJoin(err);
+                }
+            };
         self.update_ts = crate::timestamp_as_usecs();
-        self.update_state = Some(GameUpdateState::new(self.root, net_manager));
 
-        loop {
+        let reason = loop {
             // Record inputs.
             let input_events: Vec<InputEvent> = input_recv.try_iter().collect();
             self.input_events.extend(input_events.clone());
@@ -123,8 +171,8 @@ impl<'a> Client<'a> {
                     let input_events = std::mem::take(&mut self.input_events).into_iter();
 
                     // Prestep.
-                    if update_state.prestep(self.update_ts, input_events) {
-                        break;
+                    if let Some(reason) = update_state.prestep(self.update_ts, input_events) {
+                        break reason;
                     }
                 }
                 self.prestep_acc += timestamp_as_usecs() - ts;
@@ -153,6 +201,19 @@ impl<'a> Client<'a> {
                     (self.step_acc / self.update_n) as f32 * 0.001,
                     (self.poststep_acc / self.update_n) as f32 * 0.001,
                 );
+                    let stats = update_state.link_stats();
+                    let estimated_tick = update_state
+                        .estimated_server_tick(timestamp_as_usecs())
+                        .map_or_else(|| String::from("unknown"), |tick| tick.to_string());
+                    log!(
+                        "Link: {:.2}ms RTT (+-{:.2}ms), {}/{} pings answered.\n  Server tick: {} received, {} estimated.",
+                        stats.rtt as f32 * 0.001,
+                        stats.rtt_var as f32 * 0.001,
+                        stats.pongs_received,
+                        stats.pings_sent,
+                        update_state.server_tick(),
+                        estimated_tick,
+                    );
                     self.prestep_acc = 0;
                     self.step_acc = 0;
                     self.poststep_acc = 0;
@@ -186,8 +247,105 @@ impl<'a> Client<'a> {
                     self.render_n = 0;
                 }
             }
+        };
+
+        // Leave. There is no menu to go back to yet, so this ends the session.
+        self.update_state = None;
+        self.game_render_desc = None;
+        if reason != LeaveReason::Quit {
+            log!("Left {server_addr}: {reason}.");
         }
+        reason
+    }
+}
+
+pub fn connect(net_manager: &mut ClientNetManager) -> Result<(), ConnectErr> {
+    let start_ts = timestamp_as_usecs();
+    let mut retry_ts = start_ts;
+
+    loop {
+        let ts = timestamp_as_usecs();
+        if ts - start_ts >= CONNECT_TIMEOUT {
+            return Err(ConnectErr::Timeout);
+        }
+
+        // (Re)send Connect. The server answers duplicates, so a lost accept isn't fatal.
+        if ts >= retry_ts {
+            let connect = Handshake::Connect {
+                version: PROTOCOL_VERSION,
+            };
+            net_manager.send_ru(serialize_handshake(&connect));
+            retry_ts = ts + CONNECT_RETRY;
+        }
+
+        // Wait for a net event.
+        net_manager.poll();
+        for net_event in net_manager.recv() {
+            match net_event.kind {
+                // Data net events.
+                NetEventKind::Data { bytes, .. } => match deserialize_handshake(&bytes) {
+                    // On Accept, allow client to do client things.
+                    Ok(Handshake::Accept) => return Ok(()),
 
-        std::process::exit(0);
+                    Ok(Handshake::Reject { version, reason }) => {
+                        return Err(ConnectErr::Rejected { version, reason })
+                    }
+
+                    Ok(_) => log!("Unhandled event received during connect sequence."),
+
+                    Err(err) => log!("WARNING: Received a bad handshake: {err}."),
+                },
+
+                // Server booted us.
+                NetEventKind::Disconnect => return Err(ConnectErr::Disconnected),
+
+                // Connect message, probably ignore it?
+                NetEventKind::Connect => {}
+            }
+        }
+
+        std::thread::sleep(Duration::from_millis(1));
+    }
+}
+
+// The token identifies the player to servers across sessions. Created on first use.
+pub fn load_or_create_token(path: &Path) -> PlayerToken {
+    if let Some(token) = std::fs::read_to_string(path)
+        .ok()
+        .and_then(|hex| PlayerToken::from_hex(hex.trim()))
+    {
+        return token;
+    }
+
+    // Anyone who knows the token can play as (and with the rights of) the player, so it comes
+    // from the OS, and only the owner may read the file.
+    let mut bytes = [0; 16];
+    getrandom::getrandom(&mut bytes).expect("no OS randomness for the player token");
+    let token = PlayerToken(u128::from_le_bytes(bytes));
+    if let Err(err) = write_private(path, &token.to_hex()) {
+        log!(
+            "WARNING: Could not save player token to {}: {err}.",
+            path.display()
+        );
+    }
+    token
+}
+
+// Writes a file only the current user can read (where the OS supports that).
+fn write_private(path: &Path, contents: &str) -> std::io::Result<()> {
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+        options.mode(0o600);
+
+        // The mode only applies to new files.
+        if let Ok(metadata) = std::fs::metadata(path) {
+            let mut permissions = metadata.permissions();
+            permissions.set_mode(0o600);
+            std::fs::set_permissions(path, permissions)?;
+        }
     }
+    std::io::Write::write_all(&mut options.open(path)?, contents.as_bytes())
 }
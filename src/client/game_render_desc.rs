@@ -11,7 +11,12 @@ pub struct SpriteRenderDesc {
 }
 
 #[derive(Copy, Clone, Debug)]
-pub struct TileRenderDesc(pub Tile);
+pub struct TileRenderDesc {
+    pub tile: Tile,
+
+    // How cracked it is from being mined, 0 (not at all) to CRACK_STAGES.
+    pub crack: u8,
+}
 
 #[derive(Clone, Debug)]
 pub struct GameRenderDesc {
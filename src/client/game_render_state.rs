@@ -67,6 +67,9 @@ struct SpriteVertexInput {
     sprite_uv: [f32; 2],
 }
 
+// Crack overlays in the tile sheet, one per stage from left to right.
+const CRACK_UV: (f32, f32) = (96., 0.);
+
 #[allow(dead_code)]
 pub struct GameRenderState<'a> {
     // State.
@@ -802,7 +805,36 @@ impl<'a> GameRenderState<'a> {
     fn process_sprite_state(&mut self, game_render_desc: &GameRenderDesc) -> (Buffer, u32) {
         let mut sprites = Vec::with_capacity(4 * game_render_desc.sprites.len());
 
-        for &SpriteRenderDesc { x, y, u, v, w, h } in game_render_desc.sprites.iter() {
+        // Cracks go over the tiles being mined, under everything else. Bg tiles only show theirs
+        // when nothing is in front.
+        let cracks = game_render_desc
+            .fg_tiles
+            .iter()
+            .zip(game_render_desc.bg_tiles.iter())
+            .enumerate()
+            .filter_map(|(index, (fg, bg))| {
+                let crack = match fg.tile {
+                    crate::shared::Tile::None => bg.crack,
+                    _ => fg.crack,
+                };
+                if crack == 0 {
+                    return None;
+                }
+                let x = index % game_render_desc.tiles_w + game_render_desc.tiles_x;
+                let y = index / game_render_desc.tiles_w + game_render_desc.tiles_y;
+                Some(SpriteRenderDesc {
+                    x: 16. * x as f32,
+                    y: 16. * y as f32,
+                    w: 16.,
+                    h: 16.,
+                    u: CRACK_UV.0 + 16. * (crack - 1) as f32,
+                    v: CRACK_UV.1,
+                })
+            });
+
+        for SpriteRenderDesc { x, y, u, v, w, h } in
+            cracks.chain(game_render_desc.sprites.iter().copied())
+        {
             sprites.extend_from_slice(&[
                 SpriteVertexInput {
                     sprite_xy: [x, y],
@@ -853,7 +885,7 @@ impl<'a> GameRenderState<'a> {
                         // Fill FG.
                         'skip_fg: {
                             let tile_texture_properties = tile_texture_properties_lookup
-                                [game_render_desc.fg_tiles[index].0 as usize];
+                                [game_render_desc.fg_tiles[index].tile.0 as usize];
 
                             // Get texture UV.
                             let u = tile_texture_properties.u;
@@ -877,8 +909,8 @@ impl<'a> GameRenderState<'a> {
                             let mask_u = [ index - stride, index - stride + 1, index + 1, index + stride + 1 ]
                                 .into_iter()
                                 .rev()
-                                .map(|index| game_render_desc.fg_tiles[index].0)
-                                .map(|tile| tile_texture_properties_lookup[tile as usize].depth)
+                                .map(|index| game_render_desc.fg_tiles[index].tile)
+                                .map(|tile| tile_texture_properties_lookup[tile.0 as usize].depth)
                                 .map(|dep| (depth > dep) as u8)
                                 .reduce(|acc, bit| (acc << 1) | bit)
                                 .map(|out| (out << 2) as f32)
@@ -887,8 +919,8 @@ impl<'a> GameRenderState<'a> {
                             let mask_v  = [index + stride, index + stride - 1, index - 1, index - stride - 1 ]
                                 .into_iter()
                                 .rev()
-                                .map(|index| game_render_desc.fg_tiles[index].0)
-                                .map(|tile| tile_texture_properties_lookup[tile as usize].depth)
+                                .map(|index| game_render_desc.fg_tiles[index].tile)
+                                .map(|tile| tile_texture_properties_lookup[tile.0 as usize].depth)
                                 .map(|dep| (depth > dep) as u8)
                                 .reduce(|acc, bit| (acc << 1) | bit)
                                 .map(|out| (out << 2) as f32)
@@ -924,7 +956,7 @@ impl<'a> GameRenderState<'a> {
                         // Fill FG.
                         'skip_bg: {
                             let tile_texture_properties = tile_texture_properties_lookup
-                                [game_render_desc.bg_tiles[index].0 as usize];
+                                [game_render_desc.bg_tiles[index].tile.0 as usize];
 
                             // Get texture UV.
                             let u = tile_texture_properties.u;
@@ -948,8 +980,8 @@ impl<'a> GameRenderState<'a> {
                             let mask_u = [ index - stride, index - stride + 1, index + 1, index + stride + 1 ]
                                 .into_iter()
                                 .rev()
-                                .map(|index| game_render_desc.bg_tiles[index].0)
-                                .map(|tile| tile_texture_properties_lookup[tile as usize].depth)
+                                .map(|index| game_render_desc.bg_tiles[index].tile)
+                                .map(|tile| tile_texture_properties_lookup[tile.0 as usize].depth)
                                 .map(|dep| (depth > dep) as u8)
                                 .reduce(|acc, bit| (acc << 1) | bit)
                                 .map(|out| (out << 2) as f32)
@@ -958,8 +990,8 @@ impl<'a> GameRenderState<'a> {
                             let mask_v  = [index + stride, index + stride - 1, index - 1, index - stride - 1 ]
                                 .into_iter()
                                 .rev()
-                                .map(|index| game_render_desc.bg_tiles[index].0)
-                                .map(|tile| tile_texture_properties_lookup[tile as usize].depth)
+                                .map(|index| game_render_desc.bg_tiles[index].tile)
+                                .map(|tile| tile_texture_properties_lookup[tile.0 as usize].depth)
                                 .map(|dep| (depth > dep) as u8)
                                 .reduce(|acc, bit| (acc << 1) | bit)
                                 .map(|out| (out << 2) as f32)
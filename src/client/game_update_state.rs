@@ -1,14 +1,20 @@
-use crate::client::{GameRenderDesc, SpriteRenderDesc, TileRenderDesc};
-use crate::net::{ClientNetManager, NetEventKind};
+use crate::client::{
+    log, ConnectErr, GameRenderDesc, LeaveReason, SpriteRenderDesc, TileRenderDesc, CONNECT_TIMEOUT,
+};
+use crate::net::{
+    ClientNetManager, Delivery, LinkStats, NetEvent, NetEventKind, PingTracker, Priority, SendQueue,
+};
 use crate::shared::*;
-use crate::shared::{Tile, TILE_LIGHT_PROPERTIES, TILE_SIZE};
+use crate::time::timestamp_as_usecs;
 use crate::window::InputEvent;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::path::Path;
 
 pub struct GameUpdateState {
     // Net manager.
     net_manager: ClientNetManager,
+    send_queue: SendQueue<ClientNetMessage>,
+    deferring: bool,
 
     //Input.
     window_width: usize,
@@ -21,6 +27,16 @@ pub struct GameUpdateState {
     left_queue: usize,
     right_queue: usize,
     jump_queue: usize,
+    //
+    mine_layer: Option<TileLayer>,
+    mine_target: Option<(usize, usize, TileLayer)>,
+
+    // Copy of the player's inventory, as of the newest InventorySync. The selected hotbar slot,
+    // whether the rest of the inventory is open, and the slot picked up to be moved, if any.
+    inventory: Inventory,
+    hotbar_slot: usize,
+    inventory_open: bool,
+    held_slot: Option<usize>,
 
     // Viewport.
     viewport_x: usize,
@@ -41,14 +57,82 @@ pub struct GameUpdateState {
     chunk_seqs: Box<[u32]>,
     fg_tiles: Box<[Tile]>,
     bg_tiles: Box<[Tile]>,
+    liquids: Box<[Liquid]>,
+
+    // Crack stages of tiles someone is mining, by index.
+    cracks: HashMap<(usize, TileLayer), u8>,
+
+    // Drops, as of the newest ItemDropSync (from tick).
+    item_drops: HashMap<ItemDropId, ItemDrop>,
+    item_drop_tick: u32,
 
     // Humanoids.
     player_id: HumanoidId,
     humanoids: HashMap<HumanoidId, Humanoid>,
+
+    // Prediction. Inputs the server hasn't acked yet, and the predicted player after each one.
+    input_seq: u32,
+    pending_inputs: VecDeque<(u32, HumanoidInput)>,
+    predictions: VecDeque<(u32, Humanoid)>,
+
+    // Decoded HumanoidSyncs the server may still delta against, and the newest seq received.
+    baselines: VecDeque<(u32, HashMap<HumanoidId, Humanoid>)>,
+    snapshot_ack: u32,
+
+    // Interpolation. Server snapshots of the other humanoids, oldest first.
+    snapshots: VecDeque<Snapshot>,
+
+    // Players online.
+    roster: Vec<PlayerInfo>,
+
+    // Set once the game is over for this client.
+    leave: Option<LeaveReason>,
+
+    // Chat line being typed, if typing.
+    chat_input: Option<String>,
+
+    // Round trip time and clock offset to the server, the newest server tick received, and the
+    // server tick at some server time (from the newest Pong).
+    ping: PingTracker,
+    server_tick: u32,
+    tick_sync: Option<(u32, u64)>,
+}
+
+// A HumanoidSync as it arrived (without the local player).
+struct Snapshot {
+    ts: u64,
+    humanoids: HashMap<HumanoidId, Humanoid>,
 }
 
+// Bytes sent to the server per tick.
+const SEND_BUDGET: usize = 2 * 1024;
+
+// Unacked input frames kept around for replay (about 2 seconds).
+const MAX_PENDING_INPUTS: usize = 120;
+
+// How far a prediction may drift from the server before it gets corrected.
+const PREDICTION_EPSILON: f32 = 0.01;
+
+// Decoded snapshots kept around as delta baselines.
+const MAX_BASELINES: usize = 64;
+
+// Remote humanoids are rendered this far in the past (in usecs), so there is usually a snapshot
+// on either side to interpolate between.
+const INTERPOLATION_DELAY: u64 = 100_000;
+
+// How far past the newest snapshot remote humanoids keep moving before they freeze (in usecs).
+const MAX_EXTRAPOLATION: u64 = 200_000;
+
+// Snapshots older than this (in usecs) are dropped.
+const SNAPSHOT_LIFETIME: u64 = 1_000_000;
+
 impl GameUpdateState {
-    pub fn new(_root: &'static Path, mut net_manager: ClientNetManager) -> Self {
+    pub fn new(
+        _root: &'static Path,
+        mut net_manager: ClientNetManager,
+        name: &str,
+        token: PlayerToken,
+    ) -> Result<Self, ConnectErr> {
         let viewport_x = 32;
         let viewport_y = 32;
         let viewport_w = 1280;
@@ -62,34 +146,70 @@ impl GameUpdateState {
         let mut chunk_seqs: Box<[u32]> = Box::new([]);
         let mut fg_tiles: Box<[Tile]> = Box::new([]);
         let mut bg_tiles: Box<[Tile]> = Box::new([]);
+        let mut liquids: Box<[Liquid]> = Box::new([]);
 
         let mut player_id = HumanoidId::new();
         let humanoids = HashMap::new();
 
+        let mut inventory = Inventory::new();
+
+        // Whatever arrived after Start, handled once the game exists.
+        let mut leftover_msgs = Vec::new();
+        let mut leftover_events = Vec::new();
+
         // Start join sequence.
         {
             // Initial join sequence.
-            net_manager.send_ru(serialize(&[ClientNetMessage::Join]));
+            net_manager.send_ru(serialize(&[ClientNetMessage::Join {
+                name: name.to_string(),
+                token,
+                view_w: viewport_w as u16,
+                view_h: viewport_h as u16,
+            }]));
             net_manager.poll();
 
+            let start_ts = timestamp_as_usecs();
             'start: loop {
+                // The initial sync is large, so be more patient than when connecting.
+                if timestamp_as_usecs() - start_ts >= 3 * CONNECT_TIMEOUT {
+                    return Err(ConnectErr::Timeout);
+                }
+
                 // Get net messages.
                 net_manager.poll();
 
                 // Get all events.
-                for net_event in net_manager.recv() {
+                let mut net_events = net_manager.recv();
+                while let Some(net_event) = net_events.next() {
                     match net_event.kind {
                         // Data net event.
-                        NetEventKind::Data(bytes) => {
-                            for msg in deserialize(bytes).to_vec() {
+                        NetEventKind::Data { bytes, .. } => {
+                            let msgs = match deserialize(&bytes) {
+                                Ok(msgs) => msgs,
+                                Err(err) => {
+                                    log!("WARNING: Received a bad packet: {err}.");
+                                    continue;
+                                }
+                            };
+                            let mut msgs = msgs.into_vec().into_iter();
+                            while let Some(msg) = msgs.next() {
                                 match msg {
                                     ServerNetMessage::JoinAccept {
+                                        tiles,
                                         width,
                                         height,
                                         id,
                                         spawn_x: inner_spawn_x,
                                         spawn_y: inner_spawn_y,
                                     } => {
+                                        if tiles != TILE_REGISTRY.hash() {
+                                            return Err(ConnectErr::TileMismatch { tiles });
+                                        }
+                                        if !world_size_is_valid(width as usize, height as usize) {
+                                            log!("WARNING: Bad world size {width}x{height}.");
+                                            return Err(ConnectErr::BadWorld);
+                                        }
+
                                         // Player.
                                         player_id = id;
 
@@ -104,58 +224,76 @@ impl GameUpdateState {
                                             vec![Tile::None; world_w * world_h].into_boxed_slice();
                                         bg_tiles =
                                             vec![Tile::None; world_w * world_h].into_boxed_slice();
+                                        liquids = vec![Liquid::NONE; world_w * world_h]
+                                            .into_boxed_slice();
+                                    }
+
+                                    ServerNetMessage::ChunkSync { chunks, .. } => {
+                                        apply_chunk_sync(
+                                            &chunks,
+                                            world_w,
+                                            &mut chunk_seqs,
+                                            &mut fg_tiles,
+                                            &mut bg_tiles,
+                                            &mut liquids,
+                                        );
                                     }
 
-                                    ServerNetMessage::ChunkSync {
-                                        x,
-                                        y,
-                                        seq,
-                                        fg_tiles: inner_fg_tiles,
-                                        bg_tiles: inner_bg_tiles,
+                                    ServerNetMessage::InventorySync {
+                                        inventory: inner_inventory,
                                     } => {
-                                        let cx = x as usize;
-                                        let cy = y as usize;
-                                        let cur_seq =
-                                            &mut chunk_seqs[cx + cy * world_w / CHUNK_SIZE];
-
-                                        // If the current sequence isn't less, skip this chunk.
-                                        if *cur_seq >= seq {
-                                            continue;
+                                        if inner_inventory.is_valid() {
+                                            inventory = inner_inventory;
+                                        } else {
+                                            log!("WARNING: Received a bad inventory.");
                                         }
+                                    }
 
-                                        *cur_seq = seq;
-                                        for y in 0..CHUNK_SIZE {
-                                            for x in 0..CHUNK_SIZE {
-                                                let src_index = x + y * CHUNK_SIZE;
-                                                let dst_index = x
-                                                    + cx * CHUNK_SIZE
-                                                    + (y + cy * CHUNK_SIZE) * world_w;
-                                                fg_tiles[dst_index] = inner_fg_tiles[src_index];
-                                                bg_tiles[dst_index] = inner_bg_tiles[src_index];
-                                            }
-                                        }
+                                    // Humanoids and drops are synced from the moment the
+                                    // player exists, which can overtake the join messages. Not
+                                    // needed yet.
+                                    ServerNetMessage::HumanoidSync { .. } => {}
+                                    ServerNetMessage::ItemDropSync { .. } => {}
+
+                                    // Edits are sent after Start, on the same ordered stream.
+                                    ServerNetMessage::TileSync { .. } => {}
+                                    ServerNetMessage::TileCrack { .. } => {}
+
+                                    ServerNetMessage::JoinReject { reason } => {
+                                        return Err(ConnectErr::JoinRejected(reason));
                                     }
 
                                     ServerNetMessage::Start => {
+                                        leftover_msgs.extend(msgs);
+                                        leftover_events.extend(net_events);
                                         net_manager
                                             .send_ru(serialize(&[ClientNetMessage::JoinComplete]));
                                         break 'start;
                                     }
 
-                                    _ => panic!("PANIC: {:?}", msg),
+                                    _ => {
+                                        log!("WARNING: Unexpected message while joining: {msg:?}.")
+                                    }
                                 }
                             }
                         }
 
-                        _ => {}
+                        // Server booted us.
+                        NetEventKind::Disconnect => return Err(ConnectErr::Disconnected),
+
+                        NetEventKind::Connect => {}
                     }
                 }
+
+                std::thread::sleep(std::time::Duration::from_millis(1));
             }
         }
 
-        Self {
+        let mut game = Self {
             // Net manager.
             net_manager,
+            send_queue: SendQueue::new(SEND_BUDGET),
+            deferring: false,
 
             // Input.
             window_width: 0,
@@ -168,6 +306,15 @@ impl GameUpdateState {
             left_queue: 0,
             right_queue: 0,
             jump_queue: 0,
+            //
+            mine_layer: None,
+            mine_target: None,
+
+            // Inventory.
+            inventory,
+            hotbar_slot: 0,
+            inventory_open: false,
+            held_slot: None,
 
             // Viewport.
             viewport_x,
@@ -184,28 +331,84 @@ impl GameUpdateState {
             chunk_seqs,
             fg_tiles,
             bg_tiles,
+            liquids,
+            cracks: HashMap::new(),
+
+            // Drops.
+            item_drops: HashMap::new(),
+            item_drop_tick: 0,
 
             // Humanoids.
             player_id,
             humanoids,
+
+            // Prediction.
+            input_seq: 0,
+            pending_inputs: VecDeque::new(),
+            predictions: VecDeque::new(),
+
+            // Deltas.
+            baselines: VecDeque::new(),
+            snapshot_ack: 0,
+
+            // Interpolation.
+            snapshots: VecDeque::new(),
+
+            // Players.
+            roster: Vec::new(),
+
+            leave: None,
+
+            chat_input: None,
+
+            ping: PingTracker::new(),
+            server_tick: 0,
+            tick_sync: None,
+        };
+
+        let ts = timestamp_as_usecs();
+        for msg in leftover_msgs {
+            game.handle_msg(ts, msg);
         }
+        for net_event in leftover_events {
+            game.handle_net_event(ts, net_event);
+        }
+        if game.leave.is_some() {
+            return Err(ConnectErr::Disconnected);
+        }
+
+        Ok(game)
     }
 
-    pub fn prestep(&mut self, ts: u64, input_events: impl Iterator<Item = InputEvent>) -> bool {
+    // Returns why the game is over, once it is.
+    pub fn prestep(
+        &mut self,
+        ts: u64,
+        input_events: impl Iterator<Item = InputEvent>,
+    ) -> Option<LeaveReason> {
         // Shift all input queues.
         let shift = |queue: &mut _| *queue = *queue << 1 | *queue & 1;
         shift(&mut self.right_queue);
         shift(&mut self.left_queue);
         shift(&mut self.jump_queue);
 
-        // Process input events.
-        let exit = self.handle_input_events(ts, input_events);
+        // Process input events. On exit, let the server know right away.
+        if self.handle_input_events(ts, input_events) {
+            self.send_queue
+                .push(Priority::High, Delivery::Ru, ClientNetMessage::Leave);
+            self.net_manager.flush(&mut self.send_queue);
+            self.net_manager.poll();
+            return Some(LeaveReason::Quit);
+        }
+
+        // Start or stop mining whatever is under the mouse.
+        self.update_mining();
 
         // Process net events.
         self.net_manager.poll();
         self.handle_net_events(ts);
 
-        return exit;
+        self.leave.take()
     }
 
     pub fn step(&mut self, _ts: u64, ft: u64) {
@@ -218,23 +421,34 @@ impl GameUpdateState {
             self.time = 0.;
         }
 
-        // Player state stuff.
-        if let Some(player) = self.humanoids.get_mut(&self.player_id) {
-            player.input = HumanoidInput {
-                jump_queue: self.jump_queue as u8,
-                left_queue: self.left_queue as u8,
-                right_queue: self.right_queue as u8,
-            };
+        // Player state stuff. Every step is an input frame, which the server simulates too.
+        let input = HumanoidInput {
+            jump_queue: self.jump_queue as u8,
+            left_queue: self.left_queue as u8,
+            right_queue: self.right_queue as u8,
+        };
+        self.input_seq += 1;
+        self.pending_inputs
+            .push_back((self.input_seq, input.clone()));
+        if self.pending_inputs.len() > MAX_PENDING_INPUTS {
+            self.pending_inputs.pop_front();
         }
 
-        // Humanoid input pass.
-        update_humanoid_inputs(&mut self.humanoids);
+        // Predict the player. Everything else is interpolated from server snapshots.
+        if let Some(player) = self.humanoids.remove(&self.player_id) {
+            let player = self.step_humanoid(player, input, ft);
 
-        // Humanoid physics pass.
-        update_humanoid_physics(&mut self.humanoids, ft);
+            // Remember the prediction, to check against the server later.
+            self.predictions.push_back((self.input_seq, player.clone()));
+            if self.predictions.len() > MAX_PENDING_INPUTS {
+                self.predictions.pop_front();
+            }
+
+            self.humanoids.insert(self.player_id, player);
+        }
 
-        // Humanoid tile collision pass.
-        resolve_humanoid_tile_collisions(&mut self.humanoids, self.world_w, &self.fg_tiles);
+        // Drops move on between syncs.
+        update_item_drops(&mut self.item_drops, self.world_w, &self.fg_tiles, ft);
 
         // Clamp position (TODO: right-bottom world clamp).
         if let Some(player) = self.humanoids.get(&mut self.player_id) {
@@ -247,17 +461,38 @@ impl GameUpdateState {
         self.viewport_y = std::cmp::max(2 * TILE_SIZE, self.viewport_y);
     }
 
-    pub fn poststep(&mut self, _ts: u64) -> GameRenderDesc {
-        // Send the server RequestChunk messages based on view.
-        request_chunks_from_server(self);
+    pub fn poststep(&mut self, ts: u64) -> GameRenderDesc {
+        // Send the server the latest input frames.
+        if !self.pending_inputs.is_empty() {
+            let skip = self.pending_inputs.len().saturating_sub(INPUT_REDUNDANCY);
+            let msg = ClientNetMessage::PlayerInput {
+                seq: self.input_seq,
+                inputs: self
+                    .pending_inputs
+                    .iter()
+                    .skip(skip)
+                    .map(|(_, input)| input.clone())
+                    .collect(),
+                snapshot_ack: self.snapshot_ack,
+            };
+            self.send_queue.push(Priority::High, Delivery::Uu, msg);
+        }
+
+        // Measure the link.
+        if let Some(seq) = self.ping.poll(timestamp_as_usecs()) {
+            let msg = ClientNetMessage::Ping { seq };
+            self.send_queue.push(Priority::High, Delivery::Uu, msg);
+        }
 
-        // Send the server the player's current state.
-        if let Some(player) = self.humanoids.get(&self.player_id) {
-            let bytes = serialize(&[ClientNetMessage::SyncPlayer {
-                player: player.clone(),
-            }]);
-            self.net_manager.send_uu(bytes);
+        // Send as much as the budget allows.
+        let report = self.net_manager.flush(&mut self.send_queue);
+        if report.deferred > 0 && !self.deferring {
+            log!(
+                "Over the send budget, deferring {} messages.",
+                report.deferred
+            );
         }
+        self.deferring = report.deferred > 0;
 
         // Calculate light map.
         let (light_x, light_y, light_w, light_h, r, g, b) = calculate_light_map(self);
@@ -266,7 +501,7 @@ impl GameUpdateState {
         let (tiles_x, tiles_y, tiles_w, tiles_h, fg_tiles, bg_tiles) = clone_visible_tile_map(self);
 
         // Clone the sprites in the visible range..
-        let sprites = clone_visible_sprites(self);
+        let sprites = clone_visible_sprites(self, ts);
 
         // Poll the network to send all messages.
         self.net_manager.poll();
@@ -297,64 +532,250 @@ impl GameUpdateState {
         }
     }
 
-    fn handle_net_events(&mut self, _ts: u64) {
-        for e in self.net_manager.recv() {
-            match e.kind {
-                NetEventKind::Data(bytes) => {
-                    for msg in deserialize(bytes).to_vec() {
-                        match msg {
-                            ServerNetMessage::ChunkSync {
-                                x,
-                                y,
-                                seq,
-                                fg_tiles,
-                                bg_tiles,
-                            } => {
-                                let cx = x as usize;
-                                let cy = y as usize;
-                                let cur_seq =
-                                    &mut self.chunk_seqs[cx + cy * self.world_w / CHUNK_SIZE];
-
-                                if !(*cur_seq < seq) {
-                                    continue;
-                                }
+    // Stops at the first event that makes us leave (see self.leave).
+    fn handle_net_events(&mut self, ts: u64) {
+        let events: Vec<_> = self.net_manager.recv().collect();
+        for e in events {
+            if self.leave.is_some() {
+                break;
+            }
+            self.handle_net_event(ts, e);
+        }
+    }
 
-                                *cur_seq = seq;
-                                for y in 0..CHUNK_SIZE {
-                                    for x in 0..CHUNK_SIZE {
-                                        let src_index = x + y * CHUNK_SIZE;
-                                        let dst_index = x
-                                            + cx * CHUNK_SIZE
-                                            + (y + cy * CHUNK_SIZE) * self.world_w;
-                                        self.fg_tiles[dst_index] = fg_tiles[src_index];
-                                        self.bg_tiles[dst_index] = bg_tiles[src_index];
-                                    }
-                                }
-                            }
+    fn handle_net_event(&mut self, ts: u64, e: NetEvent) {
+        match e.kind {
+            NetEventKind::Data { bytes, .. } => {
+                let msgs = match deserialize(&bytes) {
+                    Ok(msgs) => msgs,
+                    Err(err) => {
+                        log!("WARNING: Received a bad packet: {err}.");
+                        return;
+                    }
+                };
+                for msg in msgs.into_vec() {
+                    self.handle_msg(ts, msg);
+                }
+            }
+            NetEventKind::Disconnect => {
+                self.leave.get_or_insert(LeaveReason::Disconnected);
+            }
+            NetEventKind::Connect => {}
+        }
+    }
+
+    fn handle_msg(&mut self, ts: u64, msg: ServerNetMessage) {
+        match msg {
+            ServerNetMessage::ChunkSync { tick, chunks } => {
+                self.server_tick = self.server_tick.max(tick);
+                apply_chunk_sync(
+                    &chunks,
+                    self.world_w,
+                    &mut self.chunk_seqs,
+                    &mut self.fg_tiles,
+                    &mut self.bg_tiles,
+                    &mut self.liquids,
+                );
+            }
 
-                            ServerNetMessage::HumanoidSync { humanoids } => {
-                                // Clone player (if it can be found).
-                                let player = self.humanoids.get(&self.player_id).cloned();
+            ServerNetMessage::TileSync {
+                tick,
+                x,
+                y,
+                seq,
+                fg_tile,
+                bg_tile,
+                liquid,
+            } => {
+                self.server_tick = self.server_tick.max(tick);
+                let (x, y) = (x as usize, y as usize);
+                if x >= self.world_w || y >= self.world_h {
+                    log!("WARNING: Received a tile outside the world.");
+                    return;
+                }
+                if !fg_tile.is_registered() || !bg_tile.is_registered() || !liquid.is_valid() {
+                    log!("WARNING: Received an unknown tile.");
+                    return;
+                }
+                let cur_seq = &mut self.chunk_seqs
+                    [x / CHUNK_SIZE + y / CHUNK_SIZE * self.world_w / CHUNK_SIZE];
 
-                                // Swap.
-                                self.humanoids = humanoids;
+                // Only apply on top of the previous seq. A chunk that is behind
+                // gets streamed again in full when it comes back into view.
+                if cur_seq.checked_add(1) != Some(seq) {
+                    return;
+                }
 
-                                // Put player back in.
-                                if let Some(player) = player {
-                                    self.humanoids.get_mut(&self.player_id).map(|p| *p = player);
-                                }
-                            }
+                *cur_seq = seq;
+                let index = x + y * self.world_w;
+                self.fg_tiles[index] = fg_tile;
+                self.bg_tiles[index] = bg_tile;
+                self.liquids[index] = liquid;
+                self.cracks.remove(&(index, TileLayer::Fg));
+                self.cracks.remove(&(index, TileLayer::Bg));
+            }
 
-                            ServerNetMessage::Ping => self
-                                .net_manager
-                                .send_ru(serialize(&[ClientNetMessage::Ping])),
+            ServerNetMessage::TileCrack {
+                tick,
+                x,
+                y,
+                layer,
+                stage,
+            } => {
+                self.server_tick = self.server_tick.max(tick);
+                let (x, y) = (x as usize, y as usize);
+                if x >= self.world_w || y >= self.world_h || stage > CRACK_STAGES {
+                    log!("WARNING: Received a bad tile crack.");
+                    return;
+                }
+                let index = x + y * self.world_w;
+                match stage {
+                    0 => self.cracks.remove(&(index, layer)),
+                    stage => self.cracks.insert((index, layer), stage),
+                };
+            }
 
-                            _ => panic!("Uncaught event: {msg:?}."),
-                        }
+            ServerNetMessage::InventorySync { inventory } => {
+                if !inventory.is_valid() {
+                    log!("WARNING: Received a bad inventory.");
+                    return;
+                }
+                self.inventory = inventory;
+            }
+
+            ServerNetMessage::ItemDropSync { tick, drops } => {
+                self.server_tick = self.server_tick.max(tick);
+
+                // Stale.
+                if tick < self.item_drop_tick {
+                    return;
+                }
+                self.item_drop_tick = tick;
+                self.item_drops = drops
+                    .into_iter()
+                    .filter(|(_, drop)| item_drop_is_valid(drop, self.world_w, self.world_h))
+                    .collect();
+            }
+
+            ServerNetMessage::HumanoidSync {
+                tick,
+                seq,
+                baseline,
+                ack,
+                updated,
+                removed,
+            } => {
+                self.server_tick = self.server_tick.max(tick);
+
+                // Stale.
+                if seq <= self.snapshot_ack {
+                    return;
+                }
+
+                // Rebuild the full snapshot from its baseline. The server only
+                // uses acked baselines, so anything older can go.
+                while self
+                    .baselines
+                    .front()
+                    .is_some_and(|(seq, _)| *seq < baseline)
+                {
+                    self.baselines.pop_front();
+                }
+                let empty = HashMap::new();
+                let base = match self.baselines.front() {
+                    _ if baseline == 0 => &empty,
+                    Some((seq, base)) if *seq == baseline => base,
+                    _ => return,
+                };
+                let Some(mut humanoids) = apply_humanoid_deltas(base, updated, &removed) else {
+                    log!("WARNING: Received a bad humanoid delta.");
+                    return;
+                };
+
+                // Never simulate a player that isn't safely inside the world.
+                if humanoids
+                    .get(&self.player_id)
+                    .is_some_and(|player| !humanoid_is_valid(player, self.world_w, self.world_h))
+                {
+                    log!("WARNING: Received an invalid player.");
+                    return;
+                }
+                self.baselines.push_back((seq, humanoids.clone()));
+                if self.baselines.len() > MAX_BASELINES {
+                    self.baselines.pop_front();
+                }
+                self.snapshot_ack = seq;
+
+                // Keep the predicted player, corrected if the server disagrees.
+                let player = humanoids
+                    .remove(&self.player_id)
+                    .and_then(|player| self.reconcile_player(ack, player));
+
+                // Buffer for interpolation.
+                self.snapshots.push_back(Snapshot {
+                    ts,
+                    humanoids: humanoids.clone(),
+                });
+                while self
+                    .snapshots
+                    .front()
+                    .is_some_and(|snapshot| snapshot.ts + SNAPSHOT_LIFETIME < ts)
+                {
+                    self.snapshots.pop_front();
+                }
+
+                // Swap.
+                self.humanoids = humanoids;
+
+                // Put player back in.
+                if let Some(player) = player {
+                    self.humanoids.insert(self.player_id, player);
+                }
+            }
+
+            ServerNetMessage::Ping { seq } => {
+                let msg = ClientNetMessage::Pong {
+                    seq,
+                    ts: timestamp_as_usecs(),
+                };
+                self.send_queue.push(Priority::High, Delivery::Uu, msg);
+            }
+
+            ServerNetMessage::Pong { seq, ts, tick } => {
+                self.ping.pong(timestamp_as_usecs(), seq, ts);
+                self.tick_sync = Some((tick, ts));
+            }
+
+            ServerNetMessage::Roster { players } => {
+                for player in &players {
+                    if !self.roster.contains(player) {
+                        log!("{} is online.", player.name);
+                    }
+                }
+                for player in &self.roster {
+                    if !players.contains(player) {
+                        log!("{} went offline.", player.name);
                     }
                 }
-                _ => panic!("Uncaught net event: {e:?}."),
+                self.roster = players;
+            }
+
+            ServerNetMessage::PlayerLeft { player } => {
+                log!("{} left the game.", player.name);
+            }
+
+            ServerNetMessage::Kick { reason } => {
+                self.leave = Some(LeaveReason::Kicked(reason));
             }
+
+            ServerNetMessage::Chat { from, text } => match from {
+                Some(name) => log!("<{name}> {text}"),
+                None => log!("{text}"),
+            },
+
+            ServerNetMessage::Time { time } => self.time = time,
+
+            _ => log!("WARNING: Unexpected message: {msg:?}."),
         }
     }
 
@@ -380,21 +801,25 @@ impl GameUpdateState {
                         PressState::Down => 1,
                         PressState::DownRepeat => 1,
                     };
+
+                    // Typing takes every key press, releases still stop movement.
+                    if bit == 1 && self.type_chat(keycode) {
+                        continue;
+                    }
+
                     match keycode {
                         'd' | 'D' => self.right_queue = self.right_queue & !1 | bit,
                         'a' | 'A' => self.left_queue = self.left_queue & !1 | bit,
                         ' ' => self.jump_queue = self.jump_queue & !1 | bit,
-                        '1' if bit == 0 => {
-                            let index = self.mouse_x / 16 + self.mouse_y / 16 * self.world_w;
-                            self.fg_tiles[index] = Tile::RedTorch;
+                        '1'..='9' | '0' if bit == 1 => {
+                            // 1 is the first slot, 0 the last.
+                            let digit = keycode.to_digit(10).unwrap() as usize;
+                            self.hotbar_slot = (digit + HOTBAR_SIZE - 1) % HOTBAR_SIZE;
                         }
-                        '2' if bit == 0 => {
-                            let index = self.mouse_x / 16 + self.mouse_y / 16 * self.world_w;
-                            self.fg_tiles[index] = Tile::GreenTorch;
-                        }
-                        '3' if bit == 0 => {
-                            let index = self.mouse_x / 16 + self.mouse_y / 16 * self.world_w;
-                            self.fg_tiles[index] = Tile::BlueTorch;
+                        'f' | 'F' if bit == 0 => self.use_item(TileLayer::Fg),
+                        'e' | 'E' if bit == 0 => {
+                            self.inventory_open = !self.inventory_open;
+                            self.held_slot = None;
                         }
                         _ => {}
                     };
@@ -412,14 +837,17 @@ impl GameUpdateState {
                     mouse_button,
                     press_state,
                 } => match (mouse_button, press_state) {
-                    (MouseButton::Left | MouseButton::Right, PressState::Down) => {
-                        let index = self.mouse_x / 16 + self.mouse_y / 16 * self.world_w;
-                        match mouse_button {
-                            MouseButton::Left => self.fg_tiles[index] = Tile::None,
-                            MouseButton::Right => self.bg_tiles[index] = Tile::None,
-                            _ => unreachable!(),
+                    // Left clicks on the inventory go to it instead of the world.
+                    (MouseButton::Left, PressState::Down) => {
+                        match self.visible_slot_at(self.mouse_x_rel, self.mouse_y_rel) {
+                            Some(slot) => self.click_slot(slot),
+                            None => self.mine_layer = Some(TileLayer::Fg),
                         }
                     }
+                    (MouseButton::Right, PressState::Down) => self.mine_layer = Some(TileLayer::Bg),
+                    (MouseButton::Left, PressState::Up) | (MouseButton::Right, PressState::Up) => {
+                        self.mine_layer = None
+                    }
                     _ => {}
                 },
             }
@@ -427,31 +855,276 @@ impl GameUpdateState {
 
         false
     }
-}
 
-fn request_chunks_from_server(game: &mut GameUpdateState) {
-    const TILE_CHUNK_SIZE: usize = TILE_SIZE * CHUNK_SIZE;
-    let cx = game.viewport_x + game.viewport_w / 2;
-    let cy = game.viewport_y + game.viewport_h / 2;
-    let x1 = (cx.saturating_sub(game.viewport_w / 2)) / TILE_CHUNK_SIZE;
-    let x2 = (cx + game.viewport_w / 2 + TILE_CHUNK_SIZE - 1) / TILE_CHUNK_SIZE;
-    let y1 = (cy.saturating_sub(game.viewport_h / 2)) / TILE_CHUNK_SIZE;
-    let y2 = (cy + game.viewport_h / 2 + TILE_CHUNK_SIZE - 1) / TILE_CHUNK_SIZE;
-
-    let mut msgs = vec![];
-    for y in y1..y2 {
-        for x in x1..x2 {
-            msgs.push(ClientNetMessage::RequestChunk {
+    // Checks the server's player (after input frame ack) against what was predicted for that
+    // frame. On a mismatch, rewinds to the server's state and replays the unacked inputs.
+    fn reconcile_player(&mut self, ack: u32, player: Humanoid) -> Option<Humanoid> {
+        // Forget everything the server has already simulated.
+        while self
+            .pending_inputs
+            .front()
+            .is_some_and(|(seq, _)| *seq <= ack)
+        {
+            self.pending_inputs.pop_front();
+        }
+        while self.predictions.front().is_some_and(|(seq, _)| *seq < ack) {
+            self.predictions.pop_front();
+        }
+
+        // Prediction was correct.
+        let predicted = self.predictions.pop_front();
+        if let Some((seq, predicted)) = predicted {
+            if seq == ack && prediction_matches(&predicted, &player) {
+                return self.humanoids.get(&self.player_id).cloned();
+            }
+        }
+
+        // Replay.
+        let ft = FRAMETIME as f32 / 1e6;
+        let mut player = player;
+        let mut predictions = VecDeque::new();
+        for (seq, input) in &self.pending_inputs {
+            player = self.step_humanoid(player, input.clone(), ft);
+            predictions.push_back((*seq, player.clone()));
+        }
+        self.predictions = predictions;
+        Some(player)
+    }
+
+    // Runs a single input frame for one humanoid, exactly like the server does.
+    fn step_humanoid(&self, humanoid: Humanoid, input: HumanoidInput, ft: f32) -> Humanoid {
+        let id = HumanoidId::new();
+        let mut humanoids = HashMap::from([(id, humanoid)]);
+        humanoids.get_mut(&id).unwrap().input = input;
+        update_humanoid_inputs(&mut humanoids);
+        update_humanoid_physics(&mut humanoids, self.world_w, &self.liquids, ft);
+        resolve_humanoid_tile_collisions(&mut humanoids, self.world_w, &self.fg_tiles);
+        humanoids.remove(&id).unwrap()
+    }
+
+    // Mines the tile under the mouse while a button is held. The server decides when it breaks,
+    // and broadcasts the result back as a TileSync.
+    fn update_mining(&mut self) {
+        let (x, y) = (self.mouse_x / TILE_SIZE, self.mouse_y / TILE_SIZE);
+        let target = self.mine_layer.map(|layer| (x, y, layer));
+        if target == self.mine_target {
+            return;
+        }
+        self.mine_target = target;
+
+        let msg = match target {
+            Some((x, y, layer)) => ClientNetMessage::MineTile {
                 x: x as u16,
                 y: y as u16,
-                seq: game.chunk_seqs[x + y * game.world_w / CHUNK_SIZE],
-            });
+                layer,
+            },
+            None => ClientNetMessage::StopMining,
+        };
+        self.send_queue.push(Priority::Normal, Delivery::Ro, msg);
+    }
+
+    // How the link to the server is doing, as measured by pings.
+    pub fn link_stats(&self) -> LinkStats {
+        self.ping.stats()
+    }
+
+    // The newest server tick any state was received from.
+    pub fn server_tick(&self) -> u32 {
+        self.server_tick
+    }
+
+    // The tick the server is at right now (at local time ts), going by the clock offset. None
+    // until the first Pong.
+    pub fn estimated_server_tick(&self, ts: u64) -> Option<u32> {
+        let (tick, tick_ts) = self.tick_sync?;
+        let server_ts = ts as i64 + self.ping.stats().clock_offset;
+        let elapsed = (server_ts - tick_ts as i64).max(0) as u64;
+        Some(tick + (elapsed / FRAMETIME) as u32)
+    }
+
+    // Enter (or T, or / to start a command) opens the chat line, Enter sends it and Escape drops
+    // it. Returns whether the key was used.
+    fn type_chat(&mut self, keycode: char) -> bool {
+        let Some(input) = &mut self.chat_input else {
+            match keycode {
+                '\r' | 't' | 'T' => self.chat_input = Some(String::new()),
+                '/' => self.chat_input = Some(String::from("/")),
+                _ => return false,
+            }
+            return true;
+        };
+
+        match keycode {
+            '\r' => {
+                let text = std::mem::take(input);
+                self.chat_input = None;
+                self.send_chat(&text);
+            }
+            '\u{1b}' => self.chat_input = None,
+            '\u{8}' => {
+                input.pop();
+            }
+            c if !c.is_control() && input.chars().count() < MAX_CHAT_LEN => input.push(c),
+            _ => {}
+        }
+        true
+    }
+
+    // Says something, or runs a command if it starts with a slash.
+    pub fn send_chat(&mut self, text: &str) {
+        let text = text.trim();
+        if text.is_empty() {
+            return;
+        }
+        let msg = ClientNetMessage::Chat {
+            text: text.chars().take(MAX_CHAT_LEN).collect(),
+        };
+        self.send_queue.push(Priority::Normal, Delivery::Ro, msg);
+    }
+
+    // Uses the selected hotbar item on the tile under the mouse. The inventory and the tile get
+    // updated once the server has done it.
+    fn use_item(&mut self, layer: TileLayer) {
+        if self.inventory.slots[self.hotbar_slot].is_none() {
+            return;
+        }
+        let (x, y) = (self.mouse_x / TILE_SIZE, self.mouse_y / TILE_SIZE);
+        let msg = ClientNetMessage::UseItem {
+            slot: self.hotbar_slot as u8,
+            x: x as u16,
+            y: y as u16,
+            layer,
+        };
+        self.send_queue.push(Priority::Normal, Delivery::Ru, msg);
+    }
+
+    // The slot at (x, y) (in pixels, relative to the view), of the ones showing.
+    fn visible_slot_at(&self, x: usize, y: usize) -> Option<usize> {
+        let slot = inventory_slot_at(x, y)?;
+        let showing = match self.inventory_open {
+            true => INVENTORY_SIZE,
+            false => HOTBAR_SIZE,
+        };
+        (slot < showing).then_some(slot)
+    }
+
+    // With the inventory closed, clicking the hotbar selects a slot. With it open, the first click
+    // picks a stack up and the second puts it down, merging or swapping with what's there.
+    fn click_slot(&mut self, slot: usize) {
+        if !self.inventory_open {
+            self.hotbar_slot = slot;
+            return;
+        }
+        match self.held_slot.take() {
+            Some(from) => {
+                let msg = ClientNetMessage::MoveItem {
+                    from: from as u8,
+                    to: slot as u8,
+                };
+                self.send_queue.push(Priority::Normal, Delivery::Ro, msg);
+            }
+            None if self.inventory.slots[slot].is_some() => self.held_slot = Some(slot),
+            None => {}
+        }
+    }
+}
+
+// Inventory slots are drawn in rows of HOTBAR_SIZE in the top left of the view, hotbar first (in
+// pixels).
+const INVENTORY_MARGIN: usize = 8;
+const INVENTORY_SLOT_SIZE: usize = 16;
+const INVENTORY_SLOT_PITCH: usize = 18;
+
+// Slot frames in the tile sheet, and the crop of an item's texture shown in them (in pixels).
+const SLOT_UV: (f32, f32) = (160., 0.);
+const SELECTED_SLOT_UV: (f32, f32) = (176., 0.);
+const ITEM_ICON_INSET: f32 = 2.;
+
+// Top left corner of a slot, relative to the view.
+fn inventory_slot_position(slot: usize) -> (usize, usize) {
+    (
+        INVENTORY_MARGIN + slot % HOTBAR_SIZE * INVENTORY_SLOT_PITCH,
+        INVENTORY_MARGIN + slot / HOTBAR_SIZE * INVENTORY_SLOT_PITCH,
+    )
+}
+
+// The slot at (x, y) relative to the view, if any.
+fn inventory_slot_at(x: usize, y: usize) -> Option<usize> {
+    let x = x.checked_sub(INVENTORY_MARGIN)?;
+    let y = y.checked_sub(INVENTORY_MARGIN)?;
+    let (column, row) = (x / INVENTORY_SLOT_PITCH, y / INVENTORY_SLOT_PITCH);
+    if column >= HOTBAR_SIZE
+        || x % INVENTORY_SLOT_PITCH >= INVENTORY_SLOT_SIZE
+        || y % INVENTORY_SLOT_PITCH >= INVENTORY_SLOT_SIZE
+    {
+        return None;
+    }
+    let slot = column + row * HOTBAR_SIZE;
+    (slot < INVENTORY_SIZE).then_some(slot)
+}
+
+// Copies the chunks in a ChunkSync into the world, skipping any that aren't newer.
+fn apply_chunk_sync(
+    chunks: &ChunkBatch,
+    world_w: usize,
+    chunk_seqs: &mut [u32],
+    fg_tiles: &mut [Tile],
+    bg_tiles: &mut [Tile],
+    liquids: &mut [Liquid],
+) {
+    let Some(chunks) = unpack_chunks(chunks) else {
+        log!("WARNING: Received a bad chunk batch.");
+        return;
+    };
+
+    let chunks_w = world_w / CHUNK_SIZE;
+    for ChunkData {
+        x,
+        y,
+        seq,
+        runs,
+        liquids: liquid_runs,
+    } in chunks
+    {
+        let (cx, cy) = (x as usize, y as usize);
+        if cx >= chunks_w {
+            continue;
+        }
+        let Some(cur_seq) = chunk_seqs.get_mut(cx + cy * chunks_w) else {
+            continue;
+        };
+        if *cur_seq >= seq {
+            continue;
+        }
+        let Some((chunk_fg_tiles, chunk_bg_tiles)) = decode_chunk_tiles(&runs) else {
+            log!("WARNING: Received bad tiles for chunk ({cx}, {cy}).");
+            continue;
+        };
+        let Some(chunk_liquids) = decode_chunk_liquids(&liquid_runs) else {
+            log!("WARNING: Received bad liquids for chunk ({cx}, {cy}).");
+            continue;
+        };
+
+        *cur_seq = seq;
+        for y in 0..CHUNK_SIZE {
+            for x in 0..CHUNK_SIZE {
+                let src_index = x + y * CHUNK_SIZE;
+                let dst_index = x + cx * CHUNK_SIZE + (y + cy * CHUNK_SIZE) * world_w;
+                fg_tiles[dst_index] = chunk_fg_tiles[src_index];
+                bg_tiles[dst_index] = chunk_bg_tiles[src_index];
+                liquids[dst_index] = chunk_liquids[src_index];
+            }
         }
     }
+}
 
-    let _bytes = serialize(&msgs);
-    //log!("{}", bytes.len());
-    game.net_manager.send_uu(serialize(&msgs));
+fn prediction_matches(a: &Humanoid, b: &Humanoid) -> bool {
+    let close = |a: f32, b: f32| (a - b).abs() <= PREDICTION_EPSILON;
+    close(a.base.x, b.base.x)
+        && close(a.base.y, b.base.y)
+        && close(a.physics.dx, b.physics.dx)
+        && close(a.physics.dy, b.physics.dy)
+        && a.base.flags == b.base.flags
 }
 
 fn calculate_light_map(
@@ -478,8 +1151,8 @@ fn calculate_light_map(
     // Calculate visible region.
     let x1 = (game.viewport_x / 16).saturating_sub(LIGHT_MAX as usize);
     let y1 = (game.viewport_y / 16).saturating_sub(LIGHT_MAX as usize);
-    let x2 = (game.viewport_x + game.viewport_w + 15) / 16 + LIGHT_MAX as usize;
-    let y2 = (game.viewport_y + game.viewport_h + 15) / 16 + LIGHT_MAX as usize;
+    let x2 = ((game.viewport_x + game.viewport_w + 15) / 16 + LIGHT_MAX as usize).min(game.world_w);
+    let y2 = ((game.viewport_y + game.viewport_h + 15) / 16 + LIGHT_MAX as usize).min(game.world_h);
     let (w, h) = (x2 - x1, y2 - y1);
 
     let mut r_channel = create_light_map_base(w, h);
@@ -497,6 +1170,27 @@ fn calculate_light_map(
 
             let fg_tile = game.fg_tiles[world_index];
             let bg_tile = game.bg_tiles[world_index];
+            let liquid = game.liquids[world_index];
+
+            // Liquid, which may glow.
+            if fg_tile == Tile::None && !liquid.is_empty() {
+                fade_map[light_index] = liquid.property().fade;
+
+                let (r, g, b) = liquid.light();
+                if r > 0 {
+                    r_channel[light_index] = r;
+                    r_probes.push(light_index as u16);
+                }
+                if g > 0 {
+                    g_channel[light_index] = g;
+                    g_probes.push(light_index as u16);
+                }
+                if b > 0 {
+                    b_channel[light_index] = b;
+                    b_probes.push(light_index as u16);
+                }
+                continue;
+            }
 
             // Special case (None, None).
             if fg_tile == Tile::None && bg_tile == Tile::None {
@@ -517,7 +1211,7 @@ fn calculate_light_map(
 
             // Case (Some, _).
             if fg_tile != Tile::None {
-                let fg_light_property = tile_light_property_map[fg_tile as usize];
+                let fg_light_property = tile_light_property_map[fg_tile.0 as usize];
 
                 //
                 fade_map[light_index] = fg_light_property.fade;
@@ -560,27 +1254,44 @@ fn clone_visible_tile_map(
 ) {
     let x1 = (game.viewport_x - 4) / 16 - 1;
     let y1 = (game.viewport_y - 4) / 16 - 1;
-    let x2 = (game.viewport_x + game.viewport_w + 4 + 15) / 16 + 1;
-    let y2 = (game.viewport_y + game.viewport_h + 4 + 15) / 16 + 1;
-    let mut fg_tiles = vec![TileRenderDesc(Tile::None); (x2 - x1) * (y2 - y1)].into_boxed_slice();
-    let mut bg_tiles = vec![TileRenderDesc(Tile::None); (x2 - x1) * (y2 - y1)].into_boxed_slice();
+    let x2 = ((game.viewport_x + game.viewport_w + 4 + 15) / 16 + 1).min(game.world_w);
+    let y2 = ((game.viewport_y + game.viewport_h + 4 + 15) / 16 + 1).min(game.world_h);
+    let none = TileRenderDesc {
+        tile: Tile::None,
+        crack: 0,
+    };
+    let mut fg_tiles = vec![none; (x2 - x1) * (y2 - y1)].into_boxed_slice();
+    let mut bg_tiles = vec![none; (x2 - x1) * (y2 - y1)].into_boxed_slice();
     let w = x2 - x1;
     let h = y2 - y1;
     for y in 0..h {
         for x in 0..w {
             let src_index = (x + x1) + (y + y1) * game.world_w;
             let dst_index = x + y * w;
-            fg_tiles[dst_index] = TileRenderDesc(game.fg_tiles[src_index]);
-            bg_tiles[dst_index] = TileRenderDesc(game.bg_tiles[src_index]);
+            let crack = |layer| game.cracks.get(&(src_index, layer)).copied().unwrap_or(0);
+            fg_tiles[dst_index] = TileRenderDesc {
+                tile: game.fg_tiles[src_index],
+                crack: crack(TileLayer::Fg),
+            };
+            bg_tiles[dst_index] = TileRenderDesc {
+                tile: game.bg_tiles[src_index],
+                crack: crack(TileLayer::Bg),
+            };
         }
     }
     (x1, y1, x2 - x1, y2 - y1, fg_tiles, bg_tiles)
 }
 
-fn clone_visible_sprites(game: &mut GameUpdateState) -> Box<[SpriteRenderDesc]> {
-    game.humanoids
-        .values()
-        .map(|humanoid| &humanoid.base)
+fn clone_visible_sprites(game: &mut GameUpdateState, ts: u64) -> Box<[SpriteRenderDesc]> {
+    // The player is drawn where it was predicted, everyone else a little in the past.
+    let player = game
+        .humanoids
+        .get(&game.player_id)
+        .map(|player| player.base.clone());
+    let remote = interpolate_humanoids(&game.snapshots, ts.saturating_sub(INTERPOLATION_DELAY));
+    let humanoids = player
+        .into_iter()
+        .chain(remote.into_values())
         .map(|base| SpriteRenderDesc {
             x: base.x.floor(),
             y: base.y.floor(),
@@ -588,6 +1299,415 @@ fn clone_visible_sprites(game: &mut GameUpdateState) -> Box<[SpriteRenderDesc]>
             h: base.h,
             u: 0.,
             v: 0.,
-        })
-        .collect()
+        });
+
+    // Drops show the middle of their item's texture.
+    let offset = (TILE_SIZE as f32 - ITEM_DROP_SIZE) / 2.;
+    let drops = game.item_drops.values().map(|drop| {
+        let (u, v) = item_uv(drop.item);
+        SpriteRenderDesc {
+            x: drop.x.floor(),
+            y: drop.y.floor(),
+            w: ITEM_DROP_SIZE,
+            h: ITEM_DROP_SIZE,
+            u: u + offset,
+            v: v + offset,
+        }
+    });
+
+    // The hotbar (or the whole inventory) goes on top of everything, following the view.
+    let showing = match game.inventory_open {
+        true => INVENTORY_SIZE,
+        false => HOTBAR_SIZE,
+    };
+    let selected = game.held_slot.unwrap_or(game.hotbar_slot);
+    let mut inventory = Vec::with_capacity(2 * showing);
+    for (slot, stack) in game.inventory.slots.iter().enumerate().take(showing) {
+        let (x, y) = inventory_slot_position(slot);
+        let (x, y) = ((game.viewport_x + x) as f32, (game.viewport_y + y) as f32);
+        let (u, v) = match slot == selected {
+            true => SELECTED_SLOT_UV,
+            false => SLOT_UV,
+        };
+        let size = INVENTORY_SLOT_SIZE as f32;
+        inventory.push(SpriteRenderDesc {
+            x,
+            y,
+            w: size,
+            h: size,
+            u,
+            v,
+        });
+        if let Some(stack) = stack {
+            let (u, v) = item_uv(stack.item);
+            inventory.push(SpriteRenderDesc {
+                x: x + ITEM_ICON_INSET,
+                y: y + ITEM_ICON_INSET,
+                w: size - 2. * ITEM_ICON_INSET,
+                h: size - 2. * ITEM_ICON_INSET,
+                u: u + ITEM_ICON_INSET,
+                v: v + ITEM_ICON_INSET,
+            });
+        }
+    }
+
+    humanoids.chain(drops).chain(inventory).collect()
+}
+
+// Top left corner of an item's texture in the tile sheet.
+fn item_uv(item: Item) -> (f32, f32) {
+    match item {
+        Item::Tile(tile) => {
+            let texture = TILE_TEXTURE_PROPERTIES[tile.0 as usize];
+            (texture.u, texture.v)
+        }
+    }
+}
+
+// Positions of the snapshot humanoids at render_ts. Interpolates between the snapshots around
+// render_ts, or extrapolates (for a limited time) from the newest one if it is older.
+fn interpolate_humanoids(
+    snapshots: &VecDeque<Snapshot>,
+    render_ts: u64,
+) -> HashMap<HumanoidId, HumanoidBase> {
+    let next = snapshots
+        .iter()
+        .position(|snapshot| snapshot.ts >= render_ts);
+    match next {
+        // Nothing received yet.
+        None if snapshots.is_empty() => HashMap::new(),
+
+        // Ran out of snapshots.
+        None => {
+            let last = snapshots.back().unwrap();
+            let dt = (render_ts - last.ts).min(MAX_EXTRAPOLATION) as f32 / 1e6;
+            last.humanoids
+                .iter()
+                .map(|(id, humanoid)| {
+                    let mut base = humanoid.base.clone();
+                    base.x += humanoid.physics.dx * dt;
+                    base.y += humanoid.physics.dy * dt;
+                    (*id, base)
+                })
+                .collect()
+        }
+
+        // Older than everything buffered.
+        Some(0) => snapshots[0]
+            .humanoids
+            .iter()
+            .map(|(id, humanoid)| (*id, humanoid.base.clone()))
+            .collect(),
+
+        // Between two snapshots. Humanoids that just appeared aren't interpolated.
+        Some(i) => {
+            let (a, b) = (&snapshots[i - 1], &snapshots[i]);
+            let t = (render_ts - a.ts) as f32 / (b.ts - a.ts) as f32;
+            b.humanoids
+                .iter()
+                .map(|(id, humanoid)| {
+                    let mut base = humanoid.base.clone();
+                    if let Some(prev) = a.humanoids.get(id) {
+                        base.x = prev.base.x + (base.x - prev.base.x) * t;
+                        base.y = prev.base.y + (base.y - prev.base.y) * t;
+                    }
+                    (*id, base)
+                })
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::client::connect;
+    use crate::net::{
+        LinkConditioner, LinkConditions, LoopbackNetwork, ServerNetManager, Transport,
+    };
+    use crate::server::{Server, WorldConfig};
+
+    fn snapshot(ts: u64, x: f32, dx: f32) -> Snapshot {
+        let humanoid = Humanoid {
+            base: HumanoidBase {
+                x,
+                y: 0.,
+                w: 24.,
+                h: 40.,
+                flags: 0,
+            },
+            ai: HumanoidAi::Zombie,
+            input: HumanoidInput::default(),
+            physics: HumanoidPhysics {
+                dx,
+                ..Default::default()
+            },
+        };
+        Snapshot {
+            ts,
+            humanoids: HashMap::from([(HumanoidId::new(), humanoid)]),
+        }
+    }
+
+    #[test]
+    fn interpolates_and_extrapolates() {
+        let x_at = |snapshots: &VecDeque<Snapshot>, ts| {
+            interpolate_humanoids(snapshots, ts)[&HumanoidId::new()].x
+        };
+        let snapshots = VecDeque::from([
+            snapshot(1_000_000, 0., 100.),
+            snapshot(1_100_000, 10., 100.),
+        ]);
+
+        // Before, between and on snapshots.
+        assert_eq!(x_at(&snapshots, 0), 0.);
+        assert_eq!(x_at(&snapshots, 1_050_000), 5.);
+        assert_eq!(x_at(&snapshots, 1_100_000), 10.);
+
+        // Past the newest, up to the limit.
+        assert_eq!(x_at(&snapshots, 1_200_000), 20.);
+        assert_eq!(
+            x_at(&snapshots, 5_000_000),
+            10. + 100. * MAX_EXTRAPOLATION as f32 / 1e6
+        );
+
+        assert!(interpolate_humanoids(&VecDeque::new(), 0).is_empty());
+    }
+
+    fn join_local_server(network: &LoopbackNetwork, conditions: LinkConditions) -> GameUpdateState {
+        join_local_world(network, conditions, 256, 128)
+    }
+
+    fn join_local_world(
+        network: &LoopbackNetwork,
+        conditions: LinkConditions,
+        width: usize,
+        height: usize,
+    ) -> GameUpdateState {
+        let server_transport = network.bind(0);
+        let server_addr = server_transport.local_addr();
+        let world_config = WorldConfig {
+            path: None,
+            seed: 1,
+            width,
+            height,
+            autosave_interval: 0,
+            operators: Vec::new(),
+        };
+        let root = Path::new(".");
+        let server = Server::new(
+            root,
+            ServerNetManager::with_transport(Box::new(server_transport)),
+            world_config,
+        );
+        std::thread::spawn(|| server.run());
+
+        let transport = Box::new(LinkConditioner::new(
+            Box::new(network.bind(0)),
+            conditions,
+            conditions,
+            1,
+        ));
+        let mut net_manager = ClientNetManager::with_transport(server_addr, transport);
+        connect(&mut net_manager).unwrap();
+        GameUpdateState::new(root, net_manager, "Player", PlayerToken(1)).unwrap()
+    }
+
+    fn tick(game: &mut GameUpdateState) {
+        let ts = timestamp_as_usecs();
+        game.prestep(ts, std::iter::empty());
+        game.step(ts, FRAMETIME);
+        game.poststep(ts);
+    }
+
+    #[test]
+    fn joins_local_server() {
+        // Server and client in one process, with a bit of latency, loss and reordering.
+        let network = LoopbackNetwork::new();
+        let conditions = LinkConditions {
+            latency: 5_000,
+            jitter: 5_000,
+            loss: 0.05,
+            duplicate: 0.05,
+            reorder: 0.05,
+        };
+        let mut game = join_local_server(&network, conditions);
+        assert_eq!(game.world_w, 256);
+        assert!(game.fg_tiles.iter().any(|&tile| tile != Tile::None));
+
+        // The server starts syncing the player once it has joined, and lists it as online.
+        let start_ts = timestamp_as_usecs();
+        while !game.humanoids.contains_key(&game.player_id) || game.roster.is_empty() {
+            assert!(timestamp_as_usecs() - start_ts < CONNECT_TIMEOUT);
+            tick(&mut game);
+            std::thread::sleep(std::time::Duration::from_micros(FRAMETIME));
+        }
+        assert_eq!(game.roster[0].name, "Player");
+
+        // The link gets measured. Both ends share a clock here, so the offset is about 0.
+        while game.link_stats().pongs_received == 0 {
+            assert!(timestamp_as_usecs() - start_ts < CONNECT_TIMEOUT);
+            tick(&mut game);
+            std::thread::sleep(std::time::Duration::from_micros(FRAMETIME));
+        }
+        let stats = game.link_stats();
+        assert!(stats.rtt >= 2 * conditions.latency);
+        assert!(stats.clock_offset.unsigned_abs() <= stats.rtt);
+        assert!(game.server_tick() > 0);
+        let estimate = game.estimated_server_tick(timestamp_as_usecs()).unwrap();
+        assert!(estimate + 2 >= game.server_tick());
+    }
+
+    #[test]
+    fn joins_small_worlds() {
+        // Smaller than the view (and its light margin), so every frame reaches the world's edges.
+        let network = LoopbackNetwork::new();
+        let mut game = join_local_world(
+            &network,
+            LinkConditions::default(),
+            MIN_WORLD_SIZE,
+            MIN_WORLD_SIZE,
+        );
+        let start_ts = timestamp_as_usecs();
+        while !game.humanoids.contains_key(&game.player_id) {
+            assert!(timestamp_as_usecs() - start_ts < CONNECT_TIMEOUT);
+            tick(&mut game);
+            std::thread::sleep(std::time::Duration::from_micros(FRAMETIME));
+        }
+
+        // Close to the bottom right corner too.
+        let player = game.humanoids.get_mut(&game.player_id).unwrap();
+        player.base.x = ((MIN_WORLD_SIZE - 2) * TILE_SIZE) as f32 - player.base.w;
+        player.base.y = ((MIN_WORLD_SIZE - 2) * TILE_SIZE) as f32 - player.base.h;
+        game.step(timestamp_as_usecs(), FRAMETIME);
+        let desc = game.poststep(timestamp_as_usecs());
+        assert!(desc.tiles_x + desc.tiles_w <= MIN_WORLD_SIZE);
+        assert!(desc.light_y + desc.light_h <= MIN_WORLD_SIZE);
+    }
+
+    #[test]
+    fn survives_garbage() {
+        let network = LoopbackNetwork::new();
+        let mut game = join_local_server(&network, LinkConditions::default());
+        let client_addr = game.net_manager.local_addr();
+        let server_addr = game.net_manager.server_addr();
+        let mut attacker = network.bind(0);
+
+        // Well-formed, but impossible.
+        let bad_player = Humanoid {
+            base: HumanoidBase {
+                x: f32::NAN,
+                y: 1e30,
+                w: 24.,
+                h: 40.,
+                flags: 0,
+            },
+            ai: HumanoidAi::Player,
+            input: HumanoidInput::default(),
+            physics: HumanoidPhysics::default(),
+        };
+        let (updated, removed) = diff_humanoids(
+            &HashMap::new(),
+            &HashMap::from([(game.player_id, bad_player)]),
+        );
+        let far_chunk = ChunkData {
+            x: 1000,
+            y: 0,
+            seq: 1,
+            runs: vec![(Tile::Stone, 128)],
+            liquids: vec![(Liquid::NONE, 64)],
+        };
+        let valid = [
+            serialize(&[ServerNetMessage::HumanoidSync {
+                tick: 1,
+                seq: u32::MAX,
+                baseline: 0,
+                ack: 0,
+                updated,
+                removed,
+            }]),
+            serialize(&[ServerNetMessage::TileSync {
+                tick: 1,
+                x: u16::MAX,
+                y: 3,
+                seq: 1,
+                fg_tile: Tile::Dirt,
+                bg_tile: Tile::Dirt,
+                liquid: Liquid::NONE,
+            }]),
+            serialize(
+                &pack_chunks(&[far_chunk], MAX_CHUNK_BATCH_SIZE)
+                    .into_iter()
+                    .map(|chunks| ServerNetMessage::ChunkSync { tick: 1, chunks })
+                    .collect::<Vec<_>>(),
+            ),
+            serialize(&[
+                ServerNetMessage::JoinAccept {
+                    tiles: TILE_REGISTRY.hash(),
+                    width: 0,
+                    height: 0,
+                    id: HumanoidId::new(),
+                    spawn_x: 0,
+                    spawn_y: 0,
+                },
+                ServerNetMessage::Start,
+            ]),
+        ];
+
+        // Anyone but the server is ignored, even when what they send makes sense.
+        let fg_tiles = game.fg_tiles.clone();
+        let kick = serialize(&[ServerNetMessage::Kick {
+            reason: String::from("spoofed"),
+        }]);
+        let tile = serialize(&[ServerNetMessage::TileSync {
+            tick: 1,
+            x: 10,
+            y: 10,
+            seq: game.chunk_seqs[1 + game.world_w / CHUNK_SIZE] + 1,
+            fg_tile: Tile::Sand,
+            bg_tile: Tile::Sand,
+            liquid: Liquid::NONE,
+        }]);
+        for bytes in valid.iter().chain([&tile, &kick]) {
+            attacker.send(client_addr, Delivery::Ru, bytes.clone());
+        }
+        tick(&mut game);
+        assert!(game.leave.is_none());
+        assert!(game.fg_tiles == fg_tiles);
+
+        // What comes from the server is checked too. Random bytes, and corrupted versions of the
+        // above.
+        let from_server = |game: &mut GameUpdateState, bytes: Box<[u8]>| {
+            let event = NetEvent {
+                source: server_addr,
+                kind: NetEventKind::Data {
+                    bytes,
+                    delivery: Delivery::Ru,
+                },
+            };
+            game.handle_net_event(timestamp_as_usecs(), event);
+        };
+        for bytes in &valid {
+            from_server(&mut game, bytes.clone());
+        }
+        tick(&mut game);
+        let mut rng = Rng::new(7);
+        for i in 0..2000 {
+            let bytes: Box<[u8]> = if i % 2 == 0 {
+                let len = rng.range(0, 64) as usize;
+                (0..len).map(|_| rng.next_u32() as u8).collect()
+            } else {
+                let mut bytes = valid[i % valid.len()].clone();
+                for _ in 0..rng.range(1, 4) {
+                    let j = rng.range(1, bytes.len() as u64) as usize;
+                    bytes[j] = rng.next_u32() as u8;
+                }
+                bytes
+            };
+            from_server(&mut game, bytes);
+            if i % 10 == 0 {
+                tick(&mut game);
+            }
+        }
+    }
 }
@@ -1,8 +1,24 @@
-use crate::client::Client;
-use crate::server::Server;
+use crate::client::{load_or_create_token, Client, LeaveReason};
+use crate::net::{
+    ClientNetManager, DiscoveryResponder, LaminarTransport, LinkConditioner, LinkConditions,
+    LoopbackNetwork, ServerNetManager, Transport, DISCOVERY_PORT,
+};
+use crate::server::{Server, WorldConfig};
 use crate::{EventLoop, Window};
+use std::net::SocketAddr;
 use std::path::Path;
 
+pub enum ServerMode {
+    // Run a server in this process and connect to it.
+    Local,
+
+    // Same, but open to others on a UDP port, and advertised on the LAN.
+    Host,
+
+    // Connect to an already running server.
+    Remote(SocketAddr),
+}
+
 pub struct ClientApp<'a> {
     // Misc.
     root: &'static Path,
@@ -12,11 +28,19 @@ pub struct ClientApp<'a> {
 
     // Client/server.
     client: Client<'a>,
-    server: Server,
+    server: Option<Server>,
 }
 
 impl<'a> ClientApp<'a> {
-    pub fn launch(root: &'static Path) -> ! {
+    // Conditions are simulated on the client's link, up is towards the server, down from it.
+    // Returns once the client left the game, after a local server has saved its world.
+    pub fn launch(
+        root: &'static Path,
+        mode: ServerMode,
+        name: String,
+        up: LinkConditions,
+        down: LinkConditions,
+    ) -> LeaveReason {
         // Window.
         let event_loop = winit::event_loop::EventLoop::new().unwrap();
         let window = winit::window::WindowBuilder::new()
@@ -31,17 +55,89 @@ impl<'a> ClientApp<'a> {
 
         let (input_send, input_recv) = crossbeam_channel::bounded(100);
 
-        // Initialize server.
-        let (server, port) = Server::new(root, 0);
+        // Who we are to servers.
+        let token = load_or_create_token(&root.join("player.token"));
+
+        // Initialize server, if hosting locally. The host may run commands.
+        let world_config = || WorldConfig {
+            // Not world.dpwd, which a dedicated server next to the same executable would use.
+            path: Some(root.join("local.dpwd")),
+            // Only used if there is no saved world yet.
+            seed: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_nanos() as u64,
+            width: 8400,
+            height: 2400,
+            autosave_interval: 5 * 60 * 1_000_000,
+            operators: vec![token],
+        };
+        let (server, server_addr, transport): (_, _, Box<dyn Transport>) = match mode {
+            ServerMode::Local => {
+                // Talk in-process, no sockets needed.
+                let network = LoopbackNetwork::new();
+                let server_transport = network.bind(0);
+                let server_addr = server_transport.local_addr();
+                let net_manager = ServerNetManager::with_transport(Box::new(server_transport));
+                let server = Server::new(root, net_manager, world_config());
+                (Some(server), server_addr, Box::new(network.bind(0)))
+            }
+            ServerMode::Host => {
+                let (net_manager, port) = ServerNetManager::new(0);
+                let mut server = Server::new(root, net_manager, world_config());
+                let server_name = format!("{name}'s world");
+                match DiscoveryResponder::bind(("0.0.0.0", DISCOVERY_PORT), &server_name, port) {
+                    Ok(discovery) => server = server.with_discovery(discovery),
+                    Err(err) => println!("Not advertising on the LAN: {err}."),
+                }
+                println!("Hosting on port {port}.");
+                let server_addr = SocketAddr::from(([127, 0, 0, 1], port));
+                (
+                    Some(server),
+                    server_addr,
+                    Box::new(LaminarTransport::bind_any()),
+                )
+            }
+            ServerMode::Remote(server_addr) => {
+                (None, server_addr, Box::new(LaminarTransport::bind_any()))
+            }
+        };
+
+        // Simulate a bad link, if asked to.
+        let transport = if (up, down) != Default::default() {
+            Box::new(LinkConditioner::new(
+                transport,
+                up,
+                down,
+                crate::timestamp_as_usecs(),
+            ))
+        } else {
+            transport
+        };
+        let net_manager = ClientNetManager::with_transport(server_addr, transport);
 
         // Initialize client.
-        let client = Client::new(root, &window, port);
+        let client = Client::new(root, &window, net_manager, name, token);
 
-        // Start.
+        // Start. The window stays up until the client is done, then the server is stopped.
+        let (shutdown_send, shutdown_recv) = crossbeam_channel::bounded(1);
         std::thread::scope(|s| {
-            let _client_thread = s.spawn(|| client.run(input_recv));
-            let _server_thread = s.spawn(|| server.run());
-            event_loop.run(|event| input_send.send(event).unwrap());
+            let client_thread = s.spawn(|| client.run(input_recv));
+            let server_thread =
+                server.map(|server| s.spawn(|| server.with_shutdown(shutdown_recv).run()));
+            event_loop.run(
+                |event| {
+                    // Nobody listens anymore once the client is done.
+                    let _ = input_send.send(event);
+                },
+                || client_thread.is_finished(),
+            );
+            let reason = client_thread.join().unwrap();
+            if let Some(server_thread) = server_thread {
+                shutdown_send.send(()).unwrap();
+                server_thread.join().unwrap();
+            }
+            reason
         })
     }
 }
@@ -0,0 +1,28 @@
+#[cfg(feature = "client")]
+pub mod client;
+#[cfg(feature = "client")]
+pub mod client_app;
+pub mod gen_map;
+pub mod net;
+pub mod server;
+pub mod shared;
+pub mod time;
+#[cfg(feature = "client")]
+pub mod window;
+
+pub use gen_map::*;
+pub use shared::*;
+pub use time::{timestamp_as_msecs, timestamp_as_secs, timestamp_as_usecs};
+#[cfg(feature = "client")]
+pub use window::*;
+
+use std::path::PathBuf;
+
+// Directory the executable lives in. Resources and worlds are relative to it.
+pub fn exe_dir() -> PathBuf {
+    std::env::current_exe()
+        .expect("Could not get CWD.")
+        .parent()
+        .unwrap()
+        .to_owned()
+}
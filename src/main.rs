@@ -1,34 +1,109 @@
-mod client;
-mod client_app;
-mod gen_map;
-mod net;
-mod server;
-mod shared;
-mod time;
-mod window;
-
-pub use gen_map::*;
-pub use shared::*;
-pub use time::{timestamp_as_msecs, timestamp_as_secs, timestamp_as_usecs};
-pub use window::*;
-
-use crate::client_app::ClientApp;
+use duplicarria2::client::LeaveReason;
+use duplicarria2::client_app::{ClientApp, ServerMode};
+use duplicarria2::net::{discover_servers, LinkConditions};
+use duplicarria2::shared::player_name_is_valid;
 use once_cell::sync::Lazy;
+use std::net::ToSocketAddrs;
 use std::path::PathBuf;
 
+const USAGE: &str = "\
+Usage: duplicarria2 [options]
+  --connect <host:port>  Join a running server instead of hosting one locally
+  --host                 Host locally, open to others and advertised on the LAN
+  --find-servers         List the servers on the LAN and exit
+  --name <name>          Player name, up to 16 letters, digits, spaces, '-' or '_' (default Player)
+  --sim-up <conditions>  Simulate a bad link towards the server, e.g. latency=80,jitter=20,loss=0.05
+  --sim-down <conditions>
+                         Same, from the server (keys: latency, jitter (ms), loss, duplicate, reorder)
+  --sim <conditions>     Same, both ways";
+
 fn main() {
     // Initialize game start timestamp.
-    lazy_static::initialize(&time::TIMESTAMP);
+    lazy_static::initialize(&duplicarria2::time::TIMESTAMP);
 
     // Get working directory.
-    static PATH: Lazy<PathBuf> = Lazy::new(|| {
-        std::env::current_exe()
-            .expect("Could not get CWD.")
-            .parent()
-            .unwrap()
-            .to_owned()
-    });
+    static PATH: Lazy<PathBuf> = Lazy::new(duplicarria2::exe_dir);
+
+    // Parse arguments.
+    let mut mode = ServerMode::Local;
+    let mut name = String::from("Player");
+    let mut up = LinkConditions::default();
+    let mut down = LinkConditions::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--connect" => {
+                let host = args.next().unwrap_or_else(|| exit_usage(&arg));
+                let addr = host
+                    .to_socket_addrs()
+                    .ok()
+                    .and_then(|mut addrs| addrs.next())
+                    .unwrap_or_else(|| {
+                        eprintln!("Could not resolve {host}.");
+                        std::process::exit(1);
+                    });
+                mode = ServerMode::Remote(addr);
+            }
+            "--host" => mode = ServerMode::Host,
+            "--find-servers" => {
+                let servers =
+                    discover_servers(std::time::Duration::from_secs(1)).unwrap_or_else(|err| {
+                        eprintln!("Could not search the LAN: {err}.");
+                        std::process::exit(1);
+                    });
+                if servers.is_empty() {
+                    println!("No servers found.");
+                }
+                for server in servers {
+                    let incompatible = if server.is_compatible() {
+                        ""
+                    } else {
+                        " (incompatible version)"
+                    };
+                    println!(
+                        "{}  {}  {} players{incompatible}",
+                        server.addr, server.info.name, server.info.players
+                    );
+                }
+                return;
+            }
+            "--name" => {
+                name = args.next().unwrap_or_else(|| exit_usage(&arg));
+                name = name.trim().to_string();
+                if !player_name_is_valid(&name) {
+                    eprintln!("Bad name {name}.");
+                    std::process::exit(2);
+                }
+            }
+            "--sim-up" | "--sim-down" | "--sim" => {
+                let value = args.next().unwrap_or_else(|| exit_usage(&arg));
+                let conditions: LinkConditions = value.parse().unwrap_or_else(|err| {
+                    eprintln!("Bad conditions {value}: {err}.");
+                    std::process::exit(2);
+                });
+                if arg != "--sim-down" {
+                    up = conditions;
+                }
+                if arg != "--sim-up" {
+                    down = conditions;
+                }
+            }
+            "--help" | "-h" => {
+                println!("{USAGE}");
+                return;
+            }
+            _ => exit_usage(&arg),
+        }
+    }
 
     // Start/Run/Free app.
-    ClientApp::launch(&PATH);
+    let reason = ClientApp::launch(&PATH, mode, name, up, down);
+    if reason != LeaveReason::Quit {
+        std::process::exit(1);
+    }
+}
+
+fn exit_usage(arg: &str) -> ! {
+    eprintln!("Bad argument: {arg}\n{USAGE}");
+    std::process::exit(2);
 }
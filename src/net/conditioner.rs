@@ -0,0 +1,316 @@
+use crate::net::{Delivery, NetEvent, NetEventKind, Transport};
+use crate::shared::Rng;
+use crate::time::timestamp_as_usecs;
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+// Extra delay of packets that get held back (in usecs).
+const REORDER_DELAY: u64 = 50_000;
+
+// Simulated link quality, for one direction.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LinkConditions {
+    // Delay added to every packet (in usecs).
+    pub latency: u64,
+
+    // Random extra delay of up to this much (in usecs). Lets unordered packets overtake each other.
+    pub jitter: u64,
+
+    // Chance of a packet getting lost, in [0, 1]. Lost reliable packets arrive a resend later.
+    pub loss: f32,
+
+    // Chance of an unreliable packet arriving twice, in [0, 1].
+    pub duplicate: f32,
+
+    // Chance of an unordered packet being held back, so later ones overtake it, in [0, 1].
+    pub reorder: f32,
+}
+
+#[derive(Debug)]
+pub enum LinkConditionsErr {
+    UnknownKey(String),
+    BadValue(String),
+}
+
+impl std::fmt::Display for LinkConditionsErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::UnknownKey(key) => write!(f, "unknown key {key}"),
+            Self::BadValue(value) => write!(f, "bad value {value}"),
+        }
+    }
+}
+
+// Parses "latency=80,jitter=20,loss=0.05,duplicate=0.01,reorder=0.02". Times are in ms, missing
+// keys are 0.
+impl FromStr for LinkConditions {
+    type Err = LinkConditionsErr;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut conditions = Self::default();
+        for pair in s.split(',').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| LinkConditionsErr::BadValue(pair.to_string()))?;
+            let bad_value = || LinkConditionsErr::BadValue(value.to_string());
+            let ms = || {
+                value
+                    .parse::<u64>()
+                    .map(|ms| ms * 1_000)
+                    .map_err(|_| bad_value())
+            };
+            let chance = || match value.parse::<f32>() {
+                Ok(chance) if (0. ..=1.).contains(&chance) => Ok(chance),
+                _ => Err(bad_value()),
+            };
+            match key {
+                "latency" => conditions.latency = ms()?,
+                "jitter" => conditions.jitter = ms()?,
+                "loss" => conditions.loss = chance()?,
+                "duplicate" => conditions.duplicate = chance()?,
+                "reorder" => conditions.reorder = chance()?,
+                _ => return Err(LinkConditionsErr::UnknownKey(key.to_string())),
+            }
+        }
+        Ok(conditions)
+    }
+}
+
+// Wraps a transport and makes its link worse, separately for outgoing and incoming packets.
+// Reliable packets are never lost or duplicated (only delayed), and ordered ones stay in order.
+// Connect and Disconnect are delayed but stay in order with the data around them.
+pub struct LinkConditioner {
+    inner: Box<dyn Transport>,
+    outgoing: Lane<(SocketAddr, Delivery, Box<[u8]>)>,
+    incoming: Lane<NetEvent>,
+    events: VecDeque<NetEvent>,
+}
+
+impl LinkConditioner {
+    pub fn new(
+        inner: Box<dyn Transport>,
+        outgoing: LinkConditions,
+        incoming: LinkConditions,
+        seed: u64,
+    ) -> Self {
+        Self {
+            inner,
+            outgoing: Lane::new(outgoing, seed),
+            incoming: Lane::new(incoming, !seed),
+            events: VecDeque::new(),
+        }
+    }
+}
+
+impl Transport for LinkConditioner {
+    fn local_addr(&self) -> SocketAddr {
+        self.inner.local_addr()
+    }
+
+    fn send(&mut self, dst: SocketAddr, delivery: Delivery, data: Box<[u8]>) {
+        let ts = timestamp_as_usecs();
+        self.outgoing
+            .push(ts, dst, Some(delivery), (dst, delivery, data));
+    }
+
+    fn poll(&mut self) {
+        let ts = timestamp_as_usecs();
+
+        for (dst, delivery, data) in self.outgoing.pop_due(ts) {
+            self.inner.send(dst, delivery, data);
+        }
+        self.inner.poll();
+
+        while let Some(event) = self.inner.recv() {
+            let delivery = match &event.kind {
+                NetEventKind::Data { delivery, .. } => Some(*delivery),
+                NetEventKind::Connect | NetEventKind::Disconnect => None,
+            };
+            self.incoming.push(ts, event.source, delivery, event);
+        }
+        self.events.extend(self.incoming.pop_due(ts));
+    }
+
+    fn recv(&mut self) -> Option<NetEvent> {
+        self.events.pop_front()
+    }
+}
+
+// Packets going one way, waiting for their delivery time.
+struct Lane<T> {
+    conditions: LinkConditions,
+    rng: Rng,
+
+    // Per peer, the latest delivery time of any packet and of any ordered packet.
+    last_ts: HashMap<SocketAddr, (u64, u64)>,
+
+    // In push order, so ties keep it.
+    pending: Vec<(u64, T)>,
+}
+
+impl<T: Clone> Lane<T> {
+    fn new(conditions: LinkConditions, seed: u64) -> Self {
+        Self {
+            conditions,
+            rng: Rng::new(seed),
+            last_ts: HashMap::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    // No delivery means a connection event, which is never lost and waits for everything before it.
+    fn push(&mut self, ts: u64, addr: SocketAddr, delivery: Option<Delivery>, item: T) {
+        let LinkConditions {
+            latency,
+            jitter,
+            loss,
+            duplicate,
+            reorder,
+        } = self.conditions;
+        let (last_ts, last_ro_ts) = self.last_ts.entry(addr).or_default();
+
+        let mut copies = 1;
+        let mut deliver_ts = ts + latency + self.rng.range(0, jitter + 1);
+        let lost = self.rng.next_f32() < loss;
+        let held_back = self.rng.next_f32() < reorder;
+        match delivery {
+            None => deliver_ts = (ts + latency).max(*last_ts),
+            Some(Delivery::Uu) if lost => return,
+            Some(Delivery::Uu) => {
+                if self.rng.next_f32() < duplicate {
+                    copies = 2;
+                }
+                if held_back {
+                    deliver_ts += REORDER_DELAY;
+                }
+            }
+
+            // As if resent after a round trip.
+            Some(Delivery::Ru | Delivery::Ro) if lost => deliver_ts += 2 * latency + jitter,
+            Some(Delivery::Ru | Delivery::Ro) => {}
+        }
+        match delivery {
+            Some(Delivery::Ru) if held_back => deliver_ts += REORDER_DELAY,
+            Some(Delivery::Ro) | None => {
+                deliver_ts = deliver_ts.max(*last_ro_ts);
+                *last_ro_ts = deliver_ts;
+            }
+            _ => {}
+        }
+        *last_ts = (*last_ts).max(deliver_ts);
+
+        for i in 0..copies {
+            let copy_ts = deliver_ts + i * self.rng.range(0, jitter + 1);
+            *last_ts = (*last_ts).max(copy_ts);
+            self.pending.push((copy_ts, item.clone()));
+        }
+    }
+
+    // Everything that is due, earliest first.
+    fn pop_due(&mut self, ts: u64) -> Vec<T> {
+        self.pending.sort_by_key(|(deliver_ts, _)| *deliver_ts);
+        let due = self
+            .pending
+            .partition_point(|(deliver_ts, _)| *deliver_ts <= ts);
+        self.pending.drain(..due).map(|(_, item)| item).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::LoopbackNetwork;
+
+    fn recv_all(transport: &mut impl Transport) -> Vec<NetEvent> {
+        transport.poll();
+        std::iter::from_fn(|| transport.recv()).collect()
+    }
+
+    fn data(events: &[NetEvent], delivery: Delivery) -> Vec<u8> {
+        events
+            .iter()
+            .filter_map(|event| match &event.kind {
+                NetEventKind::Data { bytes, delivery: d } if *d == delivery => Some(bytes[0]),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_conditions() {
+        let conditions: LinkConditions =
+            "latency=80,jitter=20,loss=0.05,reorder=1".parse().unwrap();
+        assert_eq!(
+            conditions,
+            LinkConditions {
+                latency: 80_000,
+                jitter: 20_000,
+                loss: 0.05,
+                duplicate: 0.,
+                reorder: 1.,
+            }
+        );
+        assert_eq!(
+            "".parse::<LinkConditions>().unwrap(),
+            LinkConditions::default()
+        );
+        assert!("lag=80".parse::<LinkConditions>().is_err());
+        assert!("loss=2".parse::<LinkConditions>().is_err());
+        assert!("latency".parse::<LinkConditions>().is_err());
+    }
+
+    #[test]
+    fn simulated_conditions() {
+        let network = LoopbackNetwork::new();
+        let mut server = network.bind(0);
+        let server_addr = server.local_addr();
+
+        // Everything unreliable is lost, reliable packets still arrive, ordered ones in order.
+        let lossy = LinkConditions {
+            jitter: 20_000,
+            loss: 1.,
+            ..Default::default()
+        };
+        let mut client = LinkConditioner::new(Box::new(network.bind(0)), lossy, lossy, 1);
+        for i in 0..100 {
+            client.send(server_addr, Delivery::Uu, Box::new([0]));
+            client.send(server_addr, Delivery::Ro, Box::new([i]));
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        client.poll();
+        let events = recv_all(&mut server);
+        assert!(matches!(events[0].kind, NetEventKind::Connect));
+        assert!(data(&events, Delivery::Uu).is_empty());
+        assert_eq!(data(&events, Delivery::Ro), (0..100).collect::<Vec<_>>());
+
+        // Unreliable packets get duplicated and overtaken, in the incoming direction too.
+        let messy = LinkConditions {
+            duplicate: 0.5,
+            reorder: 0.5,
+            ..Default::default()
+        };
+        let mut client = LinkConditioner::new(Box::new(network.bind(0)), messy, messy, 2);
+        for i in 0..100 {
+            server.send(client.local_addr(), Delivery::Uu, Box::new([i]));
+        }
+        client.poll();
+        std::thread::sleep(std::time::Duration::from_micros(2 * REORDER_DELAY));
+        let mut received = data(&recv_all(&mut client), Delivery::Uu);
+        assert!(received.len() > 100);
+        assert!(received.windows(2).any(|pair| pair[0] > pair[1]));
+        received.sort();
+        received.dedup();
+        assert_eq!(received, (0..100).collect::<Vec<_>>());
+
+        // Latency holds packets back.
+        let slow = LinkConditions {
+            latency: 1_000_000,
+            ..Default::default()
+        };
+        let mut client = LinkConditioner::new(Box::new(network.bind(0)), slow, slow, 3);
+        client.send(server_addr, Delivery::Ru, Box::new([0]));
+        client.poll();
+        assert!(recv_all(&mut server).is_empty());
+    }
+}
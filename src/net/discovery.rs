@@ -0,0 +1,211 @@
+use crate::shared::PROTOCOL_VERSION;
+use bitcode::{decode, encode, Decode, Encode};
+use std::collections::HashMap;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+// Port servers listen for discovery queries on, next to the default game port.
+pub const DISCOVERY_PORT: u16 = 24421;
+
+// Starts every query and answer, so stray packets on the port are ignored.
+const QUERY_MAGIC: &[u8; 4] = b"DP2?";
+const ANSWER_MAGIC: &[u8; 4] = b"DP2!";
+
+// Longest server name (in bytes) that is advertised.
+const MAX_SERVER_NAME_LEN: usize = 64;
+
+// Queries are padded to this many bytes, more than any answer takes, and shorter ones are
+// ignored. Otherwise a spoofed query would get its "source" a bigger answer than it cost to send.
+const QUERY_SIZE: usize = 128;
+
+// What a server advertises about itself.
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub struct ServerInfo {
+    pub name: String,
+    pub players: u32,
+    pub version: u32,
+
+    // Game port, the answer comes from the discovery port.
+    pub port: u16,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiscoveredServer {
+    // Where to connect to.
+    pub addr: SocketAddr,
+    pub info: ServerInfo,
+}
+
+impl DiscoveredServer {
+    pub fn is_compatible(&self) -> bool {
+        self.info.version == PROTOCOL_VERSION
+    }
+}
+
+// Answers discovery queries on behalf of a server. Polled by the server loop, never blocks.
+pub struct DiscoveryResponder {
+    sock: UdpSocket,
+    info: ServerInfo,
+}
+
+impl DiscoveryResponder {
+    // Usually bound to ("0.0.0.0", DISCOVERY_PORT). Only one server per host can have the port.
+    pub fn bind(addr: impl ToSocketAddrs, name: &str, game_port: u16) -> std::io::Result<Self> {
+        let sock = UdpSocket::bind(addr)?;
+        sock.set_nonblocking(true)?;
+        let mut name = name.to_string();
+        while name.len() > MAX_SERVER_NAME_LEN {
+            name.pop();
+        }
+        let info = ServerInfo {
+            name,
+            players: 0,
+            version: PROTOCOL_VERSION,
+            port: game_port,
+        };
+        Ok(Self { sock, info })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.sock.local_addr().unwrap()
+    }
+
+    // Answers every query received since the last poll.
+    pub fn poll(&mut self, players: usize) {
+        self.info.players = players as u32;
+        let mut answer = ANSWER_MAGIC.to_vec();
+        answer.extend_from_slice(&encode(&self.info));
+
+        let mut buf = [0; QUERY_SIZE];
+        while let Ok((len, source)) = self.sock.recv_from(&mut buf) {
+            if len >= answer.len() && buf.starts_with(QUERY_MAGIC) {
+                let _ = self.sock.send_to(&answer, source);
+            }
+        }
+    }
+}
+
+// Looks for servers. Send queries, then collect the answers as they come in.
+pub struct LanDiscovery {
+    sock: UdpSocket,
+    servers: HashMap<SocketAddr, ServerInfo>,
+}
+
+impl LanDiscovery {
+    pub fn new() -> std::io::Result<Self> {
+        let sock = UdpSocket::bind(("0.0.0.0", 0))?;
+        sock.set_broadcast(true)?;
+        sock.set_nonblocking(true)?;
+        Ok(Self {
+            sock,
+            servers: HashMap::new(),
+        })
+    }
+
+    // To ("255.255.255.255", DISCOVERY_PORT) for the whole LAN, or to a single host.
+    pub fn query(&mut self, addr: impl ToSocketAddrs) -> std::io::Result<()> {
+        let mut query = QUERY_MAGIC.to_vec();
+        query.resize(QUERY_SIZE, 0);
+        for addr in addr.to_socket_addrs()? {
+            self.sock.send_to(&query, addr)?;
+        }
+        Ok(())
+    }
+
+    // Picks up answers, returns every server found so far (sorted by address).
+    pub fn poll(&mut self) -> Vec<DiscoveredServer> {
+        let mut buf = [0; 1024];
+        while let Ok((len, source)) = self.sock.recv_from(&mut buf) {
+            let Some(info) = buf[..len]
+                .strip_prefix(ANSWER_MAGIC)
+                .and_then(|bytes| decode::<ServerInfo>(bytes).ok())
+                .filter(|info| info.name.len() <= MAX_SERVER_NAME_LEN && info.port != 0)
+            else {
+                continue;
+            };
+            let addr = SocketAddr::new(source.ip(), info.port);
+            self.servers.insert(addr, info);
+        }
+
+        let mut servers: Vec<_> = self
+            .servers
+            .iter()
+            .map(|(addr, info)| DiscoveredServer {
+                addr: *addr,
+                info: info.clone(),
+            })
+            .collect();
+        servers.sort_by_key(|server| server.addr);
+        servers
+    }
+}
+
+// Broadcasts a query on the LAN and lists whoever answers within wait.
+pub fn discover_servers(wait: Duration) -> std::io::Result<Vec<DiscoveredServer>> {
+    let mut discovery = LanDiscovery::new()?;
+    discovery.query(("255.255.255.255", DISCOVERY_PORT))?;
+    std::thread::sleep(wait);
+    Ok(discovery.poll())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Instant;
+
+    #[test]
+    fn discovers_on_loopback() {
+        let mut responder =
+            DiscoveryResponder::bind(("127.0.0.1", 0), "Test server", 1234).unwrap();
+        let mut discovery = LanDiscovery::new().unwrap();
+
+        // Garbage is ignored on both ends, and so are queries that aren't padded.
+        discovery.query(responder.local_addr()).unwrap();
+        let garbage = UdpSocket::bind(("127.0.0.1", 0)).unwrap();
+        garbage.set_nonblocking(true).unwrap();
+        garbage.send_to(b"hello", responder.local_addr()).unwrap();
+        garbage
+            .send_to(QUERY_MAGIC, responder.local_addr())
+            .unwrap();
+        garbage
+            .send_to(b"DP2!garbage", discovery.sock.local_addr().unwrap())
+            .unwrap();
+
+        let start = Instant::now();
+        let servers = loop {
+            responder.poll(3);
+            let servers = discovery.poll();
+            if !servers.is_empty() {
+                break servers;
+            }
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(1));
+        };
+        assert_eq!(
+            servers,
+            [DiscoveredServer {
+                addr: "127.0.0.1:1234".parse().unwrap(),
+                info: ServerInfo {
+                    name: String::from("Test server"),
+                    players: 3,
+                    version: PROTOCOL_VERSION,
+                    port: 1234,
+                },
+            }]
+        );
+        assert!(servers[0].is_compatible());
+        assert!(garbage.recv_from(&mut [0; QUERY_SIZE]).is_err());
+    }
+
+    #[test]
+    fn answers_fit_in_queries() {
+        let name = "x".repeat(2 * MAX_SERVER_NAME_LEN);
+        let responder = DiscoveryResponder::bind(("127.0.0.1", 0), &name, u16::MAX).unwrap();
+        let info = ServerInfo {
+            players: u32::MAX,
+            version: u32::MAX,
+            ..responder.info
+        };
+        assert!(ANSWER_MAGIC.len() + encode(&info).len() <= QUERY_SIZE);
+    }
+}
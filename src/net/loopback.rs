@@ -0,0 +1,196 @@
+use crate::net::{Delivery, NetEvent, NetEventKind, Transport};
+use crossbeam_channel::{Receiver, Sender};
+use std::collections::{HashMap, VecDeque};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+// An in-process network. Transports bound to the same network reach each other by address,
+// without any sockets involved. Packets arrive instantly and in order, wrap the transports in
+// a LinkConditioner to make things worse.
+#[derive(Clone, Default)]
+pub struct LoopbackNetwork(Arc<Mutex<LoopbackNetworkInner>>);
+
+#[derive(Default)]
+struct LoopbackNetworkInner {
+    endpoints: HashMap<SocketAddr, Sender<Datagram>>,
+    next_port: u16,
+}
+
+impl LoopbackNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Port 0 picks a free port.
+    pub fn bind(&self, port: u16) -> LoopbackTransport {
+        let mut inner = self.0.lock().unwrap();
+        let addr = |port| SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let port = match port {
+            0 => loop {
+                inner.next_port = inner.next_port.max(1024).wrapping_add(1);
+                if !inner.endpoints.contains_key(&addr(inner.next_port)) {
+                    break inner.next_port;
+                }
+            },
+            port => port,
+        };
+        let addr = addr(port);
+        assert!(!inner.endpoints.contains_key(&addr), "{addr} is taken");
+
+        let (send, recv) = crossbeam_channel::unbounded();
+        inner.endpoints.insert(addr, send);
+
+        LoopbackTransport {
+            network: self.clone(),
+            addr,
+            recv,
+            peers: HashMap::new(),
+            events: VecDeque::new(),
+        }
+    }
+}
+
+struct Datagram {
+    source: SocketAddr,
+    kind: DatagramKind,
+}
+
+enum DatagramKind {
+    Data {
+        delivery: Delivery,
+        bytes: Box<[u8]>,
+    },
+
+    // The source transport was dropped.
+    Disconnect,
+}
+
+pub struct LoopbackTransport {
+    network: LoopbackNetwork,
+    addr: SocketAddr,
+    recv: Receiver<Datagram>,
+
+    // Everyone we sent to or received from, and whether they connected to us.
+    peers: HashMap<SocketAddr, bool>,
+
+    // Delivered, but not received yet.
+    events: VecDeque<NetEvent>,
+}
+
+impl Transport for LoopbackTransport {
+    fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    fn send(&mut self, dst: SocketAddr, delivery: Delivery, bytes: Box<[u8]>) {
+        // Like UDP, sending to nobody goes nowhere.
+        let Some(endpoint) = self.network.0.lock().unwrap().endpoints.get(&dst).cloned() else {
+            return;
+        };
+        self.peers.entry(dst).or_default();
+
+        let kind = DatagramKind::Data { delivery, bytes };
+        let _ = endpoint.send(Datagram {
+            source: self.addr,
+            kind,
+        });
+    }
+
+    fn poll(&mut self) {
+        for Datagram { source, kind } in self.recv.try_iter() {
+            let connected = self.peers.entry(source).or_default();
+            match kind {
+                DatagramKind::Data { delivery, bytes } => {
+                    if !*connected {
+                        *connected = true;
+                        let kind = NetEventKind::Connect;
+                        self.events.push_back(NetEvent { source, kind });
+                    }
+
+                    let kind = NetEventKind::Data { bytes, delivery };
+                    self.events.push_back(NetEvent { source, kind });
+                }
+
+                DatagramKind::Disconnect => {
+                    if self.peers.remove(&source) == Some(true) {
+                        let kind = NetEventKind::Disconnect;
+                        self.events.push_back(NetEvent { source, kind });
+                    }
+                }
+            }
+        }
+    }
+
+    fn recv(&mut self) -> Option<NetEvent> {
+        self.events.pop_front()
+    }
+}
+
+impl Drop for LoopbackTransport {
+    fn drop(&mut self) {
+        let mut inner = self.network.0.lock().unwrap();
+        inner.endpoints.remove(&self.addr);
+
+        // Tell everyone we talked to. Channels are in order, so this arrives after our data.
+        for addr in self.peers.keys() {
+            if let Some(endpoint) = inner.endpoints.get(addr) {
+                let _ = endpoint.send(Datagram {
+                    source: self.addr,
+                    kind: DatagramKind::Disconnect,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn recv_all(transport: &mut LoopbackTransport) -> Vec<NetEvent> {
+        transport.poll();
+        std::iter::from_fn(|| transport.recv()).collect()
+    }
+
+    fn data(events: &[NetEvent]) -> Vec<u8> {
+        events
+            .iter()
+            .filter_map(|event| match &event.kind {
+                NetEventKind::Data { bytes, .. } => Some(bytes[0]),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn connect_data_disconnect() {
+        let network = LoopbackNetwork::new();
+        let mut server = network.bind(4000);
+        let mut client = network.bind(0);
+        let (server_addr, client_addr) = (server.local_addr(), client.local_addr());
+
+        client.send(server_addr, Delivery::Ru, Box::new([1]));
+        client.send(server_addr, Delivery::Uu, Box::new([2]));
+        let events = recv_all(&mut server);
+        assert!(matches!(events[0].kind, NetEventKind::Connect));
+        assert!(events.iter().all(|event| event.source == client_addr));
+        assert_eq!(data(&events), vec![1, 2]);
+
+        server.send(client_addr, Delivery::Ro, Box::new([3]));
+        assert_eq!(data(&recv_all(&mut client)), vec![3]);
+
+        // Sending to nobody is fine.
+        let nobody = SocketAddr::from((Ipv4Addr::LOCALHOST, 1));
+        client.send(nobody, Delivery::Ru, Box::new([4]));
+
+        drop(client);
+        let events = recv_all(&mut server);
+        assert!(matches!(
+            events[..],
+            [NetEvent {
+                kind: NetEventKind::Disconnect,
+                ..
+            }]
+        ));
+    }
+}
@@ -0,0 +1,140 @@
+mod conditioner;
+mod discovery;
+mod loopback;
+mod ping;
+mod send_queue;
+mod transport;
+
+pub use conditioner::*;
+pub use discovery::*;
+pub use loopback::*;
+pub use ping::*;
+pub use send_queue::*;
+pub use transport::*;
+
+use crate::shared::NetMessage;
+use std::net::{SocketAddr, ToSocketAddrs};
+
+#[derive(Clone, Debug)]
+pub enum NetEventKind {
+    Data {
+        bytes: Box<[u8]>,
+        delivery: Delivery,
+    },
+    Connect,
+    Disconnect,
+}
+
+#[derive(Clone, Debug)]
+pub struct NetEvent {
+    pub source: SocketAddr,
+    pub kind: NetEventKind,
+}
+
+pub struct ClientNetManager {
+    transport: Box<dyn Transport>,
+    addr: SocketAddr,
+}
+
+#[allow(dead_code)]
+impl ClientNetManager {
+    // Connects over UDP.
+    pub fn new(addr: impl ToSocketAddrs) -> Self {
+        Self::with_transport(addr, Box::new(LaminarTransport::bind_any()))
+    }
+
+    pub fn with_transport(addr: impl ToSocketAddrs, transport: Box<dyn Transport>) -> Self {
+        let addr = addr.to_socket_addrs().unwrap().next().unwrap();
+        Self { transport, addr }
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.transport.local_addr()
+    }
+
+    pub fn server_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn poll(&mut self) {
+        self.transport.poll();
+    }
+
+    // Only what the server sent. Anyone else could be trying to pass off kicks or state as the
+    // server's.
+    pub fn recv(&mut self) -> impl Iterator<Item = NetEvent> {
+        let addr = self.addr;
+        let events: Vec<_> = std::iter::from_fn(|| self.transport.recv())
+            .filter(|event| event.source == addr)
+            .collect();
+        events.into_iter()
+    }
+
+    pub fn send_uu(&mut self, data: Box<[u8]>) {
+        self.transport.send(self.addr, Delivery::Uu, data);
+    }
+
+    pub fn send_ru(&mut self, data: Box<[u8]>) {
+        self.transport.send(self.addr, Delivery::Ru, data);
+    }
+
+    pub fn send_ro(&mut self, data: Box<[u8]>) {
+        self.transport.send(self.addr, Delivery::Ro, data);
+    }
+
+    pub fn flush<T: NetMessage>(&mut self, queue: &mut SendQueue<T>) -> FlushReport {
+        let addr = self.addr;
+        queue.flush(|delivery, data| self.transport.send(addr, delivery, data))
+    }
+}
+
+pub struct ServerNetManager {
+    transport: Box<dyn Transport>,
+}
+
+#[allow(dead_code)]
+impl ServerNetManager {
+    // Listens on UDP. Port 0 picks a free port, the one bound to is returned.
+    pub fn new(bind_port: u16) -> (Self, u16) {
+        let transport = LaminarTransport::bind(("0.0.0.0", bind_port));
+        let bind_port = transport.local_addr().port();
+        (Self::with_transport(Box::new(transport)), bind_port)
+    }
+
+    pub fn with_transport(transport: Box<dyn Transport>) -> Self {
+        Self { transport }
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.transport.local_addr()
+    }
+
+    pub fn poll(&mut self) {
+        self.transport.poll();
+    }
+
+    pub fn recv(&mut self) -> impl Iterator<Item = NetEvent> {
+        let events: Vec<_> = std::iter::from_fn(|| self.transport.recv()).collect();
+        events.into_iter()
+    }
+
+    pub fn send_uu(&mut self, dst: SocketAddr, data: Box<[u8]>) {
+        self.transport.send(dst, Delivery::Uu, data);
+    }
+
+    pub fn send_ru(&mut self, dst: SocketAddr, data: Box<[u8]>) {
+        self.transport.send(dst, Delivery::Ru, data);
+    }
+
+    pub fn send_ro(&mut self, dst: SocketAddr, data: Box<[u8]>) {
+        self.transport.send(dst, Delivery::Ro, data);
+    }
+
+    pub fn flush<T: NetMessage>(
+        &mut self,
+        dst: SocketAddr,
+        queue: &mut SendQueue<T>,
+    ) -> FlushReport {
+        queue.flush(|delivery, data| self.transport.send(dst, delivery, data))
+    }
+}
@@ -0,0 +1,121 @@
+use std::collections::VecDeque;
+
+// Time between pings (in usecs).
+pub const PING_INTERVAL: u64 = 1_000_000;
+
+// Pings waited on at once. Older ones are considered lost.
+const MAX_PINGS_IN_FLIGHT: usize = 8;
+
+// Link quality towards a peer, as measured by pings.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct LinkStats {
+    // Smoothed round trip time and its mean deviation (in usecs).
+    pub rtt: u64,
+    pub rtt_var: u64,
+
+    // The peer's clock minus ours (in usecs), smoothed. Add it to a local timestamp to get the
+    // peer's.
+    pub clock_offset: i64,
+
+    pub pings_sent: u32,
+    pub pongs_received: u32,
+}
+
+// Sends a ping every PING_INTERVAL, and turns the pongs (which carry the peer's clock) into
+// LinkStats. RTT smoothing is the same as TCP's.
+pub struct PingTracker {
+    seq: u32,
+    ping_ts: u64,
+
+    // (seq, ts) of unanswered pings, oldest first.
+    in_flight: VecDeque<(u32, u64)>,
+
+    stats: LinkStats,
+}
+
+impl PingTracker {
+    pub fn new() -> Self {
+        Self {
+            seq: 0,
+            ping_ts: 0,
+            in_flight: VecDeque::new(),
+            stats: LinkStats::default(),
+        }
+    }
+
+    pub fn stats(&self) -> LinkStats {
+        self.stats
+    }
+
+    // The seq of a ping to send, if it is time for one.
+    pub fn poll(&mut self, ts: u64) -> Option<u32> {
+        if ts < self.ping_ts {
+            return None;
+        }
+        self.ping_ts = ts + PING_INTERVAL;
+        self.seq += 1;
+        self.stats.pings_sent += 1;
+        self.in_flight.push_back((self.seq, ts));
+        if self.in_flight.len() > MAX_PINGS_IN_FLIGHT {
+            self.in_flight.pop_front();
+        }
+        Some(self.seq)
+    }
+
+    // remote_ts is the peer's clock when it answered. Unknown (or duplicate) seqs are ignored.
+    pub fn pong(&mut self, ts: u64, seq: u32, remote_ts: u64) {
+        let Some(i) = self.in_flight.iter().position(|(s, _)| *s == seq) else {
+            return;
+        };
+        let (_, ping_ts) = self.in_flight.remove(i).unwrap();
+        let Some(rtt) = ts.checked_sub(ping_ts) else {
+            return;
+        };
+
+        // Assume the pong took half the round trip.
+        let offset = remote_ts as i64 + (rtt / 2) as i64 - ts as i64;
+
+        let stats = &mut self.stats;
+        if stats.pongs_received == 0 {
+            stats.rtt = rtt;
+            stats.rtt_var = rtt / 2;
+            stats.clock_offset = offset;
+        } else {
+            stats.rtt_var = (3 * stats.rtt_var + stats.rtt.abs_diff(rtt)) / 4;
+            stats.rtt = (7 * stats.rtt + rtt) / 8;
+            stats.clock_offset += (offset - stats.clock_offset) / 8;
+        }
+        stats.pongs_received += 1;
+    }
+}
+
+impl Default for PingTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn estimates_rtt_and_offset() {
+        // The peer's clock is 5s ahead, and the link takes 20ms each way.
+        let mut tracker = PingTracker::new();
+        for i in 0..20 {
+            let ts = i * PING_INTERVAL;
+            let seq = tracker.poll(ts).unwrap();
+            assert_eq!(tracker.poll(ts + 1), None);
+            tracker.pong(ts + 40_000, seq, ts + 20_000 + 5_000_000);
+        }
+        let stats = tracker.stats();
+        assert_eq!(stats.rtt, 40_000);
+        assert_eq!(stats.clock_offset, 5_000_000);
+        assert_eq!(stats.pongs_received, 20);
+
+        // Stale and duplicate pongs don't count.
+        tracker.pong(100 * PING_INTERVAL, 3, 0);
+        assert_eq!(tracker.stats(), stats);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::shared::{serialize, NetMessage};
+use bitcode::encode;
+use std::collections::VecDeque;
+
+// Packets are filled up to this many bytes. Larger messages still go out alone, and the
+// transport splits them up (see LaminarTransport). Changing how packets are packed means bumping
+// PROTOCOL_VERSION.
+pub const MAX_PACKET_SIZE: usize = 1200;
+
+// Higher priorities are sent first. When the budget runs out, everything after is deferred.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Priority {
+    // Player state.
+    High = 0,
+
+    // World state (chunks, tile edits).
+    Normal = 1,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum Delivery {
+    // Unreliable sequenced. Dropped instead of deferred, the next tick has newer data.
+    Uu,
+    // Reliable unordered.
+    Ru,
+    // Reliable ordered.
+    Ro,
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct FlushReport {
+    pub packets: usize,
+    pub bytes: usize,
+
+    // Reliable messages held back for the next flush.
+    pub deferred: usize,
+
+    // Unreliable messages that didn't fit.
+    pub dropped: usize,
+}
+
+// Messages waiting to be sent to one peer. Every flush packs them into packets of at most
+// MAX_PACKET_SIZE bytes, and sends at most budget bytes worth (but always at least one packet).
+pub struct SendQueue<T> {
+    budget: usize,
+    queues: [VecDeque<(Delivery, T, usize)>; 2],
+}
+
+impl<T: NetMessage> SendQueue<T> {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            budget,
+            queues: [VecDeque::new(), VecDeque::new()],
+        }
+    }
+
+    pub fn push(&mut self, priority: Priority, delivery: Delivery, msg: T) {
+        let size = encode(&msg).len();
+        self.queues[priority as usize].push_back((delivery, msg, size));
+    }
+
+    pub fn len(&self) -> usize {
+        self.queues.iter().map(VecDeque::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn flush(&mut self, mut send: impl FnMut(Delivery, Box<[u8]>)) -> FlushReport {
+        let mut report = FlushReport::default();
+        'out: for queue in self.queues.iter_mut() {
+            while let Some(&(delivery, _, _)) = queue.front() {
+                // Take messages with the same delivery until the packet is full.
+                let mut n = 0;
+                let mut size = 1;
+                for &(msg_delivery, _, msg_size) in queue.iter() {
+                    if msg_delivery != delivery || (n > 0 && size + msg_size > MAX_PACKET_SIZE) {
+                        break;
+                    }
+                    n += 1;
+                    size += msg_size;
+                }
+
+                // Out of budget. Lower priorities wait too, so reliable ordered messages
+                // never overtake each other.
+                if report.packets > 0 && report.bytes + size > self.budget {
+                    break 'out;
+                }
+
+                let msgs: Vec<T> = queue.drain(..n).map(|(_, msg, _)| msg).collect();
+                let bytes = serialize(&msgs);
+                report.packets += 1;
+                report.bytes += bytes.len();
+                send(delivery, bytes);
+            }
+        }
+
+        // Whatever unreliable data is left is stale by the next flush.
+        for queue in self.queues.iter_mut() {
+            let len = queue.len();
+            queue.retain(|(delivery, _, _)| *delivery != Delivery::Uu);
+            report.dropped += len - queue.len();
+        }
+        report.deferred = self.len();
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shared::{deserialize, ClientNetMessage, HumanoidInput};
+
+    fn input(seq: u32) -> ClientNetMessage {
+        ClientNetMessage::PlayerInput {
+            seq,
+            inputs: vec![HumanoidInput::default(); 8],
+            snapshot_ack: 0,
+        }
+    }
+
+    #[test]
+    fn send_queue_splits_and_budgets() {
+        let mut queue = SendQueue::new(4 * MAX_PACKET_SIZE);
+        for seq in 0..1000 {
+            queue.push(Priority::Normal, Delivery::Ro, input(seq));
+        }
+        queue.push(
+            Priority::High,
+            Delivery::Uu,
+            ClientNetMessage::Ping { seq: 0 },
+        );
+
+        let mut packets = Vec::new();
+        let report = queue.flush(|delivery, bytes| packets.push((delivery, bytes)));
+
+        // Split into packets that fit, within budget, high priority first.
+        assert_eq!(report.packets, packets.len());
+        assert!(report.bytes <= 4 * MAX_PACKET_SIZE);
+        assert!(packets
+            .iter()
+            .all(|(_, bytes)| bytes.len() <= MAX_PACKET_SIZE));
+        assert_eq!(packets[0].0, Delivery::Uu);
+        assert!(report.deferred > 0);
+        assert_eq!(report.dropped, 0);
+
+        // The rest follows in order over the next flushes.
+        let mut seqs = Vec::new();
+        while !queue.is_empty() {
+            queue.flush(|delivery, bytes| packets.push((delivery, bytes)));
+        }
+        for (_, bytes) in &packets[1..] {
+            for msg in deserialize::<ClientNetMessage>(bytes).unwrap().iter() {
+                if let ClientNetMessage::PlayerInput { seq, .. } = msg {
+                    seqs.push(*seq);
+                }
+            }
+        }
+        assert_eq!(seqs, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn send_queue_drops_stale_unreliable() {
+        let mut queue = SendQueue::new(1);
+        queue.push(
+            Priority::High,
+            Delivery::Ru,
+            ClientNetMessage::Ping { seq: 0 },
+        );
+        queue.push(Priority::High, Delivery::Uu, input(0));
+        queue.push(
+            Priority::Normal,
+            Delivery::Ru,
+            ClientNetMessage::JoinComplete,
+        );
+
+        // Always sends one packet, even over budget.
+        let report = queue.flush(|_, _| {});
+        assert_eq!(report.packets, 1);
+        assert_eq!(report.dropped, 1);
+        assert_eq!(report.deferred, 1);
+    }
+}
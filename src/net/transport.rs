@@ -0,0 +1,350 @@
+use crate::net::{Delivery, NetEvent, NetEventKind, MAX_PACKET_SIZE};
+use crossbeam_channel::{Receiver, Sender};
+use laminar::*;
+use std::collections::VecDeque;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+// Moves packets between peers. Connect is reported for the first packet from a new peer, and
+// Disconnect once a peer is gone.
+pub trait Transport: Send {
+    fn local_addr(&self) -> SocketAddr;
+
+    fn send(&mut self, dst: SocketAddr, delivery: Delivery, data: Box<[u8]>);
+
+    // Sends pending packets and picks up received ones.
+    fn poll(&mut self);
+
+    fn recv(&mut self) -> Option<NetEvent>;
+}
+
+// Payloads larger than MAX_PACKET_SIZE go out as fragments, each sent with the payload's
+// delivery, and get put back together on arrival. laminar can't be left to do it: it refuses
+// unreliable packets larger than a datagram, and reliable ones over 16KiB.
+//
+// A fragment is [FRAGMENT_TAG][id: u32 le][index: u16 le][count: u16 le][data]. Packets never
+// start with FRAGMENT_TAG otherwise, so whole payloads go out as they are. Changing any of this
+// means bumping PROTOCOL_VERSION.
+const FRAGMENT_TAG: u8 = 0xff;
+const FRAGMENT_HEADER_SIZE: usize = 9;
+
+// Largest payload that can be sent (about 1.2MB).
+const MAX_FRAGMENTS: usize = 1024;
+
+// Most partly received payloads kept around per peer, and how long one gets to complete. Past
+// either, the oldest is given up on, which only happens when fragments of unreliable payloads get
+// lost (or someone sends junk). Per peer, so nobody can push out anyone else's.
+const MAX_PARTIAL_PAYLOADS: usize = 8;
+const PARTIAL_PAYLOAD_LIFETIME: Duration = Duration::from_secs(10);
+
+pub(crate) fn fragment_header(id: u32, index: usize, count: usize) -> [u8; FRAGMENT_HEADER_SIZE] {
+    let mut header = [FRAGMENT_TAG; FRAGMENT_HEADER_SIZE];
+    header[1..5].copy_from_slice(&id.to_le_bytes());
+    header[5..7].copy_from_slice(&(index as u16).to_le_bytes());
+    header[7..9].copy_from_slice(&(count as u16).to_le_bytes());
+    header
+}
+
+#[derive(Debug, PartialEq)]
+pub struct PayloadTooLarge(pub usize);
+
+impl std::fmt::Display for PayloadTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} bytes is more than the {} that can be sent",
+            self.0,
+            MAX_FRAGMENTS * MAX_PACKET_SIZE
+        )
+    }
+}
+
+struct PartialPayload {
+    source: SocketAddr,
+    id: u32,
+    fragments: Vec<Option<Box<[u8]>>>,
+    missing: usize,
+    started: Instant,
+}
+
+// UDP, through laminar.
+pub struct LaminarTransport {
+    sock: Socket,
+    send: Sender<Packet>,
+    recv: Receiver<SocketEvent>,
+
+    // Fragmentation, see FRAGMENT_TAG.
+    fragment_id: u32,
+    partials: VecDeque<PartialPayload>,
+}
+
+impl LaminarTransport {
+    pub fn bind(addr: impl ToSocketAddrs) -> Self {
+        let sock = Socket::bind(addr).unwrap();
+        let send = sock.get_packet_sender();
+        let recv = sock.get_event_receiver();
+        Self {
+            sock,
+            send,
+            recv,
+            fragment_id: 0,
+            partials: VecDeque::new(),
+        }
+    }
+
+    fn send_packet(&mut self, dst: SocketAddr, delivery: Delivery, data: Vec<u8>) {
+        let packet = match delivery {
+            // Sequencing would drop a fragment arriving after a newer packet, and with it the whole
+            // payload. reassemble puts fragments in order anyway.
+            Delivery::Uu if data.first() == Some(&FRAGMENT_TAG) => Packet::unreliable(dst, data),
+            Delivery::Uu => Packet::unreliable_sequenced(dst, data, None),
+            Delivery::Ru => Packet::reliable_unordered(dst, data),
+            Delivery::Ro => Packet::reliable_ordered(dst, data, None),
+        };
+        self.send.send(packet).unwrap();
+    }
+
+    fn fragment(&mut self, data: &[u8]) -> std::result::Result<Vec<Vec<u8>>, PayloadTooLarge> {
+        let count = data.len().div_ceil(MAX_PACKET_SIZE);
+        if count > MAX_FRAGMENTS {
+            return Err(PayloadTooLarge(data.len()));
+        }
+        let id = self.fragment_id;
+        self.fragment_id = self.fragment_id.wrapping_add(1);
+        let fragments = data
+            .chunks(MAX_PACKET_SIZE)
+            .enumerate()
+            .map(|(index, chunk)| {
+                let mut fragment = Vec::with_capacity(FRAGMENT_HEADER_SIZE + chunk.len());
+                fragment.extend_from_slice(&fragment_header(id, index, count));
+                fragment.extend_from_slice(chunk);
+                fragment
+            })
+            .collect();
+        Ok(fragments)
+    }
+
+    // Adds a fragment to its payload. Returns the whole payload once it's complete, and None
+    // otherwise (including for malformed fragments).
+    fn reassemble(&mut self, source: SocketAddr, bytes: &[u8]) -> Option<Box<[u8]>> {
+        if bytes.len() < FRAGMENT_HEADER_SIZE {
+            return None;
+        }
+        let id = u32::from_le_bytes(bytes[1..5].try_into().unwrap());
+        let index = u16::from_le_bytes(bytes[5..7].try_into().unwrap()) as usize;
+        let count = u16::from_le_bytes(bytes[7..9].try_into().unwrap()) as usize;
+        if index >= count || count > MAX_FRAGMENTS {
+            return None;
+        }
+
+        let now = Instant::now();
+        self.partials
+            .retain(|partial| now - partial.started < PARTIAL_PAYLOAD_LIFETIME);
+        let position = self
+            .partials
+            .iter()
+            .position(|partial| partial.source == source && partial.id == id);
+        let position = match position {
+            Some(position) => position,
+            None => {
+                let mut from_source = self
+                    .partials
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, partial)| partial.source == source);
+                if let Some((oldest, _)) = from_source.next() {
+                    if from_source.count() + 1 >= MAX_PARTIAL_PAYLOADS {
+                        self.partials.remove(oldest);
+                    }
+                }
+                self.partials.push_back(PartialPayload {
+                    source,
+                    id,
+                    fragments: vec![None; count],
+                    missing: count,
+                    started: now,
+                });
+                self.partials.len() - 1
+            }
+        };
+        let partial = &mut self.partials[position];
+        if partial.fragments.len() != count || partial.fragments[index].is_some() {
+            return None;
+        }
+        partial.fragments[index] = Some(Box::from(&bytes[FRAGMENT_HEADER_SIZE..]));
+        partial.missing -= 1;
+        if partial.missing > 0 {
+            return None;
+        }
+
+        let partial = self.partials.remove(position).unwrap();
+        let fragments = partial.fragments.into_iter().flatten();
+        Some(fragments.flat_map(Vec::from).collect())
+    }
+
+    pub fn bind_any() -> Self {
+        Self::bind(("0.0.0.0", 0))
+    }
+}
+
+impl Transport for LaminarTransport {
+    fn local_addr(&self) -> SocketAddr {
+        self.sock.local_addr().unwrap()
+    }
+
+    fn send(&mut self, dst: SocketAddr, delivery: Delivery, data: Box<[u8]>) {
+        if data.len() <= MAX_PACKET_SIZE {
+            self.send_packet(dst, delivery, Vec::from(data));
+            return;
+        }
+
+        // Whatever made something that large is a bug, but not one worth going down over.
+        match self.fragment(&data) {
+            Ok(fragments) => {
+                for fragment in fragments {
+                    self.send_packet(dst, delivery, fragment);
+                }
+            }
+            Err(err) => println!("WARNING: Dropped a payload to {dst}: {err}."),
+        }
+    }
+
+    fn poll(&mut self) {
+        self.sock.manual_poll(Instant::now());
+    }
+
+    fn recv(&mut self) -> Option<NetEvent> {
+        loop {
+            let event = self.recv.try_recv().ok()?;
+            let (source, kind) = match event {
+                SocketEvent::Packet(packet) => {
+                    let delivery = match (packet.delivery_guarantee(), packet.order_guarantee()) {
+                        (DeliveryGuarantee::Unreliable, _) => Delivery::Uu,
+                        (DeliveryGuarantee::Reliable, OrderingGuarantee::Ordered(_)) => {
+                            Delivery::Ro
+                        }
+                        (DeliveryGuarantee::Reliable, _) => Delivery::Ru,
+                    };
+                    let bytes = match packet.payload().first() {
+                        Some(&FRAGMENT_TAG) => {
+                            match self.reassemble(packet.addr(), packet.payload()) {
+                                Some(bytes) => bytes,
+                                None => continue,
+                            }
+                        }
+                        _ => Box::from(packet.payload()),
+                    };
+                    (packet.addr(), NetEventKind::Data { bytes, delivery })
+                }
+                SocketEvent::Connect(addr) => (addr, NetEventKind::Connect),
+                SocketEvent::Disconnect(addr) | SocketEvent::Timeout(addr) => {
+                    self.partials.retain(|partial| partial.source != addr);
+                    (addr, NetEventKind::Disconnect)
+                }
+            };
+            return Some(NetEvent { source, kind });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn sends_large_payloads() {
+        let mut a = LaminarTransport::bind(("127.0.0.1", 0));
+        let mut b = LaminarTransport::bind(("127.0.0.1", 0));
+        let b_addr = b.local_addr();
+
+        // Too large for a datagram (unreliable), and for laminar's own fragmenting (reliable).
+        let payload = |len: usize, seed: u8| -> Box<[u8]> {
+            (0..len).map(|i| (i as u8).wrapping_mul(seed)).collect()
+        };
+        let sent = [
+            (Delivery::Uu, payload(10 * MAX_PACKET_SIZE + 7, 3)),
+            (Delivery::Ro, payload(40 * 1024, 5)),
+            (Delivery::Ru, payload(MAX_PACKET_SIZE, 7)),
+        ];
+        for (delivery, bytes) in &sent {
+            a.send(b_addr, *delivery, bytes.clone());
+        }
+
+        let mut received = Vec::new();
+        let start = Instant::now();
+        while received.len() < sent.len() {
+            assert!(start.elapsed().as_secs() < 5, "only got {}", received.len());
+            a.poll();
+            b.poll();
+            while let Some(event) = b.recv() {
+                if let NetEventKind::Data { bytes, delivery } = event.kind {
+                    received.push((delivery, bytes));
+                }
+            }
+            std::thread::sleep(std::time::Duration::from_millis(1));
+        }
+        for sent in &sent {
+            assert!(received.contains(sent));
+        }
+        assert!(b.partials.is_empty());
+    }
+
+    #[test]
+    fn reassembles_out_of_order() {
+        let mut transport = LaminarTransport::bind(("127.0.0.1", 0));
+        let source = transport.local_addr();
+        let fragments: Vec<Vec<u8>> = (0..3)
+            .map(|index| {
+                let mut fragment = fragment_header(7, index, 3).to_vec();
+                fragment.push(index as u8);
+                fragment
+            })
+            .collect();
+        assert_eq!(transport.reassemble(source, &fragments[2]), None);
+        assert_eq!(transport.reassemble(source, &fragments[0]), None);
+        assert_eq!(
+            transport.reassemble(source, &fragments[1]).as_deref(),
+            Some(&[0, 1, 2][..])
+        );
+    }
+
+    #[test]
+    fn drops_oversized_payloads() {
+        let mut a = LaminarTransport::bind(("127.0.0.1", 0));
+        let b_addr = LaminarTransport::bind(("127.0.0.1", 0)).local_addr();
+        let too_large = vec![0; MAX_FRAGMENTS * MAX_PACKET_SIZE + 1];
+        assert_eq!(
+            a.fragment(&too_large),
+            Err(PayloadTooLarge(too_large.len()))
+        );
+        a.send(b_addr, Delivery::Ro, too_large.into_boxed_slice());
+        assert_eq!(a.fragment(&[0; MAX_PACKET_SIZE + 1]).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn limits_partial_payloads_per_peer() {
+        let mut transport = LaminarTransport::bind(("127.0.0.1", 0));
+        let peer: SocketAddr = "10.0.0.1:1000".parse().unwrap();
+        let sprayer: SocketAddr = "10.0.0.2:1000".parse().unwrap();
+        let fragment = |id, index| {
+            let mut fragment = fragment_header(id, index, 2).to_vec();
+            fragment.push(index as u8);
+            fragment
+        };
+
+        // Someone starting lots of payloads only pushes out their own.
+        assert_eq!(transport.reassemble(peer, &fragment(0, 0)), None);
+        for id in 0..100 {
+            assert_eq!(transport.reassemble(sprayer, &fragment(id, 0)), None);
+        }
+        assert_eq!(transport.partials.len(), MAX_PARTIAL_PAYLOADS + 1);
+        assert!(transport.reassemble(peer, &fragment(0, 1)).is_some());
+
+        // And payloads that stall are given up on.
+        for partial in &mut transport.partials {
+            partial.started -= PARTIAL_PAYLOAD_LIFETIME;
+        }
+        assert_eq!(transport.reassemble(peer, &fragment(1, 0)), None);
+        assert_eq!(transport.partials.len(), 1);
+    }
+}
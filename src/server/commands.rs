@@ -0,0 +1,232 @@
+use crate::shared::HumanoidAi;
+use std::net::SocketAddr;
+
+pub const COMMAND_HELP: &str = "\
+Commands:
+  help
+  say <text>
+  tp <player> <x> <y>      Teleport to a tile
+  tp <player> <player>     Teleport to another player
+  give <player> <item> [count]
+  time <hour>              Set the time of day (0-24)
+  kick <player> [reason]
+  save
+  spawn <entity> [x y]     Entities: zombie. Spawns next to whoever asked (or the spawn point)
+Names with spaces go in quotes.";
+
+// Who issued a command, and where replies go.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CommandSource {
+    Console,
+    Player(SocketAddr),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TeleportTarget {
+    // In tiles.
+    Tile(u16, u16),
+    Player(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Say {
+        text: String,
+    },
+    Teleport {
+        player: String,
+        target: TeleportTarget,
+    },
+    Give {
+        player: String,
+        item: String,
+        count: u32,
+    },
+    // Fraction of the day, in [0, 1).
+    Time {
+        time: f32,
+    },
+    Kick {
+        player: String,
+        reason: String,
+    },
+    Save,
+    // Position in tiles.
+    Spawn {
+        ai: HumanoidAi,
+        at: Option<(u16, u16)>,
+    },
+}
+
+// Parses a command line, with or without the leading slash. Errors are meant for the issuer.
+pub fn parse_command(line: &str) -> Result<Command, String> {
+    let line = line.trim();
+    let line = line.strip_prefix('/').unwrap_or(line).trim_start();
+    let args = split_args(line)?;
+    let Some((name, args)) = args.split_first() else {
+        return Err(String::from("Empty command, try help."));
+    };
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let usage = |usage: &str| Err(format!("Usage: {usage}"));
+    let coord = |s: &str| s.parse::<u16>().map_err(|_| format!("Bad coordinate {s}."));
+    let command = match (name.as_str(), args.as_slice()) {
+        ("help", []) => Command::Help,
+
+        ("say", []) => return usage("say <text>"),
+        ("say", _) => Command::Say {
+            // Keep the spacing as typed.
+            text: line[3..].trim().to_string(),
+        },
+
+        ("tp", [player, x, y]) => Command::Teleport {
+            player: player.to_string(),
+            target: TeleportTarget::Tile(coord(x)?, coord(y)?),
+        },
+        ("tp", [player, other]) => Command::Teleport {
+            player: player.to_string(),
+            target: TeleportTarget::Player(other.to_string()),
+        },
+        ("tp", _) => return usage("tp <player> <x> <y> | tp <player> <player>"),
+
+        ("give", [player, item]) => Command::Give {
+            player: player.to_string(),
+            item: item.to_string(),
+            count: 1,
+        },
+        ("give", [player, item, count]) => Command::Give {
+            player: player.to_string(),
+            item: item.to_string(),
+            count: match count.parse() {
+                Ok(count) if count > 0 => count,
+                _ => return Err(format!("Bad count {count}.")),
+            },
+        },
+        ("give", _) => return usage("give <player> <item> [count]"),
+
+        ("time", [hour]) => match hour.parse::<f32>() {
+            Ok(hour) if (0. ..=24.).contains(&hour) => Command::Time {
+                time: hour / 24. % 1.,
+            },
+            _ => return Err(format!("Bad hour {hour}.")),
+        },
+        ("time", _) => return usage("time <hour>"),
+
+        ("kick", [player, reason @ ..]) => Command::Kick {
+            player: player.to_string(),
+            reason: match reason.join(" ") {
+                reason if reason.is_empty() => String::from("kicked by an operator"),
+                reason => reason,
+            },
+        },
+        ("kick", _) => return usage("kick <player> [reason]"),
+
+        ("save", []) => Command::Save,
+        ("save", _) => return usage("save"),
+
+        ("spawn", [entity, at @ ..]) => {
+            let ai = match *entity {
+                "zombie" => HumanoidAi::Zombie,
+                _ => return Err(format!("Unknown entity {entity}.")),
+            };
+            let at = match at {
+                [] => None,
+                [x, y] => Some((coord(x)?, coord(y)?)),
+                _ => return usage("spawn <entity> [x y]"),
+            };
+            Command::Spawn { ai, at }
+        }
+        ("spawn", _) => return usage("spawn <entity> [x y]"),
+
+        _ => return Err(format!("Unknown command {name}, try help.")),
+    };
+
+    Ok(command)
+}
+
+// Splits on whitespace, keeping "quoted strings" together.
+fn split_args(line: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let mut arg = String::new();
+        if c == '"' {
+            chars.next();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some(c) => arg.push(c),
+                    None => return Err(String::from("Unclosed quote.")),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                arg.push(c);
+            }
+        }
+        args.push(arg);
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_commands() {
+        assert_eq!(
+            parse_command("/tp \"Big Bob\" 10 20"),
+            Ok(Command::Teleport {
+                player: String::from("Big Bob"),
+                target: TeleportTarget::Tile(10, 20),
+            })
+        );
+        assert_eq!(
+            parse_command("tp bob alice"),
+            Ok(Command::Teleport {
+                player: String::from("bob"),
+                target: TeleportTarget::Player(String::from("alice")),
+            })
+        );
+        assert_eq!(parse_command("time 6"), Ok(Command::Time { time: 0.25 }));
+        assert_eq!(
+            parse_command("kick bob being  rude"),
+            Ok(Command::Kick {
+                player: String::from("bob"),
+                reason: String::from("being rude"),
+            })
+        );
+        assert_eq!(
+            parse_command("/say  hello   there "),
+            Ok(Command::Say {
+                text: String::from("hello   there"),
+            })
+        );
+        assert_eq!(
+            parse_command("spawn zombie"),
+            Ok(Command::Spawn {
+                ai: HumanoidAi::Zombie,
+                at: None,
+            })
+        );
+
+        for bad in [
+            "",
+            "/",
+            "fly",
+            "tp bob",
+            "time 25",
+            "give bob dirt 0",
+            "tp \"bob 1 2",
+        ] {
+            assert!(parse_command(bad).is_err(), "{bad}");
+        }
+    }
+}
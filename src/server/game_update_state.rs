@@ -1,19 +1,183 @@
-use crate::net::{NetEventKind, ServerNetManager};
-use crate::server::log;
+use crate::net::{
+    Delivery, LinkStats, NetEventKind, PingTracker, Priority, SendQueue, ServerNetManager,
+};
+use crate::server::*;
 use crate::shared::*;
-use std::collections::HashMap;
+use crate::time::timestamp_as_usecs;
+use std::collections::{HashMap, VecDeque};
 use std::net::SocketAddr;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::thread::JoinHandle;
 
 pub struct Connection {
     // Whether the client has joined yet.
     joined: bool,
 
-    // Disconnect flag.
+    // Disconnect flag, and whether the connection was lost (rather than closed), in which case
+    // the player's humanoid waits RECONNECT_GRACE for it to come back.
     disconnect: bool,
+    linger: bool,
 
     // The ID this connection owns.
     id: Option<HumanoidId>,
+
+    // Who joined, once joined.
+    token: Option<PlayerToken>,
+    name: String,
+
+    // Received (seq, input) frames not yet simulated, and the seq of the newest one.
+    inputs: VecDeque<(u32, HumanoidInput)>,
+    input_seq: u32,
+
+    // Seq of the last input frame simulated.
+    input_ack: u32,
+
+    // View size (in pixels), centered on the player.
+    view_w: usize,
+    view_h: usize,
+
+    // Chunks (by index) this connection has been sent, and the seq it has them at.
+    known_chunks: HashMap<usize, u32>,
+
+    // Tile the player wants to mine (x, y, layer), and progress on it, see update_mining.
+    mine_target: Option<(usize, usize, TileLayer)>,
+    mining: Option<Mining>,
+
+    // Whether the player's inventory changed since it was last sent.
+    inventory_changed: bool,
+
+    // Outgoing messages, and whether they were being held back by the send budget.
+    send_queue: SendQueue<ServerNetMessage>,
+    deferring: bool,
+
+    // Humanoid snapshots sent but not yet known to be received, and the newest acked seq.
+    snapshot_seq: u32,
+    baselines: VecDeque<(u32, HashMap<HumanoidId, Humanoid>)>,
+    snapshot_ack: u32,
+
+    // Misbehaviour so far, see strike.
+    strikes: u32,
+
+    // Round trip time and clock offset to the client.
+    ping: PingTracker,
+}
+
+impl Connection {
+    // Counts a malformed or impossible message against the connection, kicking it after too many.
+    // Well-behaved clients never get any.
+    fn strike(&mut self, source: SocketAddr, reason: &str) {
+        self.strikes += 1;
+        log!(
+            "WARNING: Strike {}/{MAX_STRIKES} for {source:?}: {reason}.",
+            self.strikes
+        );
+        if self.strikes >= MAX_STRIKES {
+            self.kick(source, "misbehaving");
+        }
+    }
+
+    // Tells the client why, then drops the connection at the end of the tick.
+    fn kick(&mut self, source: SocketAddr, reason: &str) {
+        if self.disconnect {
+            return;
+        }
+        log!("Kicked {source:?} for {reason}.");
+        let reason = reason.to_string();
+        self.send_queue.push(
+            Priority::High,
+            Delivery::Ru,
+            ServerNetMessage::Kick { reason },
+        );
+        self.disconnect = true;
+    }
+}
+
+// Strikes a connection can get before it is kicked.
+const MAX_STRIKES: u32 = 8;
+
+// Time a player whose connection was lost has to rejoin into its old humanoid (in usecs).
+const RECONNECT_GRACE: u64 = 30_000_000;
+
+// Input frames buffered per connection before old ones get dropped.
+const MAX_QUEUED_INPUTS: usize = 16;
+
+// Bytes sent to a connection per tick (about 480KiB/s).
+const SEND_BUDGET: usize = 8 * 1024;
+
+// Unacked snapshots kept per connection. If the ack falls further behind, full snapshots are sent.
+const MAX_BASELINES: usize = 64;
+
+// Area around a player (in pixels) in which humanoids are synced to it. Larger than the view, so
+// humanoids have some history by the time they scroll in.
+const INTEREST_W: f32 = 2. * 1280.;
+const INTEREST_H: f32 = 2. * 720.;
+
+// Most humanoids (the nearest) synced to a player at a time, so a crowd can't blow up the size of
+// a HumanoidSync.
+const MAX_SYNCED_HUMANOIDS: usize = 32;
+
+// Largest view a client can ask for (in pixels).
+const MAX_VIEW_W: usize = 3840;
+const MAX_VIEW_H: usize = 2160;
+
+// Chunks are streamed this many chunks beyond the view, and forgotten once they are further
+// out than the unload margin (so chunks on the edge don't get resent all the time).
+const CHUNK_STREAM_MARGIN: usize = 1;
+const CHUNK_UNLOAD_MARGIN: usize = 3;
+
+// Drops are synced to players this often (in ticks), at most this many (the nearest) at a time.
+const ITEM_DROP_SYNC_INTERVAL: u32 = 6;
+const MAX_SYNCED_ITEM_DROPS: usize = 48;
+
+// Time until a drop nobody picked up disappears (in ticks), and most drops there can be.
+const ITEM_DROP_LIFETIME: u32 = 5 * 60 * 60;
+const MAX_ITEM_DROPS: usize = 4096;
+
+// Players pick up drops this close to them (in pixels).
+const ITEM_PICKUP_RANGE: f32 = 16.;
+
+// A tile edit requested by a player, validated and applied after net events are handled.
+struct TileEdit {
+    source: SocketAddr,
+    id: HumanoidId,
+    x: usize,
+    y: usize,
+    layer: TileLayer,
+    tile: Tile,
+
+    // Inventory slot a placed tile comes out of, None for breaks.
+    slot: Option<usize>,
+}
+
+// A tile a player is mining.
+#[derive(Clone)]
+struct Mining {
+    x: usize,
+    y: usize,
+    layer: TileLayer,
+
+    // What was there when mining started. If it changes, progress is lost.
+    tile: Tile,
+
+    // Time spent on it so far (in seconds), and the crack stage everyone was told about.
+    time: f32,
+    stage: u8,
+}
+
+pub struct WorldConfig {
+    // File the world is loaded from and saved to. None keeps the world in memory only.
+    pub path: Option<PathBuf>,
+
+    // Used when a new world has to be generated.
+    pub seed: u64,
+    pub width: usize,
+    pub height: usize,
+
+    // Time between autosaves (in usecs). 0 disables autosaving.
+    pub autosave_interval: u64,
+
+    // Players allowed to run commands from chat.
+    pub operators: Vec<PlayerToken>,
 }
 
 pub struct GameUpdateState {
@@ -21,100 +185,240 @@ pub struct GameUpdateState {
     net_manager: ServerNetManager,
     connections: HashMap<SocketAddr, Connection>,
 
+    // Persistence.
+    world_path: Option<PathBuf>,
+    autosave_interval: u64,
+    autosave_ts: u64,
+    saving: Option<JoinHandle<()>>,
+
     // Tiles.
+    seed: u64,
     world_w: usize,
     world_h: usize,
     chunk_seqs: Box<[u32]>,
     fg_tiles: Box<[Tile]>,
     bg_tiles: Box<[Tile]>,
+    liquids: Box<[Liquid]>,
+    liquid_sim: LiquidSim,
+    tile_gravity: TileGravity,
+
+    // Spawn point (in tiles).
+    spawn_x: usize,
+    spawn_y: usize,
+
+    // Pending tile edits.
+    tile_edits: Vec<TileEdit>,
+
+    // Dropped items, and when each of them despawns, in spawn order. Drops aren't saved.
+    item_drop_id_counter: u32,
+    item_drops: HashMap<ItemDropId, ItemDrop>,
+    item_drop_expiry: VecDeque<(u32, ItemDropId)>,
+
+    // Time of day, as a fraction of the day.
+    time: f32,
+
+    // Pending chat messages (and commands) from players, and who may run commands.
+    chat: Vec<(SocketAddr, String)>,
+    operators: Vec<PlayerToken>,
+
+    // Ticks simulated since startup, stamped on every state message.
+    tick: u32,
 
     // Players.
     humanoid_id_counter: HumanoidId,
     humanoids: HashMap<HumanoidId, Humanoid>,
-}
 
-impl GameUpdateState {
-    pub fn new(_root: &'static Path, net_manager: ServerNetManager) -> Self {
-        let world_w = 8400;
-        let world_h = 2400;
-        let chunk_seqs = vec![1; world_w * world_h].into_boxed_slice();
-        let mut fg_tiles = vec![Tile::None; world_w * world_h].into_boxed_slice();
-        let mut bg_tiles = vec![Tile::None; world_w * world_h].into_boxed_slice();
-        for y in 0..world_h {
-            for x in 0..world_w {
-                let index = x + y * world_w;
-
-                if y == 0 || y == world_h - 1 || x == 0 || x == world_w - 1 {
-                    fg_tiles[index] = Tile::Dirt;
-                    bg_tiles[index] = Tile::Dirt;
-                    continue;
-                }
+    // Every player that ever joined, and the ones joined right now.
+    players: HashMap<PlayerToken, PlayerRecord>,
+    logged_in: HashMap<PlayerToken, SocketAddr>,
+    roster_changed: bool,
 
-                if y < 102 {
-                    fg_tiles[index] = Tile::None;
-                    bg_tiles[index] = Tile::None;
-                    continue;
-                }
+    // Humanoids of players that lost their connection, and when they get despawned.
+    lingering: HashMap<PlayerToken, (HumanoidId, u64)>,
+}
 
-                if y < 102 + 5 {
-                    fg_tiles[index] = Tile::Dirt;
-                    bg_tiles[index] = Tile::Dirt;
-                    continue;
-                }
+impl GameUpdateState {
+    pub fn new(_root: &'static Path, net_manager: ServerNetManager, config: WorldConfig) -> Self {
+        // Load the world if it exists, otherwise generate a new one.
+        let save = config
+            .path
+            .as_deref()
+            .filter(|path| path.exists())
+            .map(|path| {
+                log!("Loading world from {path:?}.");
+                load_world(path)
+                    .unwrap_or_else(|err| panic!("Could not load world {path:?}: {err}"))
+            });
+        let save = save.unwrap_or_else(|| new_world(&config));
 
-                if y < 102 + 15 {
-                    fg_tiles[index] = Tile::Stone;
-                    bg_tiles[index] = Tile::Stone;
-                    continue;
-                }
+        let WorldSave {
+            header,
+            chunk_seqs,
+            fg_tiles,
+            bg_tiles,
+            liquids,
+            humanoids,
+            players,
+        } = save;
 
-                fg_tiles[index] = Tile::DenseStone;
-                bg_tiles[index] = Tile::DenseStone;
-            }
-        }
-        fg_tiles[108 + 102 * world_w] = Tile::None;
-        fg_tiles[109 + 102 * world_w] = Tile::None;
-        fg_tiles[107 + 102 * world_w] = Tile::None;
-        fg_tiles[106 + 102 * world_w] = Tile::None;
-        fg_tiles[107 + 103 * world_w] = Tile::None;
-        fg_tiles[106 + 103 * world_w] = Tile::None;
-
-        let mut humanoid_id_counter = HumanoidId::new();
-        let mut humanoids = HashMap::new();
-        let spawn_x = 100 * TILE_SIZE;
-        let spawn_y = 100 * TILE_SIZE - 32;
-
-        humanoids.insert(
-            humanoid_id_counter.next(),
-            Humanoid {
-                base: HumanoidBase {
-                    x: spawn_x as f32 + 256.,
-                    y: spawn_y as f32,
-                    w: 32. - 8.,
-                    h: 48. - 8.,
-                    flags: 0,
-                },
-                ai: HumanoidAi::Zombie,
-                input: HumanoidInput::default(),
-                physics: HumanoidPhysics {
-                    max_dx: 50.,
-                    ..Default::default()
-                },
-            },
-        );
+        // Liquids are saved mid-flow.
+        let mut liquid_sim = LiquidSim::new();
+        liquid_sim.activate_all(&liquids);
+        let mut tile_gravity = TileGravity::new();
+        tile_gravity.schedule_all(&fg_tiles);
 
         Self {
             net_manager,
             connections: HashMap::new(),
 
-            world_w,
-            world_h,
+            world_path: config.path,
+            autosave_interval: config.autosave_interval,
+            autosave_ts: timestamp_as_usecs() + config.autosave_interval,
+            saving: None,
+
+            seed: header.seed,
+            world_w: header.width as usize,
+            world_h: header.height as usize,
             chunk_seqs,
             fg_tiles,
             bg_tiles,
+            liquids,
+            liquid_sim,
+            tile_gravity,
+            spawn_x: header.spawn_x as usize,
+            spawn_y: header.spawn_y as usize,
 
-            humanoid_id_counter,
+            tile_edits: Vec::new(),
+
+            item_drop_id_counter: 0,
+            item_drops: HashMap::new(),
+            item_drop_expiry: VecDeque::new(),
+
+            time: 0.5,
+
+            chat: Vec::new(),
+            operators: config.operators,
+
+            tick: 0,
+
+            humanoid_id_counter: header.humanoid_id_counter,
             humanoids,
+
+            players,
+            logged_in: HashMap::new(),
+            roster_changed: false,
+
+            lingering: HashMap::new(),
+        }
+    }
+
+    // Snapshots the world and writes it on a background thread, so the game doesn't stall on
+    // compression and disk. A save still being written is waited for first.
+    pub fn save(&mut self) {
+        self.update_player_records();
+        let Some(path) = self.world_path.clone() else {
+            return;
+        };
+        self.finish_saving();
+
+        let snapshot = WorldSave {
+            header: WorldHeader {
+                width: self.world_w as u32,
+                height: self.world_h as u32,
+                seed: self.seed,
+                spawn_x: self.spawn_x as u32,
+                spawn_y: self.spawn_y as u32,
+                humanoid_id_counter: self.humanoid_id_counter,
+            },
+            chunk_seqs: self.chunk_seqs.clone(),
+            fg_tiles: self.fg_tiles.clone(),
+            bg_tiles: self.bg_tiles.clone(),
+            liquids: self.liquids.clone(),
+            humanoids: self.humanoids.clone(),
+            players: self.players.clone(),
+        };
+        self.saving = Some(std::thread::spawn(move || {
+            let ts = timestamp_as_usecs();
+            let result = save_world(
+                &path,
+                &WorldSaveRef {
+                    header: snapshot.header,
+                    chunk_seqs: &snapshot.chunk_seqs,
+                    fg_tiles: &snapshot.fg_tiles,
+                    bg_tiles: &snapshot.bg_tiles,
+                    liquids: &snapshot.liquids,
+                    humanoids: &snapshot.humanoids,
+                    players: &snapshot.players,
+                },
+            );
+
+            match result {
+                Ok(()) => log!(
+                    "Saved world to {path:?} in {:.2}ms.",
+                    (timestamp_as_usecs() - ts) as f32 * 0.001
+                ),
+                Err(err) => log!("WARNING: Could not save world to {path:?}: {err}."),
+            }
+        }));
+    }
+
+    // Blocks until the save being written (if any) is on disk.
+    pub fn finish_saving(&mut self) {
+        if let Some(saving) = self.saving.take() {
+            saving.join().unwrap();
+        }
+    }
+
+    // Players joined right now.
+    pub fn player_count(&self) -> usize {
+        self.logged_in.len()
+    }
+
+    pub fn tick(&self) -> u32 {
+        self.tick
+    }
+
+    // Link stats of every connection.
+    pub fn link_stats(&self) -> impl Iterator<Item = (SocketAddr, LinkStats)> + '_ {
+        self.connections
+            .iter()
+            .map(|(addr, connection)| (*addr, connection.ping.stats()))
+    }
+
+    // Copies where joined (or lingering) players are into their records.
+    fn update_player_records(&mut self) {
+        let joined = self
+            .connections
+            .values()
+            .filter_map(|connection| Some((connection.token?, connection.id?)));
+        let lingering = self.lingering.iter().map(|(token, (id, _))| (*token, *id));
+        for (token, id) in joined.chain(lingering) {
+            let (Some(record), Some(player)) =
+                (self.players.get_mut(&token), self.humanoids.get(&id))
+            else {
+                continue;
+            };
+            record.x = player.base.x;
+            record.y = player.base.y;
+        }
+    }
+
+    // Removes a player's humanoid for good and tells everyone. Its record must be up to date.
+    fn despawn_player(&mut self, id: HumanoidId, token: PlayerToken) {
+        self.humanoids.remove(&id);
+        let name = self.players[&token].name.clone();
+        log!("{name} left the game.");
+
+        let player = PlayerInfo { id, name };
+        for connection in self.connections.values_mut() {
+            if connection.joined && !connection.disconnect {
+                let msg = ServerNetMessage::PlayerLeft {
+                    player: player.clone(),
+                };
+                connection
+                    .send_queue
+                    .push(Priority::Normal, Delivery::Ro, msg);
+            }
         }
     }
 
@@ -122,10 +426,71 @@ impl GameUpdateState {
         // Poll for event receiving.
         self.net_manager.poll();
         self.handle_net_events(ts);
+
+        // Apply tile edits received this frame.
+        self.apply_tile_edits();
+
+        // Then chat and commands.
+        self.handle_chat();
     }
 
     pub fn step(&mut self, _ts: u64, ft: u64) {
         let ft = ft as f32 / 1e6;
+        self.tick += 1;
+
+        // Liquid flow and falling tiles. Changed chunks get streamed again.
+        let mut changed = self.liquid_sim.step(
+            self.tick,
+            self.world_w,
+            self.world_h,
+            &mut self.fg_tiles,
+            &mut self.liquids,
+        );
+        for &index in &changed {
+            self.tile_gravity.schedule_around(index, self.world_w);
+        }
+        let fallen = self.tile_gravity.step(
+            self.tick,
+            self.world_w,
+            self.world_h,
+            &mut self.fg_tiles,
+            &mut self.liquids,
+        );
+        for &index in &fallen {
+            self.liquid_sim.activate_around(index, self.world_w);
+        }
+        changed.extend(fallen);
+        let mut changed_chunks: Vec<usize> = changed
+            .into_iter()
+            .map(|index| {
+                let (x, y) = (index % self.world_w, index / self.world_w);
+                x / CHUNK_SIZE + y / CHUNK_SIZE * self.world_w / CHUNK_SIZE
+            })
+            .collect();
+        changed_chunks.sort_unstable();
+        changed_chunks.dedup();
+        for chunk_index in changed_chunks {
+            self.chunk_seqs[chunk_index] += 1;
+        }
+
+        // Apply one input frame per player. A player whose next frame hasn't arrived gets no input
+        // this tick, but still falls and collides like everything else. The client's prediction
+        // is corrected by the next HumanoidSync.
+        for connection in self.connections.values_mut() {
+            let Some(id) = connection.id else {
+                continue;
+            };
+            let input = match connection.inputs.pop_front() {
+                Some((seq, input)) => {
+                    connection.input_ack = seq;
+                    input
+                }
+                None => HumanoidInput::default(),
+            };
+            if let Some(humanoid) = self.humanoids.get_mut(&id) {
+                humanoid.input = input;
+            }
+        }
 
         // Humanoid AI pass.
         update_humanoid_ais(&mut self.humanoids, self.world_w, &self.fg_tiles);
@@ -134,19 +499,30 @@ impl GameUpdateState {
         update_humanoid_inputs(&mut self.humanoids);
 
         // Humanoid physics pass.
-        update_humanoid_physics(&mut self.humanoids, ft);
+        update_humanoid_physics(&mut self.humanoids, self.world_w, &self.liquids, ft);
 
         // Humanoid tile collision pass.
         resolve_humanoid_tile_collisions(&mut self.humanoids, self.world_w, &self.fg_tiles);
-    }
 
-    pub fn poststep(&mut self, _ts: u64) {
-        let humanoid_se = serialize(&[ServerNetMessage::HumanoidSync {
-            humanoids: self.humanoids.clone(),
-        }]);
+        // Mining. Tiles mined for long enough break with the next tile edits.
+        self.update_mining(ft);
 
+        // Drops fall, get picked up, and old ones disappear.
+        update_item_drops(&mut self.item_drops, self.world_w, &self.fg_tiles, ft);
+        self.pick_up_item_drops();
+        while self
+            .item_drop_expiry
+            .front()
+            .is_some_and(|(tick, _)| *tick <= self.tick)
+        {
+            let (_, id) = self.item_drop_expiry.pop_front().unwrap();
+            self.item_drops.remove(&id);
+        }
+    }
+
+    pub fn poststep(&mut self, ts: u64) {
         // Da big sink
-        for (destination, connection) in self.connections.iter() {
+        for connection in self.connections.values_mut() {
             if connection.disconnect {
                 //self.net_manager.send_uu()
                 continue;
@@ -155,214 +531,2014 @@ impl GameUpdateState {
             if !connection.joined {
                 continue;
             }
-            self.net_manager.send_uu(destination, humanoid_se.clone());
+
+            // Measure the link.
+            if let Some(seq) = connection.ping.poll(timestamp_as_usecs()) {
+                let msg = ServerNetMessage::Ping { seq };
+                connection
+                    .send_queue
+                    .push(Priority::High, Delivery::Uu, msg);
+            }
+
+            // The whole inventory, whenever anything in it changed.
+            if let Some(token) = connection.token.filter(|_| connection.inventory_changed) {
+                connection.inventory_changed = false;
+                let msg = ServerNetMessage::InventorySync {
+                    inventory: self.players[&token].inventory.clone(),
+                };
+                connection
+                    .send_queue
+                    .push(Priority::Normal, Delivery::Ro, msg);
+            }
+
+            let Some(player) = connection.id.and_then(|id| self.humanoids.get(&id)) else {
+                continue;
+            };
+            let (cx, cy) = (player.base.x, player.base.y);
+
+            // Chunks that came into view or changed.
+            let chunks = stream_chunks(
+                connection,
+                (cx + player.base.w / 2., cy + player.base.h / 2.),
+                (self.world_w, self.world_h),
+                &self.chunk_seqs,
+                &self.fg_tiles,
+                &self.bg_tiles,
+                &self.liquids,
+            );
+            for chunks in pack_chunks(&chunks, MAX_CHUNK_BATCH_SIZE) {
+                let msg = ServerNetMessage::ChunkSync {
+                    tick: self.tick,
+                    chunks,
+                };
+                connection
+                    .send_queue
+                    .push(Priority::Normal, Delivery::Ro, msg);
+            }
+
+            // Humanoids near the player, the player's own first.
+            let rank = |id: HumanoidId, humanoid: &Humanoid| {
+                let own = Some(id) == connection.id;
+                (
+                    !own,
+                    (humanoid.base.x - cx).abs() + (humanoid.base.y - cy).abs(),
+                )
+            };
+            let mut nearby: Vec<(HumanoidId, &Humanoid)> = self
+                .humanoids
+                .iter()
+                .filter(|(_, humanoid)| {
+                    (humanoid.base.x - cx).abs() <= INTEREST_W / 2.
+                        && (humanoid.base.y - cy).abs() <= INTEREST_H / 2.
+                })
+                .map(|(id, humanoid)| (*id, humanoid))
+                .collect();
+            nearby.sort_by(|(a_id, a), (b_id, b)| {
+                let ((a_far, a), (b_far, b)) = (rank(*a_id, a), rank(*b_id, b));
+                a_far.cmp(&b_far).then(a.total_cmp(&b))
+            });
+            nearby.truncate(MAX_SYNCED_HUMANOIDS);
+            let visible: HashMap<HumanoidId, Humanoid> = nearby
+                .into_iter()
+                .map(|(id, humanoid)| (id, humanoid.clone()))
+                .collect();
+
+            // Delta against the newest snapshot the client has, if it's still around.
+            while connection
+                .baselines
+                .front()
+                .is_some_and(|(seq, _)| *seq < connection.snapshot_ack)
+            {
+                connection.baselines.pop_front();
+            }
+            let empty = HashMap::new();
+            let (baseline_seq, baseline) = match connection.baselines.front() {
+                Some((seq, baseline)) if *seq == connection.snapshot_ack => (*seq, baseline),
+                _ => (0, &empty),
+            };
+            let (updated, removed) = diff_humanoids(baseline, &visible);
+
+            connection.snapshot_seq += 1;
+            let msg = ServerNetMessage::HumanoidSync {
+                tick: self.tick,
+                seq: connection.snapshot_seq,
+                baseline: baseline_seq,
+                ack: connection.input_ack,
+                updated,
+                removed,
+            };
+            connection
+                .send_queue
+                .push(Priority::High, Delivery::Uu, msg);
+
+            connection
+                .baselines
+                .push_back((connection.snapshot_seq, visible));
+            if connection.baselines.len() > MAX_BASELINES {
+                connection.baselines.pop_front();
+            }
+
+            // Drops near the player, every so often.
+            if self.tick.is_multiple_of(ITEM_DROP_SYNC_INTERVAL) {
+                let distance = |drop: &ItemDrop| (drop.x - cx).abs() + (drop.y - cy).abs();
+                let mut drops: Vec<(ItemDropId, ItemDrop)> = self
+                    .item_drops
+                    .iter()
+                    .filter(|(_, drop)| {
+                        (drop.x - cx).abs() <= INTEREST_W / 2.
+                            && (drop.y - cy).abs() <= INTEREST_H / 2.
+                    })
+                    .map(|(id, drop)| (*id, drop.clone()))
+                    .collect();
+                drops.sort_by(|(_, a), (_, b)| distance(a).total_cmp(&distance(b)));
+                drops.truncate(MAX_SYNCED_ITEM_DROPS);
+                let msg = ServerNetMessage::ItemDropSync {
+                    tick: self.tick,
+                    drops,
+                };
+                connection
+                    .send_queue
+                    .push(Priority::Normal, Delivery::Uu, msg);
+            }
+        }
+
+        // Tell everyone who is online.
+        if std::mem::take(&mut self.roster_changed) {
+            let mut players: Vec<PlayerInfo> = self
+                .connections
+                .values()
+                .filter(|connection| connection.joined && !connection.disconnect)
+                .filter_map(|connection| {
+                    let id = connection.id?;
+                    let name = connection.name.clone();
+                    Some(PlayerInfo { id, name })
+                })
+                .collect();
+            players.sort_by(|a, b| a.name.cmp(&b.name));
+            for connection in self.connections.values_mut() {
+                if connection.joined && !connection.disconnect {
+                    let msg = ServerNetMessage::Roster {
+                        players: players.clone(),
+                    };
+                    connection
+                        .send_queue
+                        .push(Priority::Normal, Delivery::Ro, msg);
+                }
+            }
         }
 
-        // Clean disconnects.
-        self.connections.retain(|_, con| con.disconnect == false);
+        // Send as much as the budget allows. Closed connections still get their last words (the
+        // kick reason).
+        for (destination, connection) in self.connections.iter_mut() {
+            if connection.disconnect && connection.linger {
+                continue;
+            }
+
+            let report = self
+                .net_manager
+                .flush(*destination, &mut connection.send_queue);
+            if report.deferred > 0 && !connection.deferring {
+                log!(
+                    "{destination:?} is over the send budget, deferring {} messages.",
+                    report.deferred
+                );
+            } else if report.deferred == 0 && connection.deferring {
+                log!("{destination:?} caught up.");
+            }
+            connection.deferring = report.deferred > 0;
+        }
+
+        // Clean disconnects. Players are remembered where they left, and lost ones get some time
+        // to reconnect.
+        self.update_player_records();
+        let disconnected: Vec<_> = self
+            .connections
+            .iter()
+            .filter(|(_, con)| con.disconnect)
+            .map(|(source, con)| (*source, con.id, con.token, con.linger))
+            .collect();
+        for (source, id, token, linger) in disconnected {
+            self.connections.remove(&source);
+            log!("{source:?} has disconnected.");
+            let Some(token) = token else {
+                if let Some(id) = id {
+                    self.humanoids.remove(&id);
+                }
+                continue;
+            };
+            self.logged_in.remove(&token);
+            self.roster_changed = true;
+            let Some(id) = id else {
+                continue;
+            };
+            if linger {
+                log!("{} lost connection.", self.players[&token].name);
+                if let Some(humanoid) = self.humanoids.get_mut(&id) {
+                    humanoid.input = HumanoidInput::default();
+                }
+                self.lingering.insert(token, (id, ts + RECONNECT_GRACE));
+            } else {
+                self.despawn_player(id, token);
+            }
+        }
+
+        // Players that didn't make it back in time.
+        let expired: Vec<_> = self
+            .lingering
+            .iter()
+            .filter(|(_, (_, despawn_ts))| ts >= *despawn_ts)
+            .map(|(token, (id, _))| (*token, *id))
+            .collect();
+        for (token, id) in expired {
+            self.lingering.remove(&token);
+            self.despawn_player(id, token);
+        }
 
         // Poll for event sending.
         self.net_manager.poll();
+
+        // Autosave.
+        if self.world_path.is_some() && self.autosave_interval > 0 && ts >= self.autosave_ts {
+            self.autosave_ts = ts + self.autosave_interval;
+            self.save();
+        }
+    }
+
+    fn handle_chat(&mut self) {
+        for (source, text) in std::mem::take(&mut self.chat) {
+            // Might have left in the same tick.
+            let Some(connection) = self.connections.get(&source) else {
+                continue;
+            };
+            let (Some(token), name) = (connection.token, connection.name.clone()) else {
+                continue;
+            };
+
+            if let Some(line) = text.strip_prefix('/') {
+                if self.operators.contains(&token) {
+                    log!("{name} ran /{line}.");
+                    self.run_command(CommandSource::Player(source), line);
+                } else {
+                    let reply = String::from("You are not allowed to use commands.");
+                    self.reply(CommandSource::Player(source), reply);
+                }
+                continue;
+            }
+
+            log!("<{name}> {text}");
+            self.broadcast(ServerNetMessage::Chat {
+                from: Some(name),
+                text,
+            });
+        }
+    }
+
+    // Queues a message for every joined connection.
+    fn broadcast(&mut self, msg: ServerNetMessage) {
+        for connection in self.connections.values_mut() {
+            if connection.joined && !connection.disconnect {
+                connection
+                    .send_queue
+                    .push(Priority::Normal, Delivery::Ro, msg.clone());
+            }
+        }
+    }
+
+    fn reply(&mut self, source: CommandSource, text: String) {
+        match source {
+            CommandSource::Console => log!("{text}"),
+            CommandSource::Player(source) => {
+                let Some(connection) = self.connections.get_mut(&source) else {
+                    return;
+                };
+                let msg = ServerNetMessage::Chat { from: None, text };
+                connection
+                    .send_queue
+                    .push(Priority::Normal, Delivery::Ro, msg);
+            }
+        }
+    }
+
+    // Runs a console or chat command, and replies to whoever issued it.
+    pub fn run_command(&mut self, source: CommandSource, line: &str) {
+        let reply = match parse_command(line) {
+            Ok(command) => self.execute_command(source, command),
+            Err(err) => err,
+        };
+        if !reply.is_empty() {
+            self.reply(source, reply);
+        }
+    }
+
+    fn execute_command(&mut self, source: CommandSource, command: Command) -> String {
+        match command {
+            Command::Help => COMMAND_HELP.to_string(),
+
+            Command::Say { text } => {
+                log!("<Server> {text}");
+                self.broadcast(ServerNetMessage::Chat { from: None, text });
+                String::new()
+            }
+
+            Command::Teleport { player, target } => {
+                let Some((_, id)) = self.find_player(&player) else {
+                    return format!("No player named {player}.");
+                };
+                let (x, y) = match target {
+                    TeleportTarget::Tile(x, y) => {
+                        let (x, y) = (x as usize, y as usize);
+                        if x == 0 || y == 0 || x >= self.world_w - 1 || y >= self.world_h - 1 {
+                            return format!("{x}, {y} is outside the world.");
+                        }
+                        ((x * TILE_SIZE) as f32, (y * TILE_SIZE) as f32)
+                    }
+                    TeleportTarget::Player(other) => match self.find_player(&other) {
+                        Some((_, other_id)) => {
+                            let base = &self.humanoids[&other_id].base;
+                            (base.x, base.y)
+                        }
+                        None => return format!("No player named {other}."),
+                    },
+                };
+
+                // The client's prediction gets corrected by the next snapshot.
+                let humanoid = self.humanoids.get_mut(&id).unwrap();
+                humanoid.base.x = x;
+                humanoid.base.y = y;
+                humanoid.physics.dx = 0.;
+                humanoid.physics.dy = 0.;
+                format!("Teleported {player}.")
+            }
+
+            Command::Give {
+                player,
+                item,
+                count,
+            } => {
+                let Some((addr, _)) = self.find_player(&player) else {
+                    return format!("No player named {player}.");
+                };
+                let Some(item) = Item::find(&item) else {
+                    return format!("Unknown item {item}.");
+                };
+                let connection = self.connections.get_mut(&addr).unwrap();
+                let token = connection.token.unwrap();
+                let left = self
+                    .players
+                    .get_mut(&token)
+                    .unwrap()
+                    .inventory
+                    .add(item, count);
+                connection.inventory_changed = true;
+                match left {
+                    0 => format!("Gave {player} {count} {}.", item.name()),
+                    _ => format!(
+                        "Gave {player} {} {}, the rest didn't fit.",
+                        count - left,
+                        item.name()
+                    ),
+                }
+            }
+
+            Command::Time { time } => {
+                self.time = time;
+                self.broadcast(ServerNetMessage::Time { time });
+                let minutes = (time * 24. * 60.) as u32;
+                format!("Set the time to {:02}:{:02}.", minutes / 60, minutes % 60)
+            }
+
+            Command::Kick { player, reason } => match self.find_player(&player) {
+                Some((addr, _)) => {
+                    self.connections.get_mut(&addr).unwrap().kick(addr, &reason);
+                    format!("Kicked {player}.")
+                }
+                None => format!("No player named {player}."),
+            },
+
+            Command::Save => match self.world_path {
+                Some(_) => {
+                    self.save();
+                    String::from("Saving the world.")
+                }
+                None => String::from("This world is not saved to a file."),
+            },
+
+            Command::Spawn { ai, at } => {
+                let (x, y) = match (at, source) {
+                    (Some((x, y)), _) => {
+                        let (x, y) = (x as usize, y as usize);
+                        if x == 0 || y == 0 || x >= self.world_w - 1 || y >= self.world_h - 1 {
+                            return format!("{x}, {y} is outside the world.");
+                        }
+                        ((x * TILE_SIZE) as f32, (y * TILE_SIZE) as f32)
+                    }
+                    (None, CommandSource::Player(source)) => {
+                        let Some(player) = self.connections[&source]
+                            .id
+                            .and_then(|id| self.humanoids.get(&id))
+                        else {
+                            return String::from("You have no position, give one.");
+                        };
+                        (player.base.x + 2. * TILE_SIZE as f32, player.base.y)
+                    }
+                    (None, CommandSource::Console) => (
+                        (self.spawn_x * TILE_SIZE) as f32,
+                        (self.spawn_y * TILE_SIZE - 48) as f32,
+                    ),
+                };
+
+                let humanoid = match ai {
+                    HumanoidAi::Zombie => new_zombie(x, y),
+                    HumanoidAi::Player => return String::from("Players can't be spawned."),
+                };
+                let id = self.humanoid_id_counter.next();
+                self.humanoids.insert(id, humanoid);
+                format!(
+                    "Spawned a {ai:?} at {}, {}.",
+                    x as usize / TILE_SIZE,
+                    y as usize / TILE_SIZE
+                )
+            }
+        }
+    }
+
+    // A joined player by name (ignoring case), and its humanoid.
+    fn find_player(&self, name: &str) -> Option<(SocketAddr, HumanoidId)> {
+        self.connections.iter().find_map(|(addr, connection)| {
+            let id = connection.id?;
+            let found = !connection.disconnect && connection.name.eq_ignore_ascii_case(name);
+            found.then_some((*addr, id))
+        })
+    }
+
+    fn apply_tile_edits(&mut self) {
+        let mut msgs = Vec::new();
+        for edit in std::mem::take(&mut self.tile_edits) {
+            if let Err(reason) = self.validate_tile_edit(&edit) {
+                log!(
+                    "WARNING: Rejected tile edit from {:?}: {reason}.",
+                    edit.source
+                );
+                continue;
+            }
+
+            // Placing uses up the item, which may be gone by now (used up, or moved away).
+            if let Some(slot) = edit.slot {
+                if let Err(reason) = self.take_item(edit.source, slot, Item::Tile(edit.tile)) {
+                    log!(
+                        "WARNING: Rejected tile edit from {:?}: {reason}.",
+                        edit.source
+                    );
+                    continue;
+                }
+            }
+
+            // Apply. Broken tiles drop something.
+            let index = edit.x + edit.y * self.world_w;
+            let drops = self.tile_at(edit.x, edit.y, edit.layer).def().drops;
+            if edit.tile == Tile::None && drops != Tile::None {
+                self.spawn_item_drop(ItemDrop::new(Item::Tile(drops), edit.x, edit.y));
+            }
+            match edit.layer {
+                TileLayer::Fg => self.fg_tiles[index] = edit.tile,
+                TileLayer::Bg => self.bg_tiles[index] = edit.tile,
+            }
+
+            // Solid tiles displace liquid, and openings let it flow.
+            if TILE_PHYSICS_PROPERTIES[self.fg_tiles[index].0 as usize].solid {
+                self.liquids[index] = Liquid::NONE;
+            }
+            self.liquid_sim.activate_around(index, self.world_w);
+            self.tile_gravity.schedule_around(index, self.world_w);
+
+            // Bump the chunk seq.
+            let chunk_index = edit.x / CHUNK_SIZE + edit.y / CHUNK_SIZE * self.world_w / CHUNK_SIZE;
+            self.chunk_seqs[chunk_index] += 1;
+
+            msgs.push((
+                chunk_index,
+                ServerNetMessage::TileSync {
+                    tick: self.tick,
+                    x: edit.x as u16,
+                    y: edit.y as u16,
+                    seq: self.chunk_seqs[chunk_index],
+                    fg_tile: self.fg_tiles[index],
+                    bg_tile: self.bg_tiles[index],
+                    liquid: self.liquids[index],
+                },
+            ));
+        }
+
+        if msgs.is_empty() {
+            return;
+        }
+
+        // Send to everyone that has the chunk, including the editor.
+        for connection in self.connections.values_mut() {
+            if connection.disconnect || !connection.joined {
+                continue;
+            }
+
+            for (chunk_index, msg) in &msgs {
+                if let Some(known) = connection.known_chunks.get_mut(chunk_index) {
+                    *known = self.chunk_seqs[*chunk_index];
+                    let msg = msg.clone();
+                    connection
+                        .send_queue
+                        .push(Priority::Normal, Delivery::Ro, msg);
+                }
+            }
+        }
+    }
+
+    // Takes one item out of a player's inventory slot, if it's still there.
+    fn take_item(
+        &mut self,
+        source: SocketAddr,
+        slot: usize,
+        item: Item,
+    ) -> Result<(), &'static str> {
+        let Some(connection) = self.connections.get_mut(&source) else {
+            return Err("player left");
+        };
+        let Some(token) = connection.token else {
+            return Err("player left");
+        };
+        let inventory = &mut self.players.get_mut(&token).unwrap().inventory;
+        if inventory.slots[slot].map(|stack| stack.item) != Some(item) {
+            return Err("item is gone");
+        }
+        inventory.take_one(slot);
+        connection.inventory_changed = true;
+        Ok(())
+    }
+
+    fn tile_at(&self, x: usize, y: usize, layer: TileLayer) -> Tile {
+        let index = x + y * self.world_w;
+        match layer {
+            TileLayer::Fg => self.fg_tiles[index],
+            TileLayer::Bg => self.bg_tiles[index],
+        }
+    }
+
+    // Advances every player's mining by ft seconds. A tile mined for as long as its hardness
+    // breaks, and everyone that has its chunk is told how cracked it is on the way.
+    fn update_mining(&mut self, ft: f32) {
+        let mut cracks = Vec::new();
+        let sources: Vec<SocketAddr> = self.connections.keys().copied().collect();
+        for source in sources {
+            let connection = &self.connections[&source];
+            let Some(id) = connection.id else {
+                continue;
+            };
+            let break_edit = |(x, y, layer)| TileEdit {
+                source,
+                id,
+                x,
+                y,
+                layer,
+                tile: Tile::None,
+                slot: None,
+            };
+
+            // Progress is lost when switching tiles, or when the tile can't be mined (anymore).
+            // Whatever comes next under the cursor gets mined from scratch.
+            let target = connection
+                .mine_target
+                .filter(|&target| self.validate_tile_edit(&break_edit(target)).is_ok());
+            let mut mining = connection.mining.clone();
+            let lost = mining.take_if(|mining| {
+                Some((mining.x, mining.y, mining.layer)) != target
+                    || self.tile_at(mining.x, mining.y, mining.layer) != mining.tile
+            });
+            if let Some(lost) = lost.filter(|lost| lost.stage > 0) {
+                cracks.push((lost.x, lost.y, lost.layer, 0));
+            }
+            let mining = mining.or_else(|| {
+                let (x, y, layer) = target?;
+                Some(Mining {
+                    x,
+                    y,
+                    layer,
+                    tile: self.tile_at(x, y, layer),
+                    time: 0.,
+                    stage: 0,
+                })
+            });
+
+            let mining = mining.and_then(|mut mining| {
+                mining.time += ft;
+                let hardness = mining.tile.def().hardness;
+                if mining.time >= hardness {
+                    // The TileSync clears the cracks.
+                    let edit = break_edit((mining.x, mining.y, mining.layer));
+                    self.tile_edits.push(edit);
+                    return None;
+                }
+                let stage = (mining.time / hardness * (CRACK_STAGES + 1) as f32) as u8;
+                let stage = stage.min(CRACK_STAGES);
+                if stage != mining.stage {
+                    mining.stage = stage;
+                    cracks.push((mining.x, mining.y, mining.layer, stage));
+                }
+                Some(mining)
+            });
+            self.connections.get_mut(&source).unwrap().mining = mining;
+        }
+
+        // Send to everyone that has the chunk.
+        for connection in self.connections.values_mut() {
+            if connection.disconnect || !connection.joined {
+                continue;
+            }
+            for &(x, y, layer, stage) in &cracks {
+                let chunk_index = x / CHUNK_SIZE + y / CHUNK_SIZE * self.world_w / CHUNK_SIZE;
+                if !connection.known_chunks.contains_key(&chunk_index) {
+                    continue;
+                }
+                let msg = ServerNetMessage::TileCrack {
+                    tick: self.tick,
+                    x: x as u16,
+                    y: y as u16,
+                    layer,
+                    stage,
+                };
+                connection
+                    .send_queue
+                    .push(Priority::Normal, Delivery::Ro, msg);
+            }
+        }
+    }
+
+    // Players pick up the drops within ITEM_PICKUP_RANGE of them, as long as there's room for
+    // them. The oldest go first.
+    fn pick_up_item_drops(&mut self) {
+        for connection in self.connections.values_mut() {
+            let (Some(id), Some(token)) = (connection.id, connection.token) else {
+                continue;
+            };
+            let Some(player) = self.humanoids.get(&id) else {
+                continue;
+            };
+            let base = &player.base;
+            let (x1, y1) = (base.x - ITEM_PICKUP_RANGE, base.y - ITEM_PICKUP_RANGE);
+            let (x2, y2) = (
+                base.x + base.w + ITEM_PICKUP_RANGE,
+                base.y + base.h + ITEM_PICKUP_RANGE,
+            );
+            let mut nearby: Vec<ItemDropId> = self
+                .item_drops
+                .iter()
+                .filter(|(_, drop)| {
+                    drop.x < x2
+                        && drop.x + ITEM_DROP_SIZE > x1
+                        && drop.y < y2
+                        && drop.y + ITEM_DROP_SIZE > y1
+                })
+                .map(|(id, _)| *id)
+                .collect();
+            nearby.sort_unstable_by_key(|id| id.0);
+
+            let inventory = &mut self.players.get_mut(&token).unwrap().inventory;
+            for id in nearby {
+                let item = self.item_drops[&id].item;
+                if inventory.has_room_for(item) {
+                    inventory.add(item, 1);
+                    self.item_drops.remove(&id);
+                    connection.inventory_changed = true;
+                }
+            }
+        }
+    }
+
+    // Once there are MAX_ITEM_DROPS, nothing more drops until some despawn.
+    fn spawn_item_drop(&mut self, drop: ItemDrop) {
+        if self.item_drops.len() >= MAX_ITEM_DROPS {
+            return;
+        }
+        let id = ItemDropId(self.item_drop_id_counter);
+        self.item_drop_id_counter = self.item_drop_id_counter.wrapping_add(1);
+        self.item_drops.insert(id, drop);
+        self.item_drop_expiry
+            .push_back((self.tick + ITEM_DROP_LIFETIME, id));
+    }
+
+    fn validate_tile_edit(&self, edit: &TileEdit) -> Result<(), &'static str> {
+        let (x, y) = (edit.x, edit.y);
+
+        // The world border can't be edited.
+        if x == 0 || y == 0 || x >= self.world_w - 1 || y >= self.world_h - 1 {
+            return Err("out of bounds");
+        }
+
+        // Must be within reach of the player.
+        let Some(player) = self.humanoids.get(&edit.id) else {
+            return Err("player does not exist");
+        };
+        let dx = (x * TILE_SIZE + TILE_SIZE / 2) as f32 - (player.base.x + player.base.w / 2.);
+        let dy = (y * TILE_SIZE + TILE_SIZE / 2) as f32 - (player.base.y + player.base.h / 2.);
+        if dx * dx + dy * dy > TILE_REACH * TILE_REACH {
+            return Err("out of reach");
+        }
+
+        let current = self.tile_at(x, y, edit.layer);
+
+        // Break.
+        if edit.tile == Tile::None {
+            if current == Tile::None {
+                return Err("nothing to break");
+            }
+            return Ok(());
+        }
+
+        // Place.
+        if !edit.tile.is_registered() {
+            return Err("invalid tile");
+        }
+        if current != Tile::None {
+            return Err("tile is occupied");
+        }
+        if edit.layer == TileLayer::Fg && TILE_PHYSICS_PROPERTIES[edit.tile.0 as usize].solid {
+            let (x1, y1) = ((x * TILE_SIZE) as f32, (y * TILE_SIZE) as f32);
+            let (x2, y2) = (x1 + TILE_SIZE as f32, y1 + TILE_SIZE as f32);
+            let blocked = self.humanoids.values().any(|humanoid| {
+                let base = &humanoid.base;
+                base.x < x2 && base.x + base.w > x1 && base.y < y2 && base.y + base.h > y1
+            });
+            if blocked {
+                return Err("blocked by a humanoid");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_handshake(&mut self, source: SocketAddr, handshake: Handshake) {
+        let Handshake::Connect { version } = handshake else {
+            log!("WARNING: Unexpected handshake from {source:?}: {handshake:?}.");
+            return;
+        };
+
+        // Ignore if already in game.
+        if self.connections.get(&source).is_some_and(|con| con.joined) {
+            log!("WARNING: {source:?} already connected.");
+            return;
+        }
+
+        // Reject mismatched builds.
+        if version != PROTOCOL_VERSION {
+            let reason = format!(
+                "protocol version mismatch (server {PROTOCOL_VERSION:08x}, client {version:08x})"
+            );
+            log!("Rejected {source:?}: {reason}.");
+            let reject = Handshake::Reject {
+                version: PROTOCOL_VERSION,
+                reason,
+            };
+            self.net_manager
+                .send_ru(source, serialize_handshake(&reject));
+            return;
+        }
+
+        // Accept. The client may resend Connect if it missed the accept, so answer it again.
+        self.connections.entry(source).or_insert(Connection {
+            joined: false,
+            disconnect: false,
+            linger: false,
+            id: None,
+            token: None,
+            name: String::new(),
+            inputs: VecDeque::new(),
+            input_seq: 0,
+            input_ack: 0,
+            send_queue: SendQueue::new(SEND_BUDGET),
+            deferring: false,
+            view_w: 0,
+            view_h: 0,
+            known_chunks: HashMap::new(),
+            mine_target: None,
+            mining: None,
+            inventory_changed: false,
+            snapshot_seq: 0,
+            baselines: VecDeque::new(),
+            snapshot_ack: 0,
+            strikes: 0,
+            ping: PingTracker::new(),
+        });
+        self.net_manager
+            .send_ru(source, serialize_handshake(&Handshake::Accept));
+
+        log!("{source:?} has connected.");
     }
 
     fn handle_net_events(&mut self, _ts: u64) {
-        for e in self.net_manager.recv() {
+        let events: Vec<_> = self.net_manager.recv().collect();
+        for e in events {
             let source = e.source;
             let bytes = match e.kind {
-                NetEventKind::Data(bytes) => bytes,
-                NetEventKind::Connect => {
-                    self.connections.insert(
-                        source,
-                        Connection {
-                            joined: false,
-                            disconnect: false,
-                            id: None,
-                        },
-                    );
+                NetEventKind::Data { bytes, .. } => bytes,
+                // Connections are created by the handshake instead.
+                NetEventKind::Connect => continue,
+                NetEventKind::Disconnect => {
+                    if let Some(connection) = self.connections.get_mut(&source) {
+                        if !connection.disconnect {
+                            connection.disconnect = true;
+                            connection.linger = true;
+                        }
+                    }
                     continue;
                 }
-                NetEventKind::Disconnect => {
-                    self.connections
-                        .get_mut(&source)
-                        .map(|con| con.disconnect = true);
+            };
+
+            // Doesn't require a connection.
+            let msgs = match deserialize_handshake(&bytes) {
+                Ok(handshake) => {
+                    self.handle_handshake(source, handshake);
                     continue;
                 }
+                // Not a handshake, so it should be messages.
+                Err(DecodeErr::WrongTag(_)) => deserialize(&bytes),
+                Err(err) => Err(err),
             };
 
-            let msgs = deserialize(bytes).into_vec().into_iter();
-            msgs.for_each(|msg| match msg {
-                // Doesn't require a connection.
-                ClientNetMessage::Connect { .. } => {
-                    // Ignore.
-                    if self.connections.contains_key(&source) {
-                        log!("WARNING: {source:?} already connected: {msg:?}.");
-                        return;
+            let msgs = match msgs {
+                Ok(msgs) => msgs,
+                Err(err) => {
+                    match self.connections.get_mut(&source) {
+                        Some(connection) => connection.strike(source, &err.to_string()),
+                        None => log!("WARNING: Bad packet from {source:?}: {err}."),
                     }
+                    continue;
+                }
+            };
+            msgs.into_vec().into_iter().for_each(|msg| {
+                // All further events require a connection.
+                let Some(connection) = self.connections.get_mut(&source) else {
+                    log!("WARNING: {source:?} is not connected: {msg:?}");
+                    return;
+                };
 
-                    // Accept all connections.
-                    self.net_manager
-                        .send_ru(source, serialize(&[ServerNetMessage::ConnectAccept]));
-
-                    log!("{source:?} has connected.");
+                if connection.disconnect {
+                    return;
                 }
 
-                // All further events require a connection.
-                msg => {
-                    let Some(connection) = self.connections.get_mut(&source) else {
-                        log!("WARNING: {source:?} is not connected: {msg:?}");
-                        return;
-                    };
+                match msg {
+                    ClientNetMessage::Join {
+                        name,
+                        token,
+                        view_w,
+                        view_h,
+                    } => {
+                        if connection.joined {
+                            connection.strike(source, "joined twice");
+                            return;
+                        }
 
-                    if connection.disconnect {
-                        return;
-                    }
+                        let reject = |connection: &mut Connection, reason: &str| {
+                            log!("Rejected join from {source:?}: {reason}.");
+                            let reason = reason.to_string();
+                            let msg = ServerNetMessage::JoinReject { reason };
+                            connection
+                                .send_queue
+                                .push(Priority::Normal, Delivery::Ro, msg);
+                        };
+                        // The client checks names itself, so a bad one is a strike too.
+                        if !player_name_is_valid(&name) {
+                            reject(connection, "invalid name");
+                            connection.strike(source, "sent an invalid name");
+                            return;
+                        }
+                        if self.logged_in.contains_key(&token) {
+                            reject(connection, "already logged in");
+                            return;
+                        }
+                        // Names pick players out in commands, so no two may share one.
+                        let name_taken = self
+                            .logged_in
+                            .keys()
+                            .any(|token| self.players[token].name.eq_ignore_ascii_case(&name));
+                        if name_taken {
+                            reject(connection, "name taken");
+                            return;
+                        }
 
-                    match msg {
-                        ClientNetMessage::RequestChunk { x, y, seq } => {
-                            let cx = x as usize;
-                            let cy = y as usize;
-                            let cur_seq = &mut self.chunk_seqs[cx + cy * self.world_w / CHUNK_SIZE];
+                        let mut msgs = Vec::new();
 
-                            // If the seq is the same, skip.
-                            assert!(seq <= *cur_seq);
-                            if seq == *cur_seq {
-                                return;
-                            }
+                        // Continue where the player left off, or spawn on the surface, just
+                        // above the ground.
+                        let record = self.players.entry(token).or_insert_with(|| {
+                            PlayerRecord::new(
+                                name.clone(),
+                                (self.spawn_x * TILE_SIZE) as f32,
+                                (self.spawn_y * TILE_SIZE - 48) as f32,
+                            )
+                        });
+                        record.name = name.clone();
+                        let (mut spawn_x, mut spawn_y) = (record.x, record.y);
+                        let inventory = record.inventory.clone();
 
-                            // Clone the chunk.
-                            let mut fg_tiles = [Tile::None; CHUNK_AREA];
-                            let mut bg_tiles = [Tile::None; CHUNK_AREA];
-                            for y in 0..CHUNK_SIZE {
-                                for x in 0..CHUNK_SIZE {
-                                    let src_index =
-                                        x + cx * CHUNK_SIZE + (y + cy * CHUNK_SIZE) * self.world_w;
-                                    let dst_index = x + y * CHUNK_SIZE;
-                                    fg_tiles[dst_index] = self.fg_tiles[src_index];
-                                    bg_tiles[dst_index] = self.bg_tiles[src_index];
-                                }
+                        // Back within the grace period, take over the old humanoid.
+                        let lingering = self
+                            .lingering
+                            .remove(&token)
+                            .map(|(id, _)| id)
+                            .filter(|id| self.humanoids.contains_key(id));
+                        let id = match lingering {
+                            Some(id) => {
+                                let player = &self.humanoids[&id];
+                                (spawn_x, spawn_y) = (player.base.x, player.base.y);
+                                id
                             }
+                            None => self.humanoid_id_counter.next(),
+                        };
+                        connection.id = Some(id);
+                        connection.token = Some(token);
+                        connection.name = name;
+                        connection.view_w = (view_w as usize).min(MAX_VIEW_W);
+                        connection.view_h = (view_h as usize).min(MAX_VIEW_H);
+                        self.logged_in.insert(token, source);
+                        self.roster_changed = true;
+                        log!("{} joined the game.", connection.name);
 
-                            // Send.
-                            self.net_manager.send_ru(
-                                source,
-                                serialize(&[ServerNetMessage::ChunkSync {
-                                    x,
-                                    y,
-                                    seq: *cur_seq,
-                                    fg_tiles,
-                                    bg_tiles,
-                                }]),
-                            );
-                        }
+                        self.humanoids.entry(id).or_insert_with(|| Humanoid {
+                            base: HumanoidBase {
+                                x: spawn_x,
+                                y: spawn_y,
+                                w: 32. - 8.,
+                                h: 48. - 8.,
+                                flags: HUMANOID_ON_GROUND_BIT,
+                            },
+                            ai: HumanoidAi::Player,
+                            input: HumanoidInput::default(),
+                            physics: HumanoidPhysics {
+                                max_dx: 120.,
+                                ..Default::default()
+                            },
+                        });
 
-                        ClientNetMessage::Join => {
-                            let mut msgs = Vec::new();
+                        msgs.push(ServerNetMessage::JoinAccept {
+                            tiles: TILE_REGISTRY.hash(),
+                            width: self.world_w as u16,
+                            height: self.world_h as u16,
+                            id,
+                            spawn_x: self.spawn_x as u16,
+                            spawn_y: self.spawn_y as u16,
+                        });
 
-                            // Arbitrary spawn point.
-                            let spawn_x = 100 * TILE_SIZE;
-                            let spawn_y = 100 * TILE_SIZE;
-                            let viewport_w = 1920;
-                            let viewport_h = 1080;
+                        // Send the chunks around the spawn.
+                        let chunks = stream_chunks(
+                            connection,
+                            (spawn_x, spawn_y),
+                            (self.world_w, self.world_h),
+                            &self.chunk_seqs,
+                            &self.fg_tiles,
+                            &self.bg_tiles,
+                            &self.liquids,
+                        );
+                        msgs.extend(pack_chunks(&chunks, MAX_CHUNK_BATCH_SIZE).into_iter().map(
+                            |chunks| ServerNetMessage::ChunkSync {
+                                tick: self.tick,
+                                chunks,
+                            },
+                        ));
 
-                            let id = self.humanoid_id_counter.next();
-                            connection.id = Some(id);
+                        // Send end.
+                        msgs.push(ServerNetMessage::InventorySync { inventory });
+                        msgs.push(ServerNetMessage::Time { time: self.time });
+                        msgs.push(ServerNetMessage::Start);
 
-                            self.humanoids.insert(
-                                id,
-                                Humanoid {
-                                    base: HumanoidBase {
-                                        x: spawn_x as f32,
-                                        y: spawn_y as f32,
-                                        w: 32. - 8.,
-                                        h: 48. - 8.,
-                                        flags: HUMANOID_ON_GROUND_BIT,
-                                    },
-                                    ai: HumanoidAi::Player,
-                                    input: HumanoidInput::default(),
-                                    physics: HumanoidPhysics {
-                                        max_dx: 120.,
-                                        ..Default::default()
-                                    },
-                                },
-                            );
-
-                            msgs.push(ServerNetMessage::JoinAccept {
-                                width: self.world_w as u16,
-                                height: self.world_h as u16,
-                                id,
-                                spawn_x: 100,
-                                spawn_y: 100,
-                            });
-
-                            // Calculate load area.
-                            const TILE_CHUNK_SIZE: usize = TILE_SIZE * CHUNK_SIZE;
-                            let x1 = (spawn_x - viewport_w / 2) / TILE_CHUNK_SIZE;
-                            let x2 =
-                                (spawn_x + viewport_w / 2 + TILE_CHUNK_SIZE - 1) / TILE_CHUNK_SIZE;
-                            let y1 = (spawn_y - viewport_h / 2) / TILE_CHUNK_SIZE;
-                            let y2 =
-                                (spawn_y + viewport_h / 2 + TILE_CHUNK_SIZE - 1) / TILE_CHUNK_SIZE;
-
-                            // Send chunk data.
-                            for cy in y1..y2 {
-                                for cx in x1..x2 {
-                                    let mut fg_tiles = [Tile::None; CHUNK_AREA];
-                                    let mut bg_tiles = [Tile::None; CHUNK_AREA];
-                                    for y in 0..CHUNK_SIZE {
-                                        for x in 0..CHUNK_SIZE {
-                                            let src_index = x
-                                                + cx * CHUNK_SIZE
-                                                + (y + cy * CHUNK_SIZE) * self.world_w;
-                                            let dst_index = x + y * CHUNK_SIZE;
-                                            fg_tiles[dst_index] = self.fg_tiles[src_index];
-                                            bg_tiles[dst_index] = self.bg_tiles[src_index];
-                                        }
-                                    }
-
-                                    msgs.push(ServerNetMessage::ChunkSync {
-                                        x: cx as u16,
-                                        y: cy as u16,
-                                        seq: 1,
-                                        fg_tiles,
-                                        bg_tiles,
-                                    });
-                                }
+                        // Ordered, like every chunk update after it.
+                        log!("Initial sync of {} chunks.", chunks.len());
+                        for msg in msgs {
+                            connection
+                                .send_queue
+                                .push(Priority::Normal, Delivery::Ro, msg);
+                        }
+                        connection.joined = true;
+                    }
+
+                    ClientNetMessage::JoinComplete => {
+                        if !connection.joined {
+                            connection.strike(source, "completed join before joining");
+                        }
+                    }
+
+                    ClientNetMessage::PlayerInput {
+                        seq,
+                        inputs,
+                        snapshot_ack,
+                    } => {
+                        if connection.id.is_none() {
+                            connection.strike(source, "sent input before joining");
+                            return;
+                        };
+                        if inputs.len() > INPUT_REDUNDANCY {
+                            connection.strike(source, "sent too many input frames");
+                            return;
+                        }
+
+                        // Queue the frames that haven't been seen yet (inputs are resent).
+                        let first_seq = seq.saturating_add(1).saturating_sub(inputs.len() as u32);
+                        for (input_seq, input) in (first_seq..=seq).zip(inputs) {
+                            if input_seq <= connection.input_seq {
+                                continue;
                             }
+                            connection.inputs.push_back((input_seq, input));
+                            connection.input_seq = input_seq;
+                        }
 
-                            // Send end.
-                            msgs.push(ServerNetMessage::Start);
+                        // Drop the oldest frames if the client is too far ahead. The client
+                        // corrects itself when the acks skip over them.
+                        while connection.inputs.len() > MAX_QUEUED_INPUTS {
+                            connection.inputs.pop_front();
+                        }
 
-                            let se = serialize(&msgs);
-                            log!("Initial sync with size {}.", se.len());
+                        // Acks can arrive out of order, but can't be for snapshots not sent yet.
+                        if snapshot_ack > connection.snapshot_seq {
+                            connection.strike(source, "acked an unsent snapshot");
+                            return;
+                        }
+                        connection.snapshot_ack = connection.snapshot_ack.max(snapshot_ack);
+                    }
 
-                            self.net_manager.send_ru(source, se);
-                            connection.joined = true;
+                    ClientNetMessage::MineTile { x, y, layer } => {
+                        if connection.id.is_none() {
+                            connection.strike(source, "mined a tile before joining");
+                            return;
+                        }
+                        if x as usize >= self.world_w || y as usize >= self.world_h {
+                            connection.strike(source, "mined a tile outside the world");
+                            return;
                         }
 
-                        ClientNetMessage::JoinComplete => {
-                            self.net_manager
-                                .send_ru(source, serialize(&[ServerNetMessage::Ping]));
+                        connection.mine_target = Some((x as usize, y as usize, layer));
+                    }
+
+                    ClientNetMessage::StopMining => {
+                        connection.mine_target = None;
+                    }
+
+                    ClientNetMessage::UseItem { slot, x, y, layer } => {
+                        let (Some(id), Some(token)) = (connection.id, connection.token) else {
+                            connection.strike(source, "used an item before joining");
+                            return;
+                        };
+                        if slot as usize >= INVENTORY_SIZE {
+                            connection.strike(source, "used an item outside the inventory");
+                            return;
+                        }
+                        if x as usize >= self.world_w || y as usize >= self.world_h {
+                            connection.strike(source, "used an item outside the world");
+                            return;
                         }
 
-                        ClientNetMessage::SyncPlayer { player } => {
-                            let Some(id) = connection.id else {
-                                log!("{source:?} does not exist in this world!");
-                                return;
-                            };
+                        // The client may not know yet that the slot ran out.
+                        let slot = slot as usize;
+                        let Some(stack) = self.players[&token].inventory.slots[slot] else {
+                            return;
+                        };
+                        match stack.item {
+                            Item::Tile(tile) => self.tile_edits.push(TileEdit {
+                                source,
+                                id,
+                                x: x as usize,
+                                y: y as usize,
+                                layer,
+                                tile,
+                                slot: Some(slot),
+                            }),
+                        }
+                    }
 
-                            if let Some(humanoid) = self.humanoids.get_mut(&id) {
-                                *humanoid = player;
-                            }
+                    ClientNetMessage::MoveItem { from, to } => {
+                        let Some(token) = connection.token else {
+                            connection.strike(source, "moved an item before joining");
+                            return;
+                        };
+                        if from as usize >= INVENTORY_SIZE || to as usize >= INVENTORY_SIZE {
+                            connection.strike(source, "moved an item outside the inventory");
+                            return;
                         }
 
-                        ClientNetMessage::Ping => self
-                            .net_manager
-                            .send_ru(source, serialize(&[ServerNetMessage::Ping])),
+                        let inventory = &mut self.players.get_mut(&token).unwrap().inventory;
+                        inventory.move_stack(from as usize, to as usize);
+                        connection.inventory_changed = true;
+                    }
+
+                    ClientNetMessage::Ping { seq } => {
+                        let msg = ServerNetMessage::Pong {
+                            seq,
+                            ts: timestamp_as_usecs(),
+                            tick: self.tick,
+                        };
+                        connection
+                            .send_queue
+                            .push(Priority::High, Delivery::Uu, msg);
+                    }
+
+                    ClientNetMessage::Pong { seq, ts } => {
+                        connection.ping.pong(timestamp_as_usecs(), seq, ts);
+                    }
 
-                        _ => log!("Unhandled net event from {source:?}: {msg:?}"),
+                    ClientNetMessage::Leave => {
+                        connection.disconnect = true;
                     }
+
+                    ClientNetMessage::Chat { text } => {
+                        if connection.token.is_none() {
+                            connection.strike(source, "chatted before joining");
+                            return;
+                        }
+                        if text.trim().is_empty() || text.chars().count() > MAX_CHAT_LEN {
+                            connection.strike(source, "sent an invalid chat message");
+                            return;
+                        }
+
+                        self.chat.push((source, text));
+                    }
+                }
+            });
+        }
+    }
+}
+
+// Range of chunks [x1, x2) x [y1, y2) covering a view centered on (x, y) (in pixels), plus a
+// margin (in chunks).
+fn chunk_range(
+    (x, y): (f32, f32),
+    view_w: usize,
+    view_h: usize,
+    margin: usize,
+    world_w: usize,
+    world_h: usize,
+) -> (usize, usize, usize, usize) {
+    const TILE_CHUNK_SIZE: usize = TILE_SIZE * CHUNK_SIZE;
+    let (x, y) = (x.max(0.) as usize, y.max(0.) as usize);
+    let x1 = x.saturating_sub(view_w / 2) / TILE_CHUNK_SIZE;
+    let y1 = y.saturating_sub(view_h / 2) / TILE_CHUNK_SIZE;
+    let x2 = (x + view_w / 2).div_ceil(TILE_CHUNK_SIZE);
+    let y2 = (y + view_h / 2).div_ceil(TILE_CHUNK_SIZE);
+    (
+        x1.saturating_sub(margin),
+        y1.saturating_sub(margin),
+        (x2 + margin).min(world_w / CHUNK_SIZE),
+        (y2 + margin).min(world_h / CHUNK_SIZE),
+    )
+}
+
+// Syncs the chunks in view of a connection it doesn't have yet (or has an old seq of), and
+// forgets the ones that went far enough out of view.
+fn stream_chunks(
+    connection: &mut Connection,
+    center: (f32, f32),
+    (world_w, world_h): (usize, usize),
+    chunk_seqs: &[u32],
+    fg_tiles: &[Tile],
+    bg_tiles: &[Tile],
+    liquids: &[Liquid],
+) -> Vec<ChunkData> {
+    let (view_w, view_h) = (connection.view_w, connection.view_h);
+    let chunks_w = world_w / CHUNK_SIZE;
+
+    // Unload.
+    let (x1, y1, x2, y2) = chunk_range(
+        center,
+        view_w,
+        view_h,
+        CHUNK_UNLOAD_MARGIN,
+        world_w,
+        world_h,
+    );
+    connection.known_chunks.retain(|index, _| {
+        let (cx, cy) = (index % chunks_w, index / chunks_w);
+        cx >= x1 && cx < x2 && cy >= y1 && cy < y2
+    });
+
+    // Load.
+    let (x1, y1, x2, y2) = chunk_range(
+        center,
+        view_w,
+        view_h,
+        CHUNK_STREAM_MARGIN,
+        world_w,
+        world_h,
+    );
+    let mut chunks = Vec::new();
+    for cy in y1..y2 {
+        for cx in x1..x2 {
+            let index = cx + cy * chunks_w;
+            let seq = chunk_seqs[index];
+            if connection.known_chunks.get(&index) == Some(&seq) {
+                continue;
+            }
+            connection.known_chunks.insert(index, seq);
+
+            // Clone the chunk.
+            let mut chunk_fg_tiles = [Tile::None; CHUNK_AREA];
+            let mut chunk_bg_tiles = [Tile::None; CHUNK_AREA];
+            let mut chunk_liquids = [Liquid::NONE; CHUNK_AREA];
+            for y in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let src_index = x + cx * CHUNK_SIZE + (y + cy * CHUNK_SIZE) * world_w;
+                    let dst_index = x + y * CHUNK_SIZE;
+                    chunk_fg_tiles[dst_index] = fg_tiles[src_index];
+                    chunk_bg_tiles[dst_index] = bg_tiles[src_index];
+                    chunk_liquids[dst_index] = liquids[src_index];
                 }
+            }
+
+            chunks.push(ChunkData {
+                x: cx as u16,
+                y: cy as u16,
+                seq,
+                runs: encode_chunk_tiles(&chunk_fg_tiles, &chunk_bg_tiles),
+                liquids: encode_chunk_liquids(&chunk_liquids),
             });
         }
     }
+    chunks
+}
+
+fn new_world(config: &WorldConfig) -> WorldSave {
+    let (world_w, world_h, seed) = (config.width, config.height, config.seed);
+    log!("Generating {world_w}x{world_h} world with seed {seed}.");
+    let GeneratedWorld {
+        fg_tiles,
+        bg_tiles,
+        liquids,
+        surface,
+        spawn_x,
+        spawn_y,
+    } = generate_world(seed, world_w, world_h);
+    let chunk_seqs = vec![1; world_w * world_h / CHUNK_AREA].into_boxed_slice();
+
+    let mut humanoid_id_counter = HumanoidId::new();
+    let mut humanoids = HashMap::new();
+    let zombie_x = spawn_x + 16;
+    let zombie_y = surface[zombie_x];
+
+    humanoids.insert(
+        humanoid_id_counter.next(),
+        new_zombie(
+            (zombie_x * TILE_SIZE) as f32,
+            (zombie_y * TILE_SIZE) as f32 - 48.,
+        ),
+    );
+
+    WorldSave {
+        header: WorldHeader {
+            width: world_w as u32,
+            height: world_h as u32,
+            seed,
+            spawn_x: spawn_x as u32,
+            spawn_y: spawn_y as u32,
+            humanoid_id_counter,
+        },
+        chunk_seqs,
+        fg_tiles,
+        bg_tiles,
+        liquids,
+        humanoids,
+        players: HashMap::new(),
+    }
+}
+
+fn new_zombie(x: f32, y: f32) -> Humanoid {
+    Humanoid {
+        base: HumanoidBase {
+            x,
+            y,
+            w: 32. - 8.,
+            h: 48. - 8.,
+            flags: 0,
+        },
+        ai: HumanoidAi::Zombie,
+        input: HumanoidInput::default(),
+        physics: HumanoidPhysics {
+            max_dx: 50.,
+            ..Default::default()
+        },
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::{LoopbackNetwork, LoopbackTransport, Transport};
+
+    fn new_game(network: &LoopbackNetwork) -> GameUpdateState {
+        let transport = Box::new(network.bind(0));
+        let config = WorldConfig {
+            path: None,
+            seed: 1,
+            width: 128,
+            height: 128,
+            autosave_interval: 0,
+            operators: vec![PlayerToken(1)],
+        };
+        let root = Path::new(".");
+        GameUpdateState::new(root, ServerNetManager::with_transport(transport), config)
+    }
+
+    fn tick(game: &mut GameUpdateState) {
+        let ts = timestamp_as_usecs();
+        game.prestep(ts);
+        game.step(ts, FRAMETIME);
+        game.poststep(ts);
+    }
+
+    fn send_join(game: &mut GameUpdateState, client: &mut LoopbackTransport, token: u128) {
+        send_join_as(game, client, token, &format!("Player {token}"));
+    }
+
+    fn send_join_as(
+        game: &mut GameUpdateState,
+        client: &mut LoopbackTransport,
+        token: u128,
+        name: &str,
+    ) {
+        let server_addr = game.net_manager.local_addr();
+        let connect = Handshake::Connect {
+            version: PROTOCOL_VERSION,
+        };
+        let join = ClientNetMessage::Join {
+            name: name.to_string(),
+            token: PlayerToken(token),
+            view_w: 1280,
+            view_h: 720,
+        };
+        client.send(server_addr, Delivery::Ru, serialize_handshake(&connect));
+        client.send(server_addr, Delivery::Ro, serialize(&[join]));
+        tick(game);
+    }
+
+    fn join(game: &mut GameUpdateState, client: &mut LoopbackTransport) {
+        send_join(game, client, 1);
+        assert!(game.connections[&client.local_addr()].joined);
+    }
+
+    // Everything the server sent, handshakes skipped.
+    fn recv_msgs(client: &mut LoopbackTransport) -> Vec<ServerNetMessage> {
+        client.poll();
+        std::iter::from_fn(|| client.recv())
+            .filter_map(|event| match event.kind {
+                NetEventKind::Data { bytes, .. } => deserialize(&bytes).ok(),
+                _ => None,
+            })
+            .flat_map(|msgs| msgs.into_vec())
+            .collect()
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let network = LoopbackNetwork::new();
+        let mut game = new_game(&network);
+        let server_addr = game.net_manager.local_addr();
+        let mut client = network.bind(0);
+        let connect = Handshake::Connect {
+            version: PROTOCOL_VERSION + 1,
+        };
+        client.send(server_addr, Delivery::Ru, serialize_handshake(&connect));
+        tick(&mut game);
+
+        client.poll();
+        let handshakes: Vec<_> = std::iter::from_fn(|| client.recv())
+            .filter_map(|event| match event.kind {
+                NetEventKind::Data { bytes, .. } => deserialize_handshake(&bytes).ok(),
+                _ => None,
+            })
+            .collect();
+        assert!(matches!(
+            handshakes[..],
+            [Handshake::Reject { version, .. }] if version == PROTOCOL_VERSION
+        ));
+        assert!(game
+            .connections
+            .get(&client.local_addr())
+            .is_none_or(|connection| connection.id.is_none()));
+    }
+
+    #[test]
+    fn kicks_hostile_clients() {
+        let network = LoopbackNetwork::new();
+        let mut game = new_game(&network);
+        let server_addr = game.net_manager.local_addr();
+        let mut client = network.bind(0);
+        let client_addr = client.local_addr();
+        join(&mut game, &mut client);
+        let id = game.connections[&client_addr].id.unwrap();
+
+        // Well-formed, but impossible.
+        let hostile = [
+            ClientNetMessage::Join {
+                name: String::from("Player 1"),
+                token: PlayerToken(1),
+                view_w: 1280,
+                view_h: 720,
+            },
+            ClientNetMessage::PlayerInput {
+                seq: 100,
+                inputs: vec![HumanoidInput::default(); 100],
+                snapshot_ack: 0,
+            },
+            ClientNetMessage::PlayerInput {
+                seq: 1,
+                inputs: vec![HumanoidInput::default()],
+                snapshot_ack: u32::MAX,
+            },
+            ClientNetMessage::MineTile {
+                x: u16::MAX,
+                y: 0,
+                layer: TileLayer::Fg,
+            },
+            ClientNetMessage::UseItem {
+                slot: INVENTORY_SIZE as u8,
+                x: 10,
+                y: 10,
+                layer: TileLayer::Bg,
+            },
+        ];
+        for msg in hostile {
+            client.send(server_addr, Delivery::Ru, serialize(&[msg]));
+        }
+        tick(&mut game);
+        assert_eq!(game.connections[&client_addr].strikes, 5);
+
+        // And garbage, until kicked.
+        let truncated = Box::from(&serialize(&[ClientNetMessage::Ping { seq: 0 }])[..1]);
+        for _ in 0..MAX_STRIKES {
+            client.send(server_addr, Delivery::Ru, Box::clone(&truncated));
+        }
+        tick(&mut game);
+        assert!(!game.connections.contains_key(&client_addr));
+        assert!(!game.humanoids.contains_key(&id));
+        assert!(recv_msgs(&mut client)
+            .iter()
+            .any(|msg| matches!(msg, ServerNetMessage::Kick { .. })));
+    }
+
+    #[test]
+    fn starved_players_still_fall() {
+        let network = LoopbackNetwork::new();
+        let mut game = new_game(&network);
+        let server_addr = game.net_manager.local_addr();
+        let mut client = network.bind(0);
+        let client_addr = client.local_addr();
+        join(&mut game, &mut client);
+        let id = game.connections[&client_addr].id.unwrap();
+        game.humanoids.get_mut(&id).unwrap().base.y = 16.;
+
+        // Holding right, then no more input frames.
+        let right = HumanoidInput {
+            right_queue: 1,
+            ..Default::default()
+        };
+        let msg = ClientNetMessage::PlayerInput {
+            seq: 1,
+            inputs: vec![right],
+            snapshot_ack: 0,
+        };
+        client.send(server_addr, Delivery::Uu, serialize(&[msg]));
+        tick(&mut game);
+        let x = game.humanoids[&id].base.x;
+
+        // Not sending input doesn't keep the player up, or the last input going.
+        let mut y = game.humanoids[&id].base.y;
+        for _ in 0..10 {
+            tick(&mut game);
+            let humanoid = &game.humanoids[&id];
+            assert!(humanoid.base.y > y);
+            y = humanoid.base.y;
+        }
+        assert!(game.humanoids[&id].base.x - x < 10.);
+        assert_eq!(game.connections[&client_addr].input_ack, 1);
+    }
+
+    #[test]
+    fn caps_humanoid_syncs() {
+        let network = LoopbackNetwork::new();
+        let mut game = new_game(&network);
+        let mut client = network.bind(0);
+        join(&mut game, &mut client);
+        let id = game.connections[&client.local_addr()].id.unwrap();
+
+        // A crowd, some of it right on top of the player.
+        let (x, y) = (game.humanoids[&id].base.x, game.humanoids[&id].base.y);
+        for i in 0..3 * MAX_SYNCED_HUMANOIDS {
+            let zombie_id = game.humanoid_id_counter.next();
+            let offset = (i % 8) as f32 * 8.;
+            game.humanoids.insert(zombie_id, new_zombie(x + offset, y));
+        }
+        tick(&mut game);
+
+        let updated = recv_msgs(&mut client)
+            .into_iter()
+            .filter_map(|msg| match msg {
+                ServerNetMessage::HumanoidSync { updated, .. } => Some(updated),
+                _ => None,
+            })
+            .last()
+            .unwrap();
+        assert_eq!(updated.len(), MAX_SYNCED_HUMANOIDS);
+        assert!(updated.iter().any(|delta| delta.id == id));
+    }
+
+    #[test]
+    fn remembers_players() {
+        let network = LoopbackNetwork::new();
+        let mut game = new_game(&network);
+        let mut alice = network.bind(0);
+        send_join(&mut game, &mut alice, 1);
+        let id = game.connections[&alice.local_addr()].id.unwrap();
+        game.humanoids.get_mut(&id).unwrap().base.x = 300.;
+
+        // The same token can't log in twice.
+        let mut impostor = network.bind(0);
+        send_join(&mut game, &mut impostor, 1);
+        assert!(game.connections[&impostor.local_addr()].id.is_none());
+        assert!(recv_msgs(&mut impostor)
+            .iter()
+            .any(|msg| matches!(msg, ServerNetMessage::JoinReject { .. })));
+        drop(impostor);
+
+        // Neither can someone else under the same name.
+        let mut impostor = network.bind(0);
+        send_join_as(&mut game, &mut impostor, 3, "PLAYER 1");
+        assert!(game.connections[&impostor.local_addr()].id.is_none());
+        assert!(recv_msgs(&mut impostor)
+            .iter()
+            .any(|msg| matches!(msg, ServerNetMessage::JoinReject { .. })));
+        drop(impostor);
+
+        // Others see who is online.
+        let mut bob = network.bind(0);
+        send_join(&mut game, &mut bob, 2);
+        tick(&mut game);
+        let roster = recv_msgs(&mut bob)
+            .into_iter()
+            .filter_map(|msg| match msg {
+                ServerNetMessage::Roster { players } => Some(players),
+                _ => None,
+            })
+            .last()
+            .unwrap();
+        let names: Vec<_> = roster.iter().map(|player| player.name.as_str()).collect();
+        assert_eq!(names, ["Player 1", "Player 2"]);
+
+        // Leaving and coming back continues where the player left off.
+        drop(alice);
+        tick(&mut game);
+        assert_eq!(game.logged_in.len(), 1);
+        let mut alice = network.bind(0);
+        send_join(&mut game, &mut alice, 1);
+        let id = game.connections[&alice.local_addr()].id.unwrap();
+        assert_eq!(game.humanoids[&id].base.x, 300.);
+    }
+
+    #[test]
+    fn mines_tiles() {
+        let network = LoopbackNetwork::new();
+        let mut game = new_game(&network);
+        let server_addr = game.net_manager.local_addr();
+        let mut client = network.bind(0);
+        join(&mut game, &mut client);
+        let id = game.connections[&client.local_addr()].id.unwrap();
+
+        // Some stone near the player (out of pickup range), once it has landed.
+        for _ in 0..120 {
+            tick(&mut game);
+        }
+        let player = &game.humanoids[&id].base;
+        let x = (player.x + player.w / 2.) as usize / TILE_SIZE + 4;
+        let y = (player.y + player.h / 2.) as usize / TILE_SIZE;
+        let index = x + y * game.world_w;
+        game.fg_tiles[index] = Tile::Stone;
+        recv_msgs(&mut client);
+        let mine = ClientNetMessage::MineTile {
+            x: x as u16,
+            y: y as u16,
+            layer: TileLayer::Fg,
+        };
+        let ticks = (Tile::Stone.def().hardness * 1e6 / FRAMETIME as f32) as usize;
+
+        // Letting go halfway through loses the progress.
+        client.send(server_addr, Delivery::Ro, serialize(&[mine.clone()]));
+        for _ in 0..ticks / 2 {
+            tick(&mut game);
+        }
+        client.send(
+            server_addr,
+            Delivery::Ro,
+            serialize(&[ClientNetMessage::StopMining]),
+        );
+        tick(&mut game);
+        assert_eq!(game.fg_tiles[index], Tile::Stone);
+        let stages: Vec<u8> = recv_msgs(&mut client)
+            .into_iter()
+            .filter_map(|msg| match msg {
+                ServerNetMessage::TileCrack { stage, .. } => Some(stage),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(stages, [1, 2, 0]);
+
+        // Holding on long enough breaks it, and leaves a drop.
+        client.send(server_addr, Delivery::Ro, serialize(&[mine]));
+        for _ in 0..ticks + ITEM_DROP_SYNC_INTERVAL as usize + 2 {
+            tick(&mut game);
+        }
+        assert_eq!(game.fg_tiles[index], Tile::None);
+        assert!(game.connections[&client.local_addr()].mining.is_none());
+        let msgs = recv_msgs(&mut client);
+        assert!(msgs.iter().any(|msg| matches!(
+            msg,
+            ServerNetMessage::TileSync { x: sx, y: sy, fg_tile: Tile::None, .. }
+                if (*sx as usize, *sy as usize) == (x, y)
+        )));
+        assert!(msgs.iter().any(|msg| matches!(
+            msg,
+            ServerNetMessage::ItemDropSync { drops, .. }
+                if drops.iter().any(|(_, drop)| drop.item == Item::Tile(Tile::Stone))
+        )));
+    }
+
+    #[test]
+    fn shares_tile_edits() {
+        let network = LoopbackNetwork::new();
+        let mut game = new_game(&network);
+        let server_addr = game.net_manager.local_addr();
+        let mut alice = network.bind(0);
+        let mut bob = network.bind(0);
+        send_join(&mut game, &mut alice, 1);
+        send_join(&mut game, &mut bob, 2);
+        assert!(game.connections[&bob.local_addr()].joined);
+        let id = game.connections[&alice.local_addr()].id.unwrap();
+
+        // Alice places a torch next to herself, once she has landed.
+        for _ in 0..120 {
+            tick(&mut game);
+        }
+        let player = &game.humanoids[&id].base;
+        let x = (player.x + player.w / 2.) as usize / TILE_SIZE + 3;
+        let y = (player.y + player.h / 2.) as usize / TILE_SIZE;
+        game.fg_tiles[x + y * game.world_w] = Tile::None;
+        recv_msgs(&mut bob);
+        let use_item = ClientNetMessage::UseItem {
+            slot: 0,
+            x: x as u16,
+            y: y as u16,
+            layer: TileLayer::Fg,
+        };
+        alice.send(server_addr, Delivery::Ro, serialize(&[use_item]));
+        tick(&mut game);
+
+        // Bob, who is nearby, hears about it.
+        let chunk_index = x / CHUNK_SIZE + y / CHUNK_SIZE * game.world_w / CHUNK_SIZE;
+        let seq = game.chunk_seqs[chunk_index];
+        assert!(recv_msgs(&mut bob).iter().any(|msg| matches!(
+            msg,
+            ServerNetMessage::TileSync { x: sx, y: sy, seq: sseq, fg_tile: Tile::RedTorch, .. }
+                if (*sx as usize, *sy as usize, *sseq) == (x, y, seq)
+        )));
+        assert_eq!(
+            game.connections[&bob.local_addr()].known_chunks[&chunk_index],
+            seq
+        );
+    }
+
+    #[test]
+    fn streams_chunks_around_players() {
+        let network = LoopbackNetwork::new();
+        let mut game = new_game(&network);
+        let mut client = network.bind(0);
+        join(&mut game, &mut client);
+        let addr = client.local_addr();
+        let id = game.connections[&addr].id.unwrap();
+        let chunks_w = game.world_w / CHUNK_SIZE;
+        let chunk_px = (CHUNK_SIZE * TILE_SIZE) as f32;
+
+        // A small view, so moving a few chunks leaves some behind.
+        let view = 2 * CHUNK_SIZE * TILE_SIZE;
+        let connection = game.connections.get_mut(&addr).unwrap();
+        (connection.view_w, connection.view_h) = (view, view);
+        let in_range = |game: &GameUpdateState, margin| {
+            let base = &game.humanoids[&id].base;
+            let center = (base.x + base.w / 2., base.y + base.h / 2.);
+            let (x1, y1, x2, y2) =
+                chunk_range(center, view, view, margin, game.world_w, game.world_h);
+            let mut indices: Vec<usize> = (y1..y2)
+                .flat_map(|cy| (x1..x2).map(move |cx| cx + cy * chunks_w))
+                .collect();
+            indices.sort();
+            indices
+        };
+        let known = |game: &GameUpdateState| {
+            let mut indices: Vec<usize> = game.connections[&addr]
+                .known_chunks
+                .keys()
+                .copied()
+                .collect();
+            indices.sort();
+            indices
+        };
+
+        game.humanoids.get_mut(&id).unwrap().base.x = 3.5 * chunk_px;
+        game.humanoids.get_mut(&id).unwrap().base.y = 8. * chunk_px;
+        tick(&mut game);
+        recv_msgs(&mut client);
+        let known_before = known(&game);
+
+        // Moving over sends what came into view, as it is on the server.
+        game.humanoids.get_mut(&id).unwrap().base.x += 4. * chunk_px;
+        tick(&mut game);
+        let synced: Vec<ChunkData> = recv_msgs(&mut client)
+            .into_iter()
+            .filter_map(|msg| match msg {
+                ServerNetMessage::ChunkSync { chunks, .. } => unpack_chunks(&chunks),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        let mut synced_indices: Vec<usize> = synced
+            .iter()
+            .map(|chunk| chunk.x as usize + chunk.y as usize * chunks_w)
+            .collect();
+        synced_indices.sort();
+        let expected: Vec<usize> = in_range(&game, CHUNK_STREAM_MARGIN)
+            .into_iter()
+            .filter(|index| !known_before.contains(index))
+            .collect();
+        assert!(!expected.is_empty());
+        assert_eq!(synced_indices, expected);
+        for chunk in &synced {
+            let (fg_tiles, bg_tiles) = decode_chunk_tiles(&chunk.runs).unwrap();
+            for i in 0..CHUNK_AREA {
+                let x = chunk.x as usize * CHUNK_SIZE + i % CHUNK_SIZE;
+                let y = chunk.y as usize * CHUNK_SIZE + i / CHUNK_SIZE;
+                assert_eq!(fg_tiles[i], game.fg_tiles[x + y * game.world_w]);
+                assert_eq!(bg_tiles[i], game.bg_tiles[x + y * game.world_w]);
+            }
+        }
+
+        // And forgets what went far enough out of it, so it gets sent again on the way back.
+        let known_after = known(&game);
+        let kept = in_range(&game, CHUNK_UNLOAD_MARGIN);
+        assert!(known_after.iter().all(|index| kept.contains(index)));
+        assert!(known_before
+            .iter()
+            .any(|index| !known_after.contains(index)));
+        assert!(in_range(&game, CHUNK_STREAM_MARGIN)
+            .iter()
+            .all(|index| known_after.contains(index)));
+    }
+
+    #[test]
+    fn uses_and_picks_up_items() {
+        let network = LoopbackNetwork::new();
+        let mut game = new_game(&network);
+        let server_addr = game.net_manager.local_addr();
+        let mut client = network.bind(0);
+        join(&mut game, &mut client);
+        let id = game.connections[&client.local_addr()].id.unwrap();
+        let torch = Item::Tile(Tile::RedTorch);
+        let inventory = |game: &GameUpdateState| game.players[&PlayerToken(1)].inventory.clone();
+        let synced = |client: &mut LoopbackTransport| {
+            recv_msgs(client)
+                .into_iter()
+                .filter_map(|msg| match msg {
+                    ServerNetMessage::InventorySync { inventory } => Some(inventory),
+                    _ => None,
+                })
+                .last()
+        };
+
+        // New players start with some torches.
+        let start = synced(&mut client).unwrap();
+        assert_eq!(start.count(torch), 20);
+        assert_eq!(start.slots[0].unwrap().item, torch);
+
+        // Placing one uses it up.
+        for _ in 0..120 {
+            tick(&mut game);
+        }
+        let player = &game.humanoids[&id].base;
+        let x = (player.x + player.w / 2.) as usize / TILE_SIZE + 3;
+        let y = (player.y + player.h / 2.) as usize / TILE_SIZE;
+        let index = x + y * game.world_w;
+        game.fg_tiles[index] = Tile::None;
+        let use_item = ClientNetMessage::UseItem {
+            slot: 0,
+            x: x as u16,
+            y: y as u16,
+            layer: TileLayer::Fg,
+        };
+        client.send(server_addr, Delivery::Ro, serialize(&[use_item.clone()]));
+        tick(&mut game);
+        assert_eq!(game.fg_tiles[index], Tile::RedTorch);
+        assert_eq!(synced(&mut client).unwrap().count(torch), 19);
+
+        // Moving the stack away leaves nothing to use in the slot.
+        game.fg_tiles[index] = Tile::None;
+        let move_item = ClientNetMessage::MoveItem { from: 0, to: 15 };
+        client.send(server_addr, Delivery::Ro, serialize(&[move_item, use_item]));
+        tick(&mut game);
+        assert_eq!(game.fg_tiles[index], Tile::None);
+        assert_eq!(inventory(&game).slots[0], None);
+        assert_eq!(inventory(&game).slots[15].unwrap().count, 19);
+        assert_eq!(game.connections[&client.local_addr()].strikes, 0);
+
+        // Drops get picked up by walking into them.
+        let player = &game.humanoids[&id].base;
+        let (px, py) = (player.x as usize / TILE_SIZE, player.y as usize / TILE_SIZE);
+        game.spawn_item_drop(ItemDrop::new(Item::Tile(Tile::Sand), px, py));
+        for _ in 0..10 {
+            tick(&mut game);
+        }
+        assert!(game.item_drops.is_empty());
+        assert_eq!(inventory(&game).count(Item::Tile(Tile::Sand)), 1);
+        assert_eq!(
+            inventory(&game).slots[0].unwrap().item,
+            Item::Tile(Tile::Sand)
+        );
+
+        // And handed out by operators.
+        game.run_command(CommandSource::Console, "give \"Player 1\" gravel 5");
+        game.run_command(CommandSource::Console, "give \"Player 1\" unobtainium");
+        tick(&mut game);
+        assert_eq!(
+            synced(&mut client).unwrap().count(Item::Tile(Tile::Gravel)),
+            5
+        );
+    }
+
+    #[test]
+    fn leaves_and_reconnects() {
+        let network = LoopbackNetwork::new();
+        let mut game = new_game(&network);
+        let server_addr = game.net_manager.local_addr();
+        let mut bob = network.bind(0);
+        send_join(&mut game, &mut bob, 2);
+        let left = |bob: &mut LoopbackTransport| {
+            recv_msgs(bob)
+                .into_iter()
+                .filter_map(|msg| match msg {
+                    ServerNetMessage::PlayerLeft { player } => Some(player.id),
+                    _ => None,
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Leaving despawns right away.
+        let mut alice = network.bind(0);
+        send_join(&mut game, &mut alice, 1);
+        let id = game.connections[&alice.local_addr()].id.unwrap();
+        alice.send(
+            server_addr,
+            Delivery::Ru,
+            serialize(&[ClientNetMessage::Leave]),
+        );
+        tick(&mut game);
+        assert!(!game.humanoids.contains_key(&id));
+        assert!(game.lingering.is_empty());
+        tick(&mut game);
+        assert_eq!(left(&mut bob), [id]);
+
+        // Losing the connection keeps the humanoid around for a while, and rejoining takes it
+        // back over.
+        send_join(&mut game, &mut alice, 1);
+        let id = game.connections[&alice.local_addr()].id.unwrap();
+        drop(alice);
+        tick(&mut game);
+        assert!(game.humanoids.contains_key(&id));
+        assert!(game.logged_in.get(&PlayerToken(1)).is_none());
+        let mut alice = network.bind(0);
+        send_join(&mut game, &mut alice, 1);
+        assert_eq!(game.connections[&alice.local_addr()].id, Some(id));
+        assert!(game.lingering.is_empty());
+
+        // Unless the grace period runs out.
+        drop(alice);
+        tick(&mut game);
+        game.lingering
+            .values_mut()
+            .for_each(|(_, despawn_ts)| *despawn_ts = 0);
+        tick(&mut game);
+        assert!(!game.humanoids.contains_key(&id));
+        tick(&mut game);
+        assert_eq!(left(&mut bob), [id]);
+    }
+
+    #[test]
+    fn chat_and_commands() {
+        let network = LoopbackNetwork::new();
+        let mut game = new_game(&network);
+        let server_addr = game.net_manager.local_addr();
+        let mut alice = network.bind(0);
+        let mut bob = network.bind(0);
+        send_join(&mut game, &mut alice, 1);
+        send_join(&mut game, &mut bob, 2);
+        let bob_addr = bob.local_addr();
+        recv_msgs(&mut alice);
+        recv_msgs(&mut bob);
+        let chat = |game: &mut GameUpdateState, client: &mut LoopbackTransport, text: &str| {
+            let msg = ClientNetMessage::Chat {
+                text: text.to_string(),
+            };
+            client.send(server_addr, Delivery::Ro, serialize(&[msg]));
+            tick(game);
+        };
+
+        // Chat goes to everyone, commands only work for operators.
+        chat(&mut game, &mut bob, "hello");
+        chat(&mut game, &mut bob, "/save");
+        let msgs = recv_msgs(&mut alice);
+        assert!(msgs.iter().any(|msg| matches!(
+            msg,
+            ServerNetMessage::Chat { from: Some(from), text } if from == "Player 2" && text == "hello"
+        )));
+        let msgs = recv_msgs(&mut bob);
+        assert!(msgs.iter().any(|msg| matches!(
+            msg,
+            ServerNetMessage::Chat { from: None, text } if text.contains("not allowed")
+        )));
+
+        chat(&mut game, &mut alice, "/tp \"player 2\" 10 20");
+        let id = game.connections[&bob_addr].id.unwrap();
+        assert_eq!(game.humanoids[&id].base.x, (10 * TILE_SIZE) as f32);
+        chat(&mut game, &mut alice, "/time 12");
+        assert!(recv_msgs(&mut bob)
+            .iter()
+            .any(|msg| matches!(msg, ServerNetMessage::Time { time } if *time == 0.5)));
+
+        // The console can do the same.
+        let humanoids = game.humanoids.len();
+        game.run_command(CommandSource::Console, "spawn zombie 20 20");
+        assert_eq!(game.humanoids.len(), humanoids + 1);
+        game.run_command(CommandSource::Console, "kick \"Player 2\" spamming");
+        tick(&mut game);
+        assert!(!game.connections.contains_key(&bob_addr));
+        assert!(recv_msgs(&mut bob)
+            .iter()
+            .any(|msg| matches!(msg, ServerNetMessage::Kick { reason } if reason == "spamming")));
+    }
+
+    #[test]
+    fn survives_garbage() {
+        let network = LoopbackNetwork::new();
+        let mut game = new_game(&network);
+        let server_addr = game.net_manager.local_addr();
+        let mut client = network.bind(0);
+        let client_addr = client.local_addr();
+
+        // Valid packets to corrupt.
+        let valid = [
+            serialize_handshake(&Handshake::Connect {
+                version: PROTOCOL_VERSION,
+            }),
+            serialize(&[ClientNetMessage::PlayerInput {
+                seq: 10,
+                inputs: vec![HumanoidInput::default(); INPUT_REDUNDANCY],
+                snapshot_ack: 1,
+            }]),
+            serialize(&[
+                ClientNetMessage::MineTile {
+                    x: 64,
+                    y: 64,
+                    layer: TileLayer::Fg,
+                },
+                ClientNetMessage::UseItem {
+                    slot: 0,
+                    x: 64,
+                    y: 64,
+                    layer: TileLayer::Bg,
+                },
+                ClientNetMessage::MoveItem { from: 0, to: 1 },
+                ClientNetMessage::StopMining,
+            ]),
+        ];
+
+        let mut rng = Rng::new(5);
+        for i in 0..2000 {
+            // Rejoin whenever kicked, so the handlers keep getting exercised.
+            if !game.connections.contains_key(&client_addr) {
+                join(&mut game, &mut client);
+            }
+
+            let bytes: Box<[u8]> = if i % 2 == 0 {
+                let len = rng.range(0, 64) as usize;
+                (0..len).map(|_| rng.next_u32() as u8).collect()
+            } else {
+                let mut bytes = valid[i % valid.len()].clone();
+                for _ in 0..rng.range(1, 4) {
+                    let j = rng.range(1, bytes.len() as u64) as usize;
+                    bytes[j] = rng.next_u32() as u8;
+                }
+                bytes
+            };
+            client.send(server_addr, Delivery::Ru, bytes);
+            if i % 10 == 0 {
+                tick(&mut game);
+            }
+        }
+    }
 }
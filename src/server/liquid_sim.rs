@@ -0,0 +1,275 @@
+use crate::shared::*;
+use std::collections::HashSet;
+
+// Cellular liquid flow. Only active cells (ones that changed, or whose neighbours did) are
+// looked at, so settled pools cost nothing.
+//
+// Every step, bottom row first, each active liquid cell:
+//   - Falls into the cell below, as much as fits.
+//   - Otherwise spreads sideways into lower neighbours, evening levels out. Neighbours within one
+//     level of each other are settled.
+// A cell that received liquid this step waits for the next one, so liquid moves one cell per
+// step. Lava that touches water hardens into stone.
+pub struct LiquidSim {
+    active: Vec<usize>,
+}
+
+impl LiquidSim {
+    pub fn new() -> Self {
+        Self { active: Vec::new() }
+    }
+
+    // After anything changes at index (a tile edit), its liquid and its neighbours' may move.
+    pub fn activate_around(&mut self, index: usize, world_w: usize) {
+        self.active.extend_from_slice(&[
+            index,
+            index.saturating_sub(1),
+            index + 1,
+            index.saturating_sub(world_w),
+            index + world_w,
+        ]);
+    }
+
+    // Wakes every liquid cell, for a freshly loaded world.
+    pub fn activate_all(&mut self, liquids: &[Liquid]) {
+        self.active.extend(
+            liquids
+                .iter()
+                .enumerate()
+                .filter(|(_, liquid)| !liquid.is_empty())
+                .map(|(index, _)| index),
+        );
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.active.is_empty()
+    }
+
+    // Returns the indices whose liquid or fg tile changed, sorted.
+    pub fn step(
+        &mut self,
+        tick: u32,
+        world_w: usize,
+        world_h: usize,
+        fg_tiles: &mut [Tile],
+        liquids: &mut [Liquid],
+    ) -> Vec<usize> {
+        let mut active = std::mem::take(&mut self.active);
+        active.sort_unstable_by(|a, b| b.cmp(a));
+        active.dedup();
+
+        // Open to liquid of a kind: inside the border, not solid, and empty or the same liquid.
+        let is_open = |index: usize, kind: LiquidKind, fg_tiles: &[Tile], liquids: &[Liquid]| {
+            let (x, y) = (index % world_w, index / world_w);
+            let liquid: Liquid = liquids[index];
+            x > 0
+                && y > 0
+                && x < world_w - 1
+                && y < world_h - 1
+                && !TILE_PHYSICS_PROPERTIES[fg_tiles[index].0 as usize].solid
+                && (liquid.is_empty() || liquid.kind == kind)
+        };
+
+        let mut received = HashSet::new();
+        let mut changed = Vec::new();
+        for index in active {
+            if index >= liquids.len() {
+                continue;
+            }
+            let liquid = liquids[index];
+            if liquid.is_empty() {
+                continue;
+            }
+            let (x, y) = (index % world_w, index / world_w);
+            if x == 0 || y == 0 || x >= world_w - 1 || y >= world_h - 1 {
+                continue;
+            }
+            let neighbours = [index - 1, index + 1, index - world_w, index + world_w];
+
+            // Lava meets water.
+            if liquid.kind == LiquidKind::Lava
+                && neighbours
+                    .iter()
+                    .any(|&i| liquids[i].kind == LiquidKind::Water)
+            {
+                liquids[index] = Liquid::NONE;
+                fg_tiles[index] = Tile::Stone;
+                changed.push(index);
+                continue;
+            }
+            if liquid.kind == LiquidKind::Water {
+                for &i in &neighbours {
+                    if liquids[i].kind == LiquidKind::Lava {
+                        self.active.push(i);
+                    }
+                }
+            }
+
+            // Wait for this liquid's turn, or for liquid that just arrived to come to rest.
+            let interval = liquid.property().flow_interval;
+            if !tick.is_multiple_of(interval) || received.contains(&index) {
+                self.active.push(index);
+                continue;
+            }
+
+            let mut level = liquid.level;
+            let mut flows = Vec::new();
+
+            // Fall.
+            let below = index + world_w;
+            let can_fall =
+                is_open(below, liquid.kind, fg_tiles, liquids) && liquids[below].level < LIQUID_MAX;
+            if can_fall {
+                let flow = level.min(LIQUID_MAX - liquids[below].level);
+                level -= flow;
+                flows.push((below, flow));
+            }
+
+            // Spread, alternating which side goes first so neither is favoured.
+            if !can_fall {
+                let sides = match (tick / interval) % 2 {
+                    0 => [index - 1, index + 1],
+                    _ => [index + 1, index - 1],
+                };
+                let lower: Vec<usize> = sides
+                    .into_iter()
+                    .filter(|&i| is_open(i, liquid.kind, fg_tiles, liquids))
+                    .filter(|&i| liquids[i].level + 1 < level)
+                    .collect();
+                let n = lower.len() as u8;
+                for i in lower {
+                    // The first side may have taken enough to level with the second.
+                    if liquids[i].level + 1 >= level {
+                        continue;
+                    }
+                    let flow = ((level - liquids[i].level) / (n + 1)).max(1);
+                    level -= flow;
+                    flows.push((i, flow));
+                }
+            }
+
+            if flows.is_empty() {
+                continue;
+            }
+            liquids[index] = Liquid::new(liquid.kind, level);
+            changed.push(index);
+            self.activate_around(index, world_w);
+            for (i, flow) in flows {
+                liquids[i] = Liquid::new(liquid.kind, liquids[i].level + flow);
+                received.insert(i);
+                changed.push(i);
+                self.activate_around(i, world_w);
+            }
+        }
+
+        changed.sort_unstable();
+        changed.dedup();
+        changed
+    }
+}
+
+impl Default for LiquidSim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Stone box with the given interior, row by row: '#' stone, '~' water, '*' lava, '.' air.
+    fn world(rows: &[&str]) -> (usize, usize, Vec<Tile>, Vec<Liquid>) {
+        let (w, h) = (rows[0].len() + 2, rows.len() + 2);
+        let mut fg_tiles = vec![Tile::Stone; w * h];
+        let mut liquids = vec![Liquid::NONE; w * h];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let index = x + 1 + (y + 1) * w;
+                match c {
+                    '#' => continue,
+                    '~' => liquids[index] = Liquid::new(LiquidKind::Water, LIQUID_MAX),
+                    '*' => liquids[index] = Liquid::new(LiquidKind::Lava, LIQUID_MAX),
+                    _ => {}
+                }
+                fg_tiles[index] = Tile::None;
+            }
+        }
+        (w, h, fg_tiles, liquids)
+    }
+
+    fn settle(w: usize, h: usize, fg_tiles: &mut [Tile], liquids: &mut [Liquid]) -> u32 {
+        let mut sim = LiquidSim::new();
+        sim.activate_all(liquids);
+        let mut tick = 0;
+        while !sim.is_settled() {
+            tick += 1;
+            assert!(tick < 10_000, "never settled");
+            sim.step(tick, w, h, fg_tiles, liquids);
+        }
+        tick
+    }
+
+    fn total(liquids: &[Liquid]) -> u32 {
+        liquids.iter().map(|liquid| liquid.level as u32).sum()
+    }
+
+    #[test]
+    fn falls_and_spreads() {
+        let (w, h, mut fg_tiles, mut liquids) = world(&[
+            "...~~...", //
+            "........", "........", "........",
+        ]);
+        let before = total(&liquids);
+        settle(w, h, &mut fg_tiles, &mut liquids);
+
+        // Nothing lost, all of it on the floor, evened out.
+        assert_eq!(total(&liquids), before);
+        let floor = &liquids[4 * w + 1..4 * w + w - 1];
+        assert_eq!(floor.iter().map(|l| l.level as u32).sum::<u32>(), before);
+        let levels: Vec<u8> = floor.iter().map(|l| l.level).collect();
+        assert!(
+            levels.windows(2).all(|l| l[0].abs_diff(l[1]) <= 1),
+            "{levels:?}"
+        );
+
+        // Deterministic.
+        let (_, _, mut fg_tiles2, mut liquids2) = world(&[
+            "...~~...", //
+            "........", "........", "........",
+        ]);
+        settle(w, h, &mut fg_tiles2, &mut liquids2);
+        assert_eq!(liquids, liquids2);
+    }
+
+    #[test]
+    fn stays_in_containers() {
+        let (w, h, mut fg_tiles, mut liquids) = world(&[
+            "........", //
+            "#~~.#...", "#####...",
+        ]);
+        settle(w, h, &mut fg_tiles, &mut liquids);
+
+        // The cup evens out, and nothing leaks out of it.
+        let cup: Vec<u8> = (2..5).map(|x| liquids[x + 2 * w].level).collect();
+        assert!(cup.iter().all(|&level| level >= 10), "{cup:?}");
+        assert_eq!(
+            cup.iter().map(|&level| level as u32).sum::<u32>(),
+            total(&liquids)
+        );
+    }
+
+    #[test]
+    fn lava_hardens_in_water() {
+        let (w, h, mut fg_tiles, mut liquids) = world(&[
+            "~~..", //
+            "..**",
+        ]);
+        settle(w, h, &mut fg_tiles, &mut liquids);
+
+        // The water ran into the lava, which crusted over and kept the rest of it in.
+        assert_eq!(fg_tiles[3 + 2 * w], Tile::Stone);
+        assert!(liquids[3 + 2 * w].is_empty());
+        assert_eq!(liquids[4 + 2 * w].kind, LiquidKind::Lava);
+    }
+}
@@ -1,8 +1,18 @@
+pub mod commands;
 pub mod game_update_state;
+pub mod liquid_sim;
 pub mod server;
+pub mod tile_gravity;
+pub mod world_gen;
+pub mod world_save;
 
+pub use commands::*;
 pub use game_update_state::*;
+pub use liquid_sim::*;
 pub use server::*;
+pub use tile_gravity::*;
+pub use world_gen::*;
+pub use world_save::*;
 
 pub mod server_log {
     macro_rules! log {
@@ -1,6 +1,8 @@
-use crate::net::ServerNetManager;
-use crate::server::GameUpdateState;
+use crate::net::{DiscoveryResponder, ServerNetManager};
+use crate::server::{CommandSource, GameUpdateState, WorldConfig};
+use crate::shared::FRAMETIME;
 use crate::time::timestamp_as_usecs;
+use crossbeam_channel::Receiver;
 use std::path::Path;
 
 pub struct Server {
@@ -8,6 +10,15 @@ pub struct Server {
     update_ts: u64,
     update_state: GameUpdateState,
 
+    // Command lines typed by the operator.
+    console: Option<Receiver<String>>,
+
+    // Signalled (on Ctrl+C, say) to save the world and stop.
+    shutdown: Option<Receiver<()>>,
+
+    // Answers LAN discovery queries.
+    discovery: Option<DiscoveryResponder>,
+
     // Diagnostic.
     update_n: u64,
     prestep_acc: u64,
@@ -16,29 +27,54 @@ pub struct Server {
 }
 
 impl Server {
-    pub fn new(root: &'static Path, bind_port: u16) -> (Self, u16) {
-        let (net_manager, bind_port) = ServerNetManager::new(bind_port);
-
-        (
-            Self {
-                update_ts: timestamp_as_usecs(),
-                update_state: GameUpdateState::new(root, net_manager),
-
-                update_n: 0,
-                prestep_acc: 0,
-                step_acc: 0,
-                poststep_acc: 0,
-            },
-            bind_port,
-        )
+    pub fn new(
+        root: &'static Path,
+        net_manager: ServerNetManager,
+        world_config: WorldConfig,
+    ) -> Self {
+        Self {
+            update_ts: timestamp_as_usecs(),
+            update_state: GameUpdateState::new(root, net_manager, world_config),
+
+            console: None,
+            shutdown: None,
+            discovery: None,
+
+            update_n: 0,
+            prestep_acc: 0,
+            step_acc: 0,
+            poststep_acc: 0,
+        }
+    }
+
+    pub fn with_console(mut self, console: Receiver<String>) -> Self {
+        self.console = Some(console);
+        self
+    }
+
+    pub fn with_shutdown(mut self, shutdown: Receiver<()>) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    pub fn with_discovery(mut self, discovery: DiscoveryResponder) -> Self {
+        self.discovery = Some(discovery);
+        self
     }
 
-    pub fn run(mut self) -> ! {
+    // Runs until told to shut down, then saves the world.
+    pub fn run(mut self) {
         //let frametime = 33_332_u64;
-        let frametime = 16_666_u64;
+        let frametime = FRAMETIME;
 
         //
         loop {
+            if let Some(shutdown) = &self.shutdown {
+                if shutdown.try_recv().is_ok() {
+                    break;
+                }
+            }
+
             // Wait for enough time to process a frame.
             let next_timestamp = crate::time::wait(self.update_ts + frametime, 1_000);
             assert!(next_timestamp - self.update_ts >= frametime,);
@@ -46,6 +82,11 @@ impl Server {
             // Prestep.
             let ts = timestamp_as_usecs();
             {
+                if let Some(console) = &self.console {
+                    for line in console.try_iter() {
+                        self.update_state.run_command(CommandSource::Console, &line);
+                    }
+                }
                 self.update_state.prestep(self.update_ts);
             }
             self.prestep_acc += timestamp_as_usecs() - ts;
@@ -65,6 +106,9 @@ impl Server {
             let ts = timestamp_as_usecs();
             {
                 self.update_state.poststep(self.update_ts);
+                if let Some(discovery) = &mut self.discovery {
+                    discovery.poll(self.update_state.player_count());
+                }
             }
             self.poststep_acc += timestamp_as_usecs() - ts;
 
@@ -85,5 +129,9 @@ impl Server {
                 self.update_n = 0;
             }
         }
+
+        // Save before going away.
+        self.update_state.save();
+        self.update_state.finish_saving();
     }
 }
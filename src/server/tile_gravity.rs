@@ -0,0 +1,223 @@
+use crate::shared::*;
+
+// Ticks between falls, i.e. a falling tile drops one cell every this many ticks.
+pub const TILE_FALL_INTERVAL: u32 = 3;
+
+// Makes tiles that fall (sand, gravel) drop while nothing solid is under them. Like LiquidSim,
+// only scheduled cells are looked at: ones whose tile just moved or got placed, and the ones above
+// anything that changed.
+//
+// Every fall step, bottom row first, each scheduled falling tile with open space below it moves
+// down one cell, swapping places with whatever liquid was there. Going bottom up, a whole column
+// over a gap moves down together.
+pub struct TileGravity {
+    scheduled: Vec<usize>,
+}
+
+impl TileGravity {
+    pub fn new() -> Self {
+        Self {
+            scheduled: Vec::new(),
+        }
+    }
+
+    // After anything changes at index, the tile there and the one above may have to fall.
+    pub fn schedule_around(&mut self, index: usize, world_w: usize) {
+        self.scheduled
+            .extend_from_slice(&[index, index.saturating_sub(world_w)]);
+    }
+
+    // Schedules every falling tile, for a freshly loaded world.
+    pub fn schedule_all(&mut self, fg_tiles: &[Tile]) {
+        self.scheduled.extend(
+            fg_tiles
+                .iter()
+                .enumerate()
+                .filter(|(_, tile)| TILE_PHYSICS_PROPERTIES[tile.0 as usize].falls)
+                .map(|(index, _)| index),
+        );
+    }
+
+    pub fn is_settled(&self) -> bool {
+        self.scheduled.is_empty()
+    }
+
+    // Returns the indices whose fg tile or liquid changed, sorted.
+    pub fn step(
+        &mut self,
+        tick: u32,
+        world_w: usize,
+        world_h: usize,
+        fg_tiles: &mut [Tile],
+        liquids: &mut [Liquid],
+    ) -> Vec<usize> {
+        if !tick.is_multiple_of(TILE_FALL_INTERVAL) {
+            return Vec::new();
+        }
+
+        let mut scheduled = std::mem::take(&mut self.scheduled);
+        scheduled.sort_unstable_by(|a, b| b.cmp(a));
+        scheduled.dedup();
+
+        let mut changed = Vec::new();
+        for index in scheduled {
+            if index >= fg_tiles.len() {
+                continue;
+            }
+            let tile = fg_tiles[index];
+            if !TILE_PHYSICS_PROPERTIES[tile.0 as usize].falls {
+                continue;
+            }
+
+            // The world border holds everything up.
+            let (x, y) = (index % world_w, index / world_w);
+            if x == 0 || x >= world_w - 1 || y + 2 >= world_h {
+                continue;
+            }
+            let below = index + world_w;
+            if TILE_PHYSICS_PROPERTIES[fg_tiles[below].0 as usize].solid {
+                continue;
+            }
+
+            // Fall, pushing whatever liquid was below up into the gap.
+            fg_tiles[below] = tile;
+            fg_tiles[index] = Tile::None;
+            liquids[index] = liquids[below];
+            liquids[below] = Liquid::NONE;
+            changed.extend_from_slice(&[index, below]);
+            self.scheduled.push(below);
+            self.schedule_around(index, world_w);
+        }
+
+        changed.sort_unstable();
+        changed
+    }
+}
+
+impl Default for TileGravity {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Stone box with the given interior, row by row: '#' stone, 's' sand, '~' water, '.' air.
+    fn world(rows: &[&str]) -> (usize, usize, Vec<Tile>, Vec<Liquid>) {
+        let (w, h) = (rows[0].len() + 2, rows.len() + 2);
+        let mut fg_tiles = vec![Tile::Stone; w * h];
+        let mut liquids = vec![Liquid::NONE; w * h];
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let index = x + 1 + (y + 1) * w;
+                fg_tiles[index] = match c {
+                    '#' => Tile::Stone,
+                    's' => Tile::Sand,
+                    _ => Tile::None,
+                };
+                if c == '~' {
+                    liquids[index] = Liquid::new(LiquidKind::Water, LIQUID_MAX);
+                }
+            }
+        }
+        (w, h, fg_tiles, liquids)
+    }
+
+    // Inverse of world(), to compare against.
+    fn rows(w: usize, h: usize, fg_tiles: &[Tile], liquids: &[Liquid]) -> Vec<String> {
+        (1..h - 1)
+            .map(|y| {
+                (1..w - 1)
+                    .map(|x| {
+                        let index = x + y * w;
+                        match fg_tiles[index] {
+                            Tile::Stone => '#',
+                            Tile::None if !liquids[index].is_empty() => '~',
+                            Tile::None => '.',
+                            _ => 's',
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    // Returns the number of ticks it took.
+    fn settle(w: usize, h: usize, fg_tiles: &mut [Tile], liquids: &mut [Liquid]) -> u32 {
+        let mut gravity = TileGravity::new();
+        gravity.schedule_all(fg_tiles);
+        let mut tick = 0;
+        while !gravity.is_settled() {
+            tick += 1;
+            assert!(tick < 10_000, "never settled");
+            gravity.step(tick, w, h, fg_tiles, liquids);
+        }
+        tick
+    }
+
+    #[test]
+    fn columns_collapse() {
+        let (w, h, mut fg_tiles, mut liquids) = world(&[
+            "s.s.", //
+            "s.s.", "..#.", "....",
+        ]);
+        let ticks = settle(w, h, &mut fg_tiles, &mut liquids);
+
+        // The unsupported column fell to the floor in one piece, the supported one stayed.
+        assert_eq!(
+            rows(w, h, &fg_tiles, &liquids),
+            ["..s.", "..s.", "s.#.", "s..."]
+        );
+        assert!(ticks <= 3 * TILE_FALL_INTERVAL);
+
+        // Mining the stone brings the rest down too.
+        let mut gravity = TileGravity::new();
+        let stone = 3 + 3 * w;
+        fg_tiles[stone] = Tile::None;
+        gravity.schedule_around(stone, w);
+        let mut changed = Vec::new();
+        for tick in 1..100 {
+            changed.extend(gravity.step(tick, w, h, &mut fg_tiles, &mut liquids));
+        }
+        assert!(gravity.is_settled());
+        assert_eq!(
+            rows(w, h, &fg_tiles, &liquids),
+            ["....", "....", "s.s.", "s.s."]
+        );
+        assert!(changed.contains(&(3 + 4 * w)));
+    }
+
+    #[test]
+    fn sinks_through_liquid() {
+        let (w, h, mut fg_tiles, mut liquids) = world(&[
+            ".s.", //
+            "#~#", "#~#",
+        ]);
+        let before: u32 = liquids.iter().map(|l| l.level as u32).sum();
+        settle(w, h, &mut fg_tiles, &mut liquids);
+
+        // The water got pushed up, none of it lost.
+        assert_eq!(rows(w, h, &fg_tiles, &liquids), [".~.", "#~#", "#s#"]);
+        let after: u32 = liquids.iter().map(|l| l.level as u32).sum();
+        assert_eq!(after, before);
+    }
+
+    #[test]
+    fn is_deterministic() {
+        let map = ["ss.s.ss.", "s..s#..s", "..#.....", "s...ss..", "........"];
+        let (w, h, mut fg_tiles, mut liquids) = world(&map);
+        settle(w, h, &mut fg_tiles, &mut liquids);
+        let (_, _, mut fg_tiles2, mut liquids2) = world(&map);
+        settle(w, h, &mut fg_tiles2, &mut liquids2);
+        assert_eq!(fg_tiles, fg_tiles2);
+
+        // Nothing left floating.
+        for index in 0..w * (h - 1) {
+            if TILE_PHYSICS_PROPERTIES[fg_tiles[index].0 as usize].falls {
+                assert!(TILE_PHYSICS_PROPERTIES[fg_tiles[index + w].0 as usize].solid);
+            }
+        }
+    }
+}
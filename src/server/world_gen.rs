@@ -0,0 +1,269 @@
+use crate::shared::*;
+use rayon::prelude::*;
+
+pub struct GeneratedWorld {
+    pub fg_tiles: Box<[Tile]>,
+    pub bg_tiles: Box<[Tile]>,
+    pub liquids: Box<[Liquid]>,
+
+    // First solid row of every column.
+    pub surface: Box<[usize]>,
+
+    // Spawn point, in tiles. spawn_y is the surface row under the spawn.
+    pub spawn_x: usize,
+    pub spawn_y: usize,
+}
+
+// Salts used to derive independent noise fields from a single seed.
+const SURFACE_SALT: u64 = 0x01;
+const BUMP_SALT: u64 = 0x02;
+const DIRT_SALT: u64 = 0x03;
+const DENSE_SALT: u64 = 0x04;
+const POCKET_SALT: u64 = 0x05;
+const TUNNEL_SALT: u64 = 0x06;
+const CAVERN_SALT: u64 = 0x07;
+const LIQUID_SALT: u64 = 0x08;
+
+pub fn generate_world(seed: u64, world_w: usize, world_h: usize) -> GeneratedWorld {
+    assert!(world_size_is_valid(world_w, world_h));
+
+    // Terrain heightmap.
+    let base = (world_h / 20).max(24);
+    let amplitude = (world_h / 60).clamp(4, 40) as f32;
+    let surface: Box<[usize]> = (0..world_w)
+        .map(|x| {
+            let hills = fractal_1d(seed ^ SURFACE_SALT, x as f32 / 96., 4) * 2. - 1.;
+            let bumps = fractal_1d(seed ^ BUMP_SALT, x as f32 / 8., 2) * 2. - 1.;
+            let y = base as f32 + hills * amplitude + bumps * 2.;
+            (y as usize).clamp(8, world_h / 2)
+        })
+        .collect();
+
+    // Strata depths, relative to the surface.
+    let dirt_depth: Box<[usize]> = (0..world_w)
+        .map(|x| 4 + (fractal_1d(seed ^ DIRT_SALT, x as f32 / 32., 2) * 8.) as usize)
+        .collect();
+    let dense_depth: Box<[usize]> = (0..world_w)
+        .map(|x| 30 + (fractal_1d(seed ^ DENSE_SALT, x as f32 / 64., 3) * 20.) as usize)
+        .collect();
+
+    // Spawn in the middle of the world, on the surface.
+    let spawn_x = world_w / 2;
+    let spawn_y = surface[spawn_x];
+
+    // Fill tiles, row by row.
+    let mut fg_tiles = vec![Tile::None; world_w * world_h].into_boxed_slice();
+    let mut bg_tiles = vec![Tile::None; world_w * world_h].into_boxed_slice();
+    let mut liquids = vec![Liquid::NONE; world_w * world_h].into_boxed_slice();
+    fg_tiles
+        .par_chunks_mut(world_w)
+        .zip(bg_tiles.par_chunks_mut(world_w))
+        .zip(liquids.par_chunks_mut(world_w))
+        .enumerate()
+        .for_each(|(y, ((fg_row, bg_row), liquid_row))| {
+            for x in 0..world_w {
+                // World border.
+                if y == 0 || y == world_h - 1 || x == 0 || x == world_w - 1 {
+                    fg_row[x] = Tile::Dirt;
+                    bg_row[x] = Tile::Dirt;
+                    continue;
+                }
+
+                // Sky.
+                let surface_y = surface[x];
+                if y < surface_y {
+                    continue;
+                }
+
+                // Strata.
+                let depth = y - surface_y;
+                let mut tile = if depth < dirt_depth[x] {
+                    Tile::Dirt
+                } else if depth < dense_depth[x] {
+                    Tile::Stone
+                } else {
+                    Tile::DenseStone
+                };
+
+                // Pockets of softer material in the deeper layers.
+                let (fx, fy) = (x as f32, y as f32);
+                let pocket = fractal_2d(seed ^ POCKET_SALT, fx / 12., fy / 12., 2);
+                tile = match tile {
+                    Tile::Stone if pocket > 0.72 => Tile::Dirt,
+                    Tile::DenseStone if pocket > 0.70 => Tile::Stone,
+                    tile => tile,
+                };
+                bg_row[x] = tile;
+
+                // Caves only carve the foreground, and never too close to the surface or spawn.
+                let near_spawn = x.abs_diff(spawn_x) < 8 && depth < 16;
+                if depth > 8 && !near_spawn {
+                    // Cave density increases with depth.
+                    let bias = (depth as f32 / world_h as f32).min(0.5) * 0.1;
+
+                    // Long winding tunnels.
+                    let tunnel = fractal_2d(seed ^ TUNNEL_SALT, fx / 48., fy / 32., 3);
+                    if (tunnel - 0.5).abs() < 0.02 + bias * 0.5 {
+                        continue;
+                    }
+
+                    // Large open caverns, some flooded. Lava down in the dense layer, water above.
+                    let cavern = fractal_2d(seed ^ CAVERN_SALT, fx / 24., fy / 16., 3);
+                    if cavern > 0.74 - bias {
+                        let flood = fractal_2d(seed ^ LIQUID_SALT, fx / 64., fy / 64., 2);
+                        if flood > 0.65 {
+                            let kind = match tile {
+                                Tile::DenseStone => LiquidKind::Lava,
+                                _ => LiquidKind::Water,
+                            };
+                            liquid_row[x] = Liquid::new(kind, LIQUID_MAX);
+                        }
+                        continue;
+                    }
+                }
+
+                fg_row[x] = tile;
+            }
+        });
+
+    GeneratedWorld {
+        fg_tiles,
+        bg_tiles,
+        liquids,
+        surface,
+        spawn_x,
+        spawn_y,
+    }
+}
+
+// Hashes a lattice point to [0, 1).
+fn lattice(seed: u64, x: i64, y: i64) -> f32 {
+    let h = mix64(
+        seed.wrapping_mul(0x2545_F491_4F6C_DD1D)
+            ^ (x as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15)
+            ^ (y as u64).wrapping_mul(0xC2B2_AE3D_27D4_EB4F),
+    );
+    (h >> 40) as f32 / (1u64 << 24) as f32
+}
+
+fn smooth(t: f32) -> f32 {
+    t * t * (3. - 2. * t)
+}
+
+fn value_1d(seed: u64, x: f32) -> f32 {
+    let x0 = x.floor();
+    let t = smooth(x - x0);
+    let a = lattice(seed, x0 as i64, 0);
+    let b = lattice(seed, x0 as i64 + 1, 0);
+    a + (b - a) * t
+}
+
+fn value_2d(seed: u64, x: f32, y: f32) -> f32 {
+    let (x0, y0) = (x.floor(), y.floor());
+    let (tx, ty) = (smooth(x - x0), smooth(y - y0));
+    let (ix, iy) = (x0 as i64, y0 as i64);
+    let a = lattice(seed, ix, iy);
+    let b = lattice(seed, ix + 1, iy);
+    let c = lattice(seed, ix, iy + 1);
+    let d = lattice(seed, ix + 1, iy + 1);
+    let top = a + (b - a) * tx;
+    let bottom = c + (d - c) * tx;
+    top + (bottom - top) * ty
+}
+
+// Sums octaves of value noise, normalized to [0, 1).
+fn fractal_1d(seed: u64, x: f32, octaves: u32) -> f32 {
+    let (mut sum, mut norm, mut amp, mut freq) = (0., 0., 1., 1.);
+    for octave in 0..octaves {
+        sum += value_1d(seed.wrapping_add(octave as u64), x * freq) * amp;
+        norm += amp;
+        amp *= 0.5;
+        freq *= 2.;
+    }
+    sum / norm
+}
+
+fn fractal_2d(seed: u64, x: f32, y: f32, octaves: u32) -> f32 {
+    let (mut sum, mut norm, mut amp, mut freq) = (0., 0., 1., 1.);
+    for octave in 0..octaves {
+        sum += value_2d(seed.wrapping_add(octave as u64), x * freq, y * freq) * amp;
+        norm += amp;
+        amp *= 0.5;
+        freq *= 2.;
+    }
+    sum / norm
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn deterministic_for_seed() {
+        let a = generate_world(1234, 512, 256);
+        let b = generate_world(1234, 512, 256);
+        assert!(a.fg_tiles == b.fg_tiles);
+        assert!(a.bg_tiles == b.bg_tiles);
+        assert!(a.liquids == b.liquids);
+        assert_eq!(a.surface, b.surface);
+        assert_eq!((a.spawn_x, a.spawn_y), (b.spawn_x, b.spawn_y));
+
+        let c = generate_world(4321, 512, 256);
+        assert!(a.fg_tiles != c.fg_tiles);
+    }
+
+    #[test]
+    fn border_is_solid() {
+        let (w, h) = (256, 128);
+        let world = generate_world(7, w, h);
+        for x in 0..w {
+            assert_ne!(world.fg_tiles[x], Tile::None);
+            assert_ne!(world.fg_tiles[x + (h - 1) * w], Tile::None);
+        }
+        for y in 0..h {
+            assert_ne!(world.fg_tiles[y * w], Tile::None);
+            assert_ne!(world.fg_tiles[w - 1 + y * w], Tile::None);
+        }
+    }
+
+    #[test]
+    fn spawn_is_on_surface() {
+        let w = 512;
+        let world = generate_world(99, w, 256);
+        let (x, y) = (world.spawn_x, world.spawn_y);
+
+        // Open sky above, ground below.
+        for y in 1..y {
+            assert_eq!(world.fg_tiles[x + y * w], Tile::None);
+        }
+        assert_eq!(world.fg_tiles[x + y * w], Tile::Dirt);
+    }
+
+    #[test]
+    fn has_caves_and_pockets() {
+        let (w, h) = (512, 256);
+        let world = generate_world(5, w, h);
+
+        // Carved foreground with a background wall behind it.
+        let caves = (0..w * h)
+            .filter(|&i| world.fg_tiles[i] == Tile::None && world.bg_tiles[i] != Tile::None)
+            .count();
+        assert!(caves > 0);
+
+        // Dirt found below the dirt layer.
+        let pockets = (0..w * h)
+            .filter(|&i| {
+                let (x, y) = (i % w, i / w);
+                x > 0 && x < w - 1 && y < h - 1 && y > world.surface[x] + 16
+            })
+            .filter(|&i| world.bg_tiles[i] == Tile::Dirt)
+            .count();
+        assert!(pockets > 0);
+
+        // Some caverns are flooded, and liquid is only ever in open space.
+        assert!(world.liquids.iter().any(|liquid| !liquid.is_empty()));
+        assert!((0..w * h)
+            .filter(|&i| !world.liquids[i].is_empty())
+            .all(|i| world.fg_tiles[i] == Tile::None));
+    }
+}
@@ -0,0 +1,668 @@
+use crate::shared::*;
+use bitcode::{decode, encode, Decode, DecodeOwned, Encode};
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+use rayon::prelude::*;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::path::Path;
+
+// File layout:
+//   [magic: 4 bytes][version: u32 le]
+//   [header block][humanoid block][player block][region block]*
+// Every block is [len: u32 le][deflated bitcode payload].
+const WORLD_MAGIC: [u8; 4] = *b"DPWD";
+pub const WORLD_FORMAT_VERSION: u32 = 5;
+
+// Version 1 had no player block, and still loads.
+const WORLD_FORMAT_VERSION_NO_PLAYERS: u32 = 1;
+
+// Versions 1 and 2 stored tiles as a hardcoded enum, and still load.
+const WORLD_FORMAT_VERSION_TILE_ENUM: u32 = 2;
+
+// Version 3 had no liquids, and still loads.
+const WORLD_FORMAT_VERSION_NO_LIQUIDS: u32 = 3;
+
+// Version 4 had no inventories, and still loads.
+const WORLD_FORMAT_VERSION_NO_INVENTORIES: u32 = 4;
+
+// Regions are square groups of chunks, compressed independently.
+const REGION_CHUNKS: usize = 32;
+const REGION_TILES: usize = REGION_CHUNKS * CHUNK_SIZE;
+
+const COMPRESSION_LEVEL: u8 = 6;
+
+// Largest the header block may inflate to. It's a few dozen bytes.
+const MAX_HEADER_SIZE: usize = 1024;
+
+#[derive(Debug)]
+pub enum WorldSaveErr {
+    Io(std::io::Error),
+    BadMagic,
+    UnsupportedVersion(u32),
+    Corrupt(&'static str),
+}
+
+impl std::fmt::Display for WorldSaveErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "io error: {err}"),
+            Self::BadMagic => write!(f, "not a world file"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported world version {version} (expected {WORLD_FORMAT_VERSION})"
+            ),
+            Self::Corrupt(what) => write!(f, "corrupt world file: {what}"),
+        }
+    }
+}
+
+impl From<std::io::Error> for WorldSaveErr {
+    fn from(err: std::io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct WorldHeader {
+    pub width: u32,
+    pub height: u32,
+    pub seed: u64,
+    pub spawn_x: u32,
+    pub spawn_y: u32,
+    pub humanoid_id_counter: HumanoidId,
+}
+
+// What is remembered about a player between sessions, by token.
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub struct PlayerRecord {
+    pub name: String,
+    pub x: f32,
+    pub y: f32,
+    pub inventory: Inventory,
+}
+
+// What new players start out with.
+const STARTING_ITEMS: [(Tile, u32); 3] = [
+    (Tile::RedTorch, 20),
+    (Tile::GreenTorch, 20),
+    (Tile::BlueTorch, 20),
+];
+
+impl PlayerRecord {
+    pub fn new(name: String, x: f32, y: f32) -> Self {
+        let mut inventory = Inventory::new();
+        for (tile, count) in STARTING_ITEMS {
+            inventory.add(Item::Tile(tile), count);
+        }
+        Self {
+            name,
+            x,
+            y,
+            inventory,
+        }
+    }
+}
+
+#[derive(Encode, Decode)]
+struct NoInventoryPlayerRecord {
+    name: String,
+    x: f32,
+    y: f32,
+}
+
+impl From<NoInventoryPlayerRecord> for PlayerRecord {
+    // Torches used to be free, so players from back then get the same start as new ones.
+    fn from(record: NoInventoryPlayerRecord) -> Self {
+        Self::new(record.name, record.x, record.y)
+    }
+}
+
+// Borrowed view of everything that gets written.
+pub struct WorldSaveRef<'a> {
+    pub header: WorldHeader,
+    pub chunk_seqs: &'a [u32],
+    pub fg_tiles: &'a [Tile],
+    pub bg_tiles: &'a [Tile],
+    pub liquids: &'a [Liquid],
+    pub humanoids: &'a HashMap<HumanoidId, Humanoid>,
+    pub players: &'a HashMap<PlayerToken, PlayerRecord>,
+}
+
+pub struct WorldSave {
+    pub header: WorldHeader,
+    pub chunk_seqs: Box<[u32]>,
+    pub fg_tiles: Box<[Tile]>,
+    pub bg_tiles: Box<[Tile]>,
+    pub liquids: Box<[Liquid]>,
+    pub humanoids: HashMap<HumanoidId, Humanoid>,
+    pub players: HashMap<PlayerToken, PlayerRecord>,
+}
+
+#[derive(Encode, Decode)]
+struct Region {
+    // Region position, in regions.
+    x: u32,
+    y: u32,
+
+    // Row-major, clipped to the world bounds.
+    chunk_seqs: Vec<u32>,
+    fg_tiles: Vec<Tile>,
+    bg_tiles: Vec<Tile>,
+    liquids: Vec<Liquid>,
+}
+
+#[derive(Encode, Decode)]
+struct NoLiquidsRegion {
+    x: u32,
+    y: u32,
+    chunk_seqs: Vec<u32>,
+    fg_tiles: Vec<Tile>,
+    bg_tiles: Vec<Tile>,
+}
+
+impl From<NoLiquidsRegion> for Region {
+    fn from(region: NoLiquidsRegion) -> Self {
+        Self {
+            x: region.x,
+            y: region.y,
+            chunk_seqs: region.chunk_seqs,
+            liquids: vec![Liquid::NONE; region.fg_tiles.len()],
+            fg_tiles: region.fg_tiles,
+            bg_tiles: region.bg_tiles,
+        }
+    }
+}
+
+// Tiles before the registry, encoded as an enum. Never change.
+#[derive(Copy, Clone, Encode, Decode)]
+enum LegacyTile {
+    None,
+    Dirt,
+    Stone,
+    DenseStone,
+    RedTorch,
+    GreenTorch,
+    BlueTorch,
+    Size,
+}
+
+#[derive(Encode, Decode)]
+struct LegacyRegion {
+    x: u32,
+    y: u32,
+    chunk_seqs: Vec<u32>,
+    fg_tiles: Vec<LegacyTile>,
+    bg_tiles: Vec<LegacyTile>,
+}
+
+impl From<LegacyRegion> for NoLiquidsRegion {
+    // The registry kept the enum's ids. Size was never a tile, and its id is taken now.
+    fn from(region: LegacyRegion) -> Self {
+        let tile = |tile: LegacyTile| match tile {
+            LegacyTile::Size => Tile(u8::MAX),
+            tile => Tile(tile as u8),
+        };
+        let tiles = |tiles: Vec<LegacyTile>| tiles.into_iter().map(tile).collect();
+        Self {
+            x: region.x,
+            y: region.y,
+            chunk_seqs: region.chunk_seqs,
+            fg_tiles: tiles(region.fg_tiles),
+            bg_tiles: tiles(region.bg_tiles),
+        }
+    }
+}
+
+pub fn save_world(path: &Path, world: &WorldSaveRef) -> Result<(), WorldSaveErr> {
+    let world_w = world.header.width as usize;
+    let world_h = world.header.height as usize;
+    let chunks_w = world_w / CHUNK_SIZE;
+    let chunks_h = world_h / CHUNK_SIZE;
+    assert_eq!(world.chunk_seqs.len(), chunks_w * chunks_h);
+    assert_eq!(world.fg_tiles.len(), world_w * world_h);
+    assert_eq!(world.bg_tiles.len(), world_w * world_h);
+    assert_eq!(world.liquids.len(), world_w * world_h);
+
+    // Compress every region in parallel.
+    let regions_w = chunks_w.div_ceil(REGION_CHUNKS);
+    let regions_h = chunks_h.div_ceil(REGION_CHUNKS);
+    let regions: Vec<Vec<u8>> = (0..regions_w * regions_h)
+        .into_par_iter()
+        .map(|index| {
+            let (rx, ry) = (index % regions_w, index / regions_w);
+            let cxr = rx * REGION_CHUNKS..((rx + 1) * REGION_CHUNKS).min(chunks_w);
+            let cyr = ry * REGION_CHUNKS..((ry + 1) * REGION_CHUNKS).min(chunks_h);
+            let xr = rx * REGION_TILES..((rx + 1) * REGION_TILES).min(world_w);
+            let yr = ry * REGION_TILES..((ry + 1) * REGION_TILES).min(world_h);
+
+            let mut region = Region {
+                x: rx as u32,
+                y: ry as u32,
+                chunk_seqs: Vec::with_capacity(cxr.len() * cyr.len()),
+                fg_tiles: Vec::with_capacity(xr.len() * yr.len()),
+                bg_tiles: Vec::with_capacity(xr.len() * yr.len()),
+                liquids: Vec::with_capacity(xr.len() * yr.len()),
+            };
+            for cy in cyr {
+                let row = cy * chunks_w;
+                region
+                    .chunk_seqs
+                    .extend_from_slice(&world.chunk_seqs[row + cxr.start..row + cxr.end]);
+            }
+            for y in yr {
+                let row = y * world_w;
+                region
+                    .fg_tiles
+                    .extend_from_slice(&world.fg_tiles[row + xr.start..row + xr.end]);
+                region
+                    .bg_tiles
+                    .extend_from_slice(&world.bg_tiles[row + xr.start..row + xr.end]);
+                region
+                    .liquids
+                    .extend_from_slice(&world.liquids[row + xr.start..row + xr.end]);
+            }
+
+            compress_to_vec(&encode(&region), COMPRESSION_LEVEL)
+        })
+        .collect();
+
+    // Player humanoids are owned by their connection, and respawn from their record on join.
+    let humanoids: HashMap<HumanoidId, Humanoid> = world
+        .humanoids
+        .iter()
+        .filter(|(_, humanoid)| !matches!(humanoid.ai, HumanoidAi::Player))
+        .map(|(id, humanoid)| (*id, humanoid.clone()))
+        .collect();
+
+    // Write to a temporary file first, so a crash mid-save never eats the old world.
+    let tmp_path = path.with_extension("tmp");
+    {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&tmp_path)?);
+        file.write_all(&WORLD_MAGIC)?;
+        file.write_all(&WORLD_FORMAT_VERSION.to_le_bytes())?;
+        write_block(
+            &mut file,
+            &compress_to_vec(&encode(&world.header), COMPRESSION_LEVEL),
+        )?;
+        write_block(
+            &mut file,
+            &compress_to_vec(&encode(&humanoids), COMPRESSION_LEVEL),
+        )?;
+        write_block(
+            &mut file,
+            &compress_to_vec(&encode(world.players), COMPRESSION_LEVEL),
+        )?;
+        for region in &regions {
+            write_block(&mut file, region)?;
+        }
+        file.flush()?;
+    }
+    std::fs::rename(&tmp_path, path)?;
+
+    Ok(())
+}
+
+pub fn load_world(path: &Path) -> Result<WorldSave, WorldSaveErr> {
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)?.read_to_end(&mut bytes)?;
+    let mut bytes = &bytes[..];
+
+    // Magic and version.
+    if bytes.len() < 8 || bytes[0..4] != WORLD_MAGIC {
+        return Err(WorldSaveErr::BadMagic);
+    }
+    let version = u32::from_le_bytes(bytes[4..8].try_into().unwrap());
+    if !(WORLD_FORMAT_VERSION_NO_PLAYERS..=WORLD_FORMAT_VERSION).contains(&version) {
+        return Err(WorldSaveErr::UnsupportedVersion(version));
+    }
+    bytes = &bytes[8..];
+
+    // Header.
+    let header: WorldHeader = read_block(&mut bytes, MAX_HEADER_SIZE)?;
+    let world_w = header.width as usize;
+    let world_h = header.height as usize;
+    // The size comes from the file, so check it before allocating anything that large.
+    if world_w == 0
+        || world_h == 0
+        || !world_w.is_multiple_of(CHUNK_SIZE)
+        || !world_h.is_multiple_of(CHUNK_SIZE)
+        || world_w > u16::MAX as usize
+        || world_h > u16::MAX as usize
+        || world_w * world_h > MAX_WORLD_AREA
+    {
+        return Err(WorldSaveErr::Corrupt("bad world size"));
+    }
+    let chunks_w = world_w / CHUNK_SIZE;
+    let chunks_h = world_h / CHUNK_SIZE;
+
+    // No block holds more than one of each layer per tile (with room to spare), so a small file
+    // can't inflate to more than the world it describes.
+    let max_block =
+        world_w * world_h * (2 * size_of::<Tile>() + size_of::<Liquid>() + size_of::<u32>())
+            + MAX_HEADER_SIZE;
+
+    // Humanoids.
+    let humanoids: HashMap<HumanoidId, Humanoid> = read_block(&mut bytes, max_block)?;
+
+    // Players.
+    let players: HashMap<PlayerToken, PlayerRecord> = match version {
+        WORLD_FORMAT_VERSION_NO_PLAYERS => HashMap::new(),
+        WORLD_FORMAT_VERSION_TILE_ENUM
+        | WORLD_FORMAT_VERSION_NO_LIQUIDS
+        | WORLD_FORMAT_VERSION_NO_INVENTORIES => {
+            read_block::<HashMap<PlayerToken, NoInventoryPlayerRecord>>(&mut bytes, max_block)?
+                .into_iter()
+                .map(|(token, record)| (token, record.into()))
+                .collect()
+        }
+        _ => read_block(&mut bytes, max_block)?,
+    };
+    if !players.values().all(|record| record.inventory.is_valid()) {
+        return Err(WorldSaveErr::Corrupt("bad inventory"));
+    }
+
+    // Regions.
+    let regions_w = chunks_w.div_ceil(REGION_CHUNKS);
+    let regions_h = chunks_h.div_ceil(REGION_CHUNKS);
+    let mut seen = vec![false; regions_w * regions_h];
+    let mut chunk_seqs = vec![0; chunks_w * chunks_h].into_boxed_slice();
+    let mut fg_tiles = vec![Tile::None; world_w * world_h].into_boxed_slice();
+    let mut bg_tiles = vec![Tile::None; world_w * world_h].into_boxed_slice();
+    let mut liquids = vec![Liquid::NONE; world_w * world_h].into_boxed_slice();
+    while !bytes.is_empty() {
+        let region: Region = match version {
+            WORLD_FORMAT_VERSION_NO_PLAYERS | WORLD_FORMAT_VERSION_TILE_ENUM => {
+                NoLiquidsRegion::from(read_block::<LegacyRegion>(&mut bytes, max_block)?).into()
+            }
+            WORLD_FORMAT_VERSION_NO_LIQUIDS => {
+                read_block::<NoLiquidsRegion>(&mut bytes, max_block)?.into()
+            }
+            _ => read_block(&mut bytes, max_block)?,
+        };
+        let (rx, ry) = (region.x as usize, region.y as usize);
+        if rx >= regions_w || ry >= regions_h || seen[rx + ry * regions_w] {
+            return Err(WorldSaveErr::Corrupt("bad region position"));
+        }
+        seen[rx + ry * regions_w] = true;
+
+        let cxr = rx * REGION_CHUNKS..((rx + 1) * REGION_CHUNKS).min(chunks_w);
+        let cyr = ry * REGION_CHUNKS..((ry + 1) * REGION_CHUNKS).min(chunks_h);
+        let xr = rx * REGION_TILES..((rx + 1) * REGION_TILES).min(world_w);
+        let yr = ry * REGION_TILES..((ry + 1) * REGION_TILES).min(world_h);
+        if region.chunk_seqs.len() != cxr.len() * cyr.len()
+            || region.fg_tiles.len() != xr.len() * yr.len()
+            || region.bg_tiles.len() != xr.len() * yr.len()
+            || region.liquids.len() != xr.len() * yr.len()
+        {
+            return Err(WorldSaveErr::Corrupt("bad region size"));
+        }
+        if !region
+            .fg_tiles
+            .iter()
+            .chain(&region.bg_tiles)
+            .all(|tile| tile.is_registered())
+        {
+            return Err(WorldSaveErr::Corrupt("unknown tile"));
+        }
+        if !region.liquids.iter().all(|liquid| liquid.is_valid()) {
+            return Err(WorldSaveErr::Corrupt("bad liquid"));
+        }
+
+        for (i, cy) in cyr.enumerate() {
+            let row = cy * chunks_w;
+            let src = &region.chunk_seqs[i * cxr.len()..(i + 1) * cxr.len()];
+            chunk_seqs[row + cxr.start..row + cxr.end].copy_from_slice(src);
+        }
+        for (i, y) in yr.enumerate() {
+            let row = y * world_w;
+            let src = i * xr.len()..(i + 1) * xr.len();
+            fg_tiles[row + xr.start..row + xr.end].copy_from_slice(&region.fg_tiles[src.clone()]);
+            bg_tiles[row + xr.start..row + xr.end].copy_from_slice(&region.bg_tiles[src.clone()]);
+            liquids[row + xr.start..row + xr.end].copy_from_slice(&region.liquids[src]);
+        }
+    }
+    if seen.contains(&false) {
+        return Err(WorldSaveErr::Corrupt("missing region"));
+    }
+
+    Ok(WorldSave {
+        header,
+        chunk_seqs,
+        fg_tiles,
+        bg_tiles,
+        liquids,
+        humanoids,
+        players,
+    })
+}
+
+fn write_block(w: &mut impl Write, block: &[u8]) -> std::io::Result<()> {
+    w.write_all(&(block.len() as u32).to_le_bytes())?;
+    w.write_all(block)
+}
+
+fn read_block<T: DecodeOwned>(bytes: &mut &[u8], max_size: usize) -> Result<T, WorldSaveErr> {
+    if bytes.len() < 4 {
+        return Err(WorldSaveErr::Corrupt("truncated block header"));
+    }
+    let len = u32::from_le_bytes(bytes[0..4].try_into().unwrap()) as usize;
+    if bytes.len() < 4 + len {
+        return Err(WorldSaveErr::Corrupt("truncated block"));
+    }
+    let block = &bytes[4..4 + len];
+    *bytes = &bytes[4 + len..];
+
+    let inflated = decompress_to_vec_with_limit(block, max_size)
+        .map_err(|_| WorldSaveErr::Corrupt("bad deflate"))?;
+    decode(&inflated).map_err(|_| WorldSaveErr::Corrupt("bad block"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::generate_world;
+
+    #[test]
+    fn round_trip() {
+        // Not a multiple of the region size, to exercise clipping.
+        let (w, h) = (400, 320);
+        let world = generate_world(42, w, h);
+        let chunk_seqs: Box<[u32]> = (0..(w / CHUNK_SIZE) * (h / CHUNK_SIZE))
+            .map(|i| i as u32)
+            .collect();
+        let mut counter = HumanoidId::new();
+        let mut humanoids = HashMap::new();
+        let zombie = Humanoid {
+            base: HumanoidBase {
+                x: 12.,
+                y: 34.,
+                w: 24.,
+                h: 40.,
+                flags: 0,
+            },
+            ai: HumanoidAi::Zombie,
+            input: HumanoidInput::default(),
+            physics: HumanoidPhysics::default(),
+        };
+        let player = Humanoid {
+            ai: HumanoidAi::Player,
+            ..zombie.clone()
+        };
+        let zombie_id = counter.next();
+        humanoids.insert(zombie_id, zombie);
+        humanoids.insert(counter.next(), player);
+        let mut record = PlayerRecord::new("Steve".to_string(), 56., 78.);
+        record.inventory.add(Item::Tile(Tile::Sand), 1234);
+        record.inventory.move_stack(0, 12);
+        let players = HashMap::from([(PlayerToken(1234), record.clone())]);
+
+        let path = std::env::temp_dir().join(format!("round_trip_{}.world", std::process::id()));
+        save_world(
+            &path,
+            &WorldSaveRef {
+                header: WorldHeader {
+                    width: w as u32,
+                    height: h as u32,
+                    seed: 42,
+                    spawn_x: world.spawn_x as u32,
+                    spawn_y: world.spawn_y as u32,
+                    humanoid_id_counter: counter,
+                },
+                chunk_seqs: &chunk_seqs,
+                fg_tiles: &world.fg_tiles,
+                bg_tiles: &world.bg_tiles,
+                liquids: &world.liquids,
+                humanoids: &humanoids,
+                players: &players,
+            },
+        )
+        .unwrap();
+        let loaded = load_world(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            (loaded.header.width, loaded.header.height),
+            (w as u32, h as u32)
+        );
+        assert_eq!(loaded.header.seed, 42);
+        assert_eq!(loaded.header.humanoid_id_counter, counter);
+        assert_eq!(loaded.chunk_seqs, chunk_seqs);
+        assert!(loaded.fg_tiles == world.fg_tiles);
+        assert!(loaded.bg_tiles == world.bg_tiles);
+        assert!(loaded.liquids == world.liquids);
+
+        // Only the zombie is persisted, the player lives on in its record.
+        assert_eq!(loaded.humanoids.len(), 1);
+        assert_eq!(loaded.humanoids[&zombie_id].base.x, 12.);
+        assert_eq!(loaded.players, players);
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let path = std::env::temp_dir().join(format!("bad_{}.world", std::process::id()));
+
+        std::fs::write(&path, b"nope").unwrap();
+        assert!(matches!(load_world(&path), Err(WorldSaveErr::BadMagic)));
+
+        let mut bytes = WORLD_MAGIC.to_vec();
+        bytes.extend_from_slice(&(WORLD_FORMAT_VERSION + 1).to_le_bytes());
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            load_world(&path),
+            Err(WorldSaveErr::UnsupportedVersion(_))
+        ));
+
+        let mut bytes = WORLD_MAGIC.to_vec();
+        bytes.extend_from_slice(&WORLD_FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&[255, 0, 0, 0, 1, 2, 3]);
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(load_world(&path), Err(WorldSaveErr::Corrupt(_))));
+
+        // A header claiming a huge world is turned down before anything gets allocated.
+        let header = WorldHeader {
+            width: 1 << 30,
+            height: 1 << 30,
+            seed: 0,
+            spawn_x: 0,
+            spawn_y: 0,
+            humanoid_id_counter: HumanoidId::new(),
+        };
+        let mut bytes = WORLD_MAGIC.to_vec();
+        bytes.extend_from_slice(&WORLD_FORMAT_VERSION.to_le_bytes());
+        write_block(
+            &mut bytes,
+            &compress_to_vec(&encode(&header), COMPRESSION_LEVEL),
+        )
+        .unwrap();
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            load_world(&path),
+            Err(WorldSaveErr::Corrupt("bad world size"))
+        ));
+
+        // Neither is a block that inflates to more than the world could hold.
+        let header = WorldHeader {
+            width: CHUNK_SIZE as u32,
+            height: CHUNK_SIZE as u32,
+            ..header
+        };
+        let mut bytes = WORLD_MAGIC.to_vec();
+        bytes.extend_from_slice(&WORLD_FORMAT_VERSION.to_le_bytes());
+        write_block(
+            &mut bytes,
+            &compress_to_vec(&encode(&header), COMPRESSION_LEVEL),
+        )
+        .unwrap();
+        write_block(
+            &mut bytes,
+            &compress_to_vec(&vec![0; 1 << 24], COMPRESSION_LEVEL),
+        )
+        .unwrap();
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(matches!(
+            load_world(&path),
+            Err(WorldSaveErr::Corrupt("bad deflate"))
+        ));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn loads_tile_enum_worlds() {
+        // One chunk, as version 2 wrote it.
+        let header = WorldHeader {
+            width: CHUNK_SIZE as u32,
+            height: CHUNK_SIZE as u32,
+            seed: 42,
+            spawn_x: 0,
+            spawn_y: 0,
+            humanoid_id_counter: HumanoidId::new(),
+        };
+        let bytes = |fg_tile: LegacyTile| {
+            let region = LegacyRegion {
+                x: 0,
+                y: 0,
+                chunk_seqs: vec![1],
+                fg_tiles: vec![fg_tile; CHUNK_AREA],
+                bg_tiles: vec![LegacyTile::BlueTorch; CHUNK_AREA],
+            };
+            let mut bytes = WORLD_MAGIC.to_vec();
+            bytes.extend_from_slice(&WORLD_FORMAT_VERSION_TILE_ENUM.to_le_bytes());
+            let blocks = [
+                encode(&header),
+                encode(&HashMap::<HumanoidId, Humanoid>::new()),
+                encode(&HashMap::from([(
+                    PlayerToken(1),
+                    NoInventoryPlayerRecord {
+                        name: String::from("Steve"),
+                        x: 1.,
+                        y: 2.,
+                    },
+                )])),
+                encode(&region),
+            ];
+            for block in blocks {
+                write_block(&mut bytes, &compress_to_vec(&block, COMPRESSION_LEVEL)).unwrap();
+            }
+            bytes
+        };
+
+        let path = std::env::temp_dir().join(format!("tile_enum_{}.world", std::process::id()));
+        std::fs::write(&path, bytes(LegacyTile::DenseStone)).unwrap();
+        let loaded = load_world(&path).unwrap();
+        assert!(loaded.fg_tiles.iter().all(|&tile| tile == Tile::DenseStone));
+        assert!(loaded.bg_tiles.iter().all(|&tile| tile == Tile::BlueTorch));
+        assert!(loaded.liquids.iter().all(|liquid| liquid.is_empty()));
+        let record = &loaded.players[&PlayerToken(1)];
+        assert_eq!((record.x, record.y), (1., 2.));
+        assert_eq!(record.inventory.count(Item::Tile(Tile::RedTorch)), 20);
+
+        std::fs::write(&path, bytes(LegacyTile::Size)).unwrap();
+        assert!(matches!(
+            load_world(&path),
+            Err(WorldSaveErr::Corrupt("unknown tile"))
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
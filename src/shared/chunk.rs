@@ -0,0 +1,245 @@
+use crate::shared::{Liquid, Tile, CHUNK_AREA, CHUNK_SIZE, MAX_WORLD_AREA, MIN_WORLD_SIZE};
+use bitcode::{decode, encode, Decode, Encode};
+use miniz_oxide::deflate::compress_to_vec;
+use miniz_oxide::inflate::decompress_to_vec_with_limit;
+
+// Batches are kept below this many bytes (before deflating), so one fits in a single packet.
+pub const MAX_CHUNK_BATCH_SIZE: usize = 1024;
+
+// Inflated batches larger than this are rejected.
+const MAX_INFLATED_SIZE: usize = 64 * 1024;
+
+const COMPRESSION_LEVEL: u8 = 6;
+
+// Worlds are whole chunks, at least MIN_WORLD_SIZE each way, and small enough for u16 tile
+// coordinates and for clients to load.
+pub fn world_size_is_valid(world_w: usize, world_h: usize) -> bool {
+    world_w >= MIN_WORLD_SIZE
+        && world_h >= MIN_WORLD_SIZE
+        && world_w.is_multiple_of(CHUNK_SIZE)
+        && world_h.is_multiple_of(CHUNK_SIZE)
+        && world_w <= u16::MAX as usize
+        && world_h <= u16::MAX as usize
+        && world_w * world_h <= MAX_WORLD_AREA
+}
+
+// A chunk's tiles as runs of (tile, length), fg then bg, and its liquids the same way.
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub struct ChunkData {
+    pub x: u16,
+    pub y: u16,
+    pub seq: u32,
+    pub runs: Vec<(Tile, u8)>,
+    pub liquids: Vec<(Liquid, u8)>,
+}
+
+// Several encoded chunks, deflated if that made them smaller.
+#[derive(Clone, Debug, Encode, Decode)]
+pub struct ChunkBatch {
+    pub deflated: bool,
+    pub bytes: Box<[u8]>,
+}
+
+pub fn encode_chunk_tiles(
+    fg_tiles: &[Tile; CHUNK_AREA],
+    bg_tiles: &[Tile; CHUNK_AREA],
+) -> Vec<(Tile, u8)> {
+    encode_runs(fg_tiles.iter().chain(bg_tiles))
+}
+
+// Returns None if the runs don't add up to a chunk, or hold unknown tiles.
+pub fn decode_chunk_tiles(runs: &[(Tile, u8)]) -> Option<([Tile; CHUNK_AREA], [Tile; CHUNK_AREA])> {
+    if !runs.iter().all(|(tile, _)| tile.is_registered()) {
+        return None;
+    }
+    let mut tiles = [Tile::None; 2 * CHUNK_AREA];
+    decode_runs(runs, &mut tiles)?;
+
+    let (fg_tiles, bg_tiles) = tiles.split_at(CHUNK_AREA);
+    Some((fg_tiles.try_into().unwrap(), bg_tiles.try_into().unwrap()))
+}
+
+pub fn encode_chunk_liquids(liquids: &[Liquid; CHUNK_AREA]) -> Vec<(Liquid, u8)> {
+    encode_runs(liquids.iter())
+}
+
+// Returns None if the runs don't add up to a chunk, or hold invalid liquids.
+pub fn decode_chunk_liquids(runs: &[(Liquid, u8)]) -> Option<[Liquid; CHUNK_AREA]> {
+    if !runs.iter().all(|(liquid, _)| liquid.is_valid()) {
+        return None;
+    }
+    let mut liquids = [Liquid::NONE; CHUNK_AREA];
+    decode_runs(runs, &mut liquids)?;
+    Some(liquids)
+}
+
+fn encode_runs<'a, T: Copy + PartialEq + 'a>(items: impl Iterator<Item = &'a T>) -> Vec<(T, u8)> {
+    let mut runs: Vec<(T, u8)> = Vec::new();
+    for &item in items {
+        match runs.last_mut() {
+            Some((last, len)) if *last == item && *len < u8::MAX => *len += 1,
+            _ => runs.push((item, 1)),
+        }
+    }
+    runs
+}
+
+// Fills out with the runs, which must add up to exactly its length.
+fn decode_runs<T: Copy>(runs: &[(T, u8)], out: &mut [T]) -> Option<()> {
+    let mut i = 0;
+    for &(item, len) in runs {
+        let run = out.get_mut(i..i + len as usize)?;
+        run.fill(item);
+        i += len as usize;
+    }
+    (i == out.len()).then_some(())
+}
+
+// Groups chunks into batches of at most max_size bytes each (as long as every chunk fits).
+pub fn pack_chunks(chunks: &[ChunkData], max_size: usize) -> Vec<ChunkBatch> {
+    let mut batches = Vec::new();
+    let mut start = 0;
+    let mut size = 0;
+    for (i, chunk) in chunks.iter().enumerate() {
+        let chunk_size = encode(chunk).len();
+        if size + chunk_size > max_size && i > start {
+            batches.push(pack_batch(&chunks[start..i]));
+            start = i;
+            size = 0;
+        }
+        size += chunk_size;
+    }
+    if start < chunks.len() {
+        batches.push(pack_batch(&chunks[start..]));
+    }
+    batches
+}
+
+fn pack_batch(chunks: &[ChunkData]) -> ChunkBatch {
+    let bytes = encode(chunks);
+    let deflated = compress_to_vec(&bytes, COMPRESSION_LEVEL);
+    if deflated.len() < bytes.len() {
+        ChunkBatch {
+            deflated: true,
+            bytes: deflated.into_boxed_slice(),
+        }
+    } else {
+        ChunkBatch {
+            deflated: false,
+            bytes: bytes.into_boxed_slice(),
+        }
+    }
+}
+
+pub fn unpack_chunks(batch: &ChunkBatch) -> Option<Vec<ChunkData>> {
+    if !batch.deflated {
+        return decode(&batch.bytes).ok();
+    }
+    let bytes = decompress_to_vec_with_limit(&batch.bytes, MAX_INFLATED_SIZE).ok()?;
+    decode(&bytes).ok()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::shared::{LiquidKind, Rng, LIQUID_MAX};
+
+    fn random_tiles(rng: &mut Rng) -> [Tile; CHUNK_AREA] {
+        let palette = [Tile::None, Tile::Dirt, Tile::Stone, Tile::RedTorch];
+        std::array::from_fn(|_| palette[rng.range(0, palette.len() as u64) as usize])
+    }
+
+    #[test]
+    fn tiles_round_trip() {
+        // Uniform chunks collapse to a run per layer.
+        let fg = [Tile::Stone; CHUNK_AREA];
+        let bg = [Tile::None; CHUNK_AREA];
+        let runs = encode_chunk_tiles(&fg, &bg);
+        assert_eq!(runs, vec![(Tile::Stone, 64), (Tile::None, 64)]);
+        assert_eq!(decode_chunk_tiles(&runs), Some((fg, bg)));
+
+        let mut rng = Rng::new(3);
+        for _ in 0..100 {
+            let (fg, bg) = (random_tiles(&mut rng), random_tiles(&mut rng));
+            let runs = encode_chunk_tiles(&fg, &bg);
+            assert_eq!(decode_chunk_tiles(&runs), Some((fg, bg)));
+        }
+
+        // Too short, too long.
+        assert_eq!(decode_chunk_tiles(&[(Tile::Dirt, 100)]), None);
+        assert_eq!(decode_chunk_tiles(&[(Tile(255), 128)]), None);
+        assert_eq!(
+            decode_chunk_tiles(&[(Tile::Dirt, 255), (Tile::Dirt, 2)]),
+            None
+        );
+    }
+
+    #[test]
+    fn liquids_round_trip() {
+        let mut liquids = [Liquid::NONE; CHUNK_AREA];
+        liquids[CHUNK_AREA / 2..].fill(Liquid::new(LiquidKind::Water, LIQUID_MAX));
+        liquids[3] = Liquid::new(LiquidKind::Lava, 5);
+        let runs = encode_chunk_liquids(&liquids);
+        assert_eq!(runs.len(), 4);
+        assert_eq!(decode_chunk_liquids(&runs), Some(liquids));
+
+        // Wrong length, or impossible levels.
+        assert_eq!(decode_chunk_liquids(&runs[1..]), None);
+        let overfull = Liquid {
+            kind: LiquidKind::Water,
+            level: LIQUID_MAX + 1,
+        };
+        assert_eq!(decode_chunk_liquids(&[(overfull, 64)]), None);
+        let dry_lava = Liquid {
+            kind: LiquidKind::Lava,
+            level: 0,
+        };
+        assert_eq!(decode_chunk_liquids(&[(dry_lava, 64)]), None);
+    }
+
+    #[test]
+    fn batches_round_trip() {
+        let mut rng = Rng::new(9);
+        let chunks: Vec<ChunkData> = (0..200)
+            .map(|i| {
+                // Mostly solid, some noisy.
+                let (fg, bg) = if i % 4 == 0 {
+                    (random_tiles(&mut rng), random_tiles(&mut rng))
+                } else {
+                    ([Tile::Stone; CHUNK_AREA], [Tile::Dirt; CHUNK_AREA])
+                };
+                ChunkData {
+                    x: i,
+                    y: i * 2,
+                    seq: i as u32,
+                    runs: encode_chunk_tiles(&fg, &bg),
+                    liquids: encode_chunk_liquids(&[Liquid::NONE; CHUNK_AREA]),
+                }
+            })
+            .collect();
+
+        let batches = pack_chunks(&chunks, MAX_CHUNK_BATCH_SIZE);
+        assert!(batches.len() > 1);
+        assert!(batches.iter().any(|batch| batch.deflated));
+        assert!(batches
+            .iter()
+            .all(|batch| batch.bytes.len() <= MAX_CHUNK_BATCH_SIZE));
+
+        let unpacked: Vec<ChunkData> = batches
+            .iter()
+            .flat_map(|batch| unpack_chunks(batch).unwrap())
+            .collect();
+        assert_eq!(unpacked, chunks);
+
+        // Much smaller than the raw tiles.
+        let packed: usize = batches.iter().map(|batch| batch.bytes.len()).sum();
+        assert!(packed < chunks.len() * 2 * CHUNK_AREA / 4);
+
+        // Garbage is rejected.
+        let garbage = ChunkBatch {
+            deflated: true,
+            bytes: Box::new([1, 2, 3]),
+        };
+        assert!(unpack_chunks(&garbage).is_none());
+    }
+}
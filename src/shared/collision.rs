@@ -148,8 +148,9 @@ impl<Group: Default + ColliderGroup, Data: Default> CollisionGroup<Group, Data>
     }
 }
 
+#[cfg(test)]
 mod test {
-    
+    use super::*;
 
     #[test]
     fn collision_test() {
@@ -1,4 +1,4 @@
-use crate::shared::{Tile, TILE_PHYSICS_PROPERTIES, TILE_SIZE};
+use crate::shared::{Liquid, Tile, LIQUID_MAX, TILE_PHYSICS_PROPERTIES, TILE_SIZE};
 use bitcode::{Decode, Encode};
 use std::collections::HashMap;
 
@@ -152,6 +152,35 @@ pub fn update_humanoid_ais(
     }
 }
 
+// Fastest a humanoid received from elsewhere may move (in pixels per second).
+const MAX_HUMANOID_SPEED: f32 = 16384.;
+
+// Whether a humanoid received from elsewhere can safely be simulated in a world of this size (in
+// tiles), i.e. it is inside the world border and its physics are sane.
+pub fn humanoid_is_valid(humanoid: &Humanoid, world_w: usize, world_h: usize) -> bool {
+    let Humanoid { base, physics, .. } = humanoid;
+    let (min_x, min_y) = (TILE_SIZE as f32, TILE_SIZE as f32);
+    let max_x = ((world_w.max(1) - 1) * TILE_SIZE) as f32;
+    let max_y = ((world_h.max(1) - 1) * TILE_SIZE) as f32;
+    let speed_is_valid = |v: f32| v.abs() <= MAX_HUMANOID_SPEED;
+
+    base.w > 0.
+        && base.h > 0.
+        && base.x >= min_x
+        && base.y >= min_y
+        && base.x + base.w <= max_x
+        && base.y + base.h <= max_y
+        && [
+            physics.max_dx,
+            physics.dx,
+            physics.dy,
+            physics.ddx,
+            physics.ddy,
+        ]
+        .into_iter()
+        .all(speed_is_valid)
+}
+
 pub fn update_humanoid_inputs(humanoids: &mut HashMap<HumanoidId, Humanoid>) {
     for Humanoid {
         ref mut base,
@@ -179,7 +208,9 @@ pub fn update_humanoid_inputs(humanoids: &mut HashMap<HumanoidId, Humanoid>) {
             .reduce(|b, acc| acc | b)
             .unwrap();
 
-        if jump_buffer && base.flags & HUMANOID_ON_GROUND_BIT != 0 {
+        // Swimming counts as standing on something.
+        let can_jump = base.flags & (HUMANOID_ON_GROUND_BIT | HUMANOID_IN_LIQUID_BIT) != 0;
+        if jump_buffer && can_jump {
             physics.dy -= 300.;
         }
 
@@ -190,7 +221,12 @@ pub fn update_humanoid_inputs(humanoids: &mut HashMap<HumanoidId, Humanoid>) {
     }
 }
 
-pub fn update_humanoid_physics(humanoids: &mut HashMap<HumanoidId, Humanoid>, ft: f32) {
+pub fn update_humanoid_physics(
+    humanoids: &mut HashMap<HumanoidId, Humanoid>,
+    stride: usize,
+    liquids: &[Liquid],
+    ft: f32,
+) {
     for Humanoid {
         ref mut base,
         ref mut physics,
@@ -200,6 +236,18 @@ pub fn update_humanoid_physics(humanoids: &mut HashMap<HumanoidId, Humanoid>, ft
         // Gravity.
         physics.ddy += 500.;
 
+        // Liquids push up and slow down, in proportion to how submerged the humanoid is.
+        let (buoyancy, drag) = humanoid_submersion(base, stride, liquids);
+        physics.ddy -= buoyancy;
+        let damping = (1. - drag * ft).max(0.);
+        physics.dx *= damping;
+        physics.dy *= damping;
+        if buoyancy > 0. {
+            base.flags |= HUMANOID_IN_LIQUID_BIT;
+        } else {
+            base.flags &= !HUMANOID_IN_LIQUID_BIT;
+        }
+
         update_humanoid_physics_y(base, physics, ft);
         physics.ddy = 0.;
 
@@ -208,6 +256,44 @@ pub fn update_humanoid_physics(humanoids: &mut HashMap<HumanoidId, Humanoid>, ft
     }
 }
 
+// Buoyancy and drag on a humanoid, summed over the liquid tiles it overlaps and weighted by how
+// much of it each covers. A tile's liquid fills its bottom level / LIQUID_MAX.
+fn humanoid_submersion(base: &HumanoidBase, stride: usize, liquids: &[Liquid]) -> (f32, f32) {
+    if stride == 0 || base.w <= 0. || base.h <= 0. {
+        return (0., 0.);
+    }
+    let tile_size = TILE_SIZE as f32;
+    let x1 = (base.x / tile_size).max(0.) as usize;
+    let y1 = (base.y / tile_size).max(0.) as usize;
+    let x2 = (((base.x + base.w) / tile_size).ceil().max(0.) as usize).min(stride);
+    let y2 = (((base.y + base.h) / tile_size).ceil().max(0.) as usize).min(liquids.len() / stride);
+
+    let (mut buoyancy, mut drag) = (0., 0.);
+    for y in y1..y2 {
+        for x in x1..x2 {
+            let liquid = liquids[x + y * stride];
+            if liquid.is_empty() {
+                continue;
+            }
+            let top =
+                (y + 1) as f32 * tile_size - liquid.level as f32 * tile_size / LIQUID_MAX as f32;
+            let bottom = (y + 1) as f32 * tile_size;
+            let left = x as f32 * tile_size;
+            let right = left + tile_size;
+            let w = right.min(base.x + base.w) - left.max(base.x);
+            let h = bottom.min(base.y + base.h) - top.max(base.y);
+            if w <= 0. || h <= 0. {
+                continue;
+            }
+            let covered = w * h / (base.w * base.h);
+            let property = liquid.property();
+            buoyancy += covered * property.buoyancy;
+            drag += covered * property.drag;
+        }
+    }
+    (buoyancy, drag)
+}
+
 pub fn resolve_humanoid_tile_collisions(
     humanoids: &mut HashMap<HumanoidId, Humanoid>,
     stride: usize,
@@ -227,6 +313,7 @@ pub fn resolve_humanoid_tile_collisions(
 
 pub type HumanoidFlags = u8;
 pub const HUMANOID_ON_GROUND_BIT: u8 = 1 << 1;
+pub const HUMANOID_IN_LIQUID_BIT: u8 = 1 << 2;
 
 #[derive(Clone, Debug, Default, Encode, Decode)]
 pub struct HumanoidInput {
@@ -235,7 +322,7 @@ pub struct HumanoidInput {
     pub right_queue: u8,
 }
 
-#[derive(Clone, Debug, Encode, Decode)]
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
 pub struct HumanoidBase {
     pub x: f32,
     pub y: f32,
@@ -244,7 +331,7 @@ pub struct HumanoidBase {
     pub flags: u8,
 }
 
-#[derive(Clone, Debug, Default, Encode, Decode)]
+#[derive(Clone, Debug, Default, PartialEq, Encode, Decode)]
 pub struct HumanoidPhysics {
     pub last_x: f32,
     pub last_y: f32,
@@ -255,7 +342,7 @@ pub struct HumanoidPhysics {
     pub ddy: f32,
 }
 
-#[derive(Clone, Debug, Encode, Decode)]
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
 pub enum HumanoidAi {
     Player,
     Zombie,
@@ -263,6 +350,96 @@ pub enum HumanoidAi {
 
 pub struct HumanoidAnimation {}
 
+// The parts of a humanoid that changed since a baseline. New humanoids have every part set.
+// Input isn't synced, it only matters to whoever simulates the humanoid.
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub struct HumanoidDelta {
+    pub id: HumanoidId,
+    pub base: Option<HumanoidBase>,
+    pub ai: Option<HumanoidAi>,
+    pub physics: Option<HumanoidPhysics>,
+}
+
+// Returns the deltas that turn baseline into current, and the ids that were removed.
+pub fn diff_humanoids(
+    baseline: &HashMap<HumanoidId, Humanoid>,
+    current: &HashMap<HumanoidId, Humanoid>,
+) -> (Vec<HumanoidDelta>, Vec<HumanoidId>) {
+    fn changed<T: PartialEq + Clone>(old: &T, new: &T) -> Option<T> {
+        (old != new).then(|| new.clone())
+    }
+    let updated = current
+        .iter()
+        .filter_map(|(id, humanoid)| {
+            let delta = match baseline.get(id) {
+                Some(old) => HumanoidDelta {
+                    id: *id,
+                    base: changed(&old.base, &humanoid.base),
+                    ai: changed(&old.ai, &humanoid.ai),
+                    physics: changed(&old.physics, &humanoid.physics),
+                },
+                None => HumanoidDelta {
+                    id: *id,
+                    base: Some(humanoid.base.clone()),
+                    ai: Some(humanoid.ai.clone()),
+                    physics: Some(humanoid.physics.clone()),
+                },
+            };
+            let unchanged = delta.base.is_none() && delta.ai.is_none() && delta.physics.is_none();
+            (!unchanged).then_some(delta)
+        })
+        .collect();
+    let removed = baseline
+        .keys()
+        .filter(|id| !current.contains_key(id))
+        .copied()
+        .collect();
+    (updated, removed)
+}
+
+// Inverse of diff_humanoids. Returns None if a delta refers to a humanoid it can't build.
+pub fn apply_humanoid_deltas(
+    baseline: &HashMap<HumanoidId, Humanoid>,
+    updated: Vec<HumanoidDelta>,
+    removed: &[HumanoidId],
+) -> Option<HashMap<HumanoidId, Humanoid>> {
+    let mut humanoids = baseline.clone();
+    for id in removed {
+        humanoids.remove(id);
+    }
+    for HumanoidDelta {
+        id,
+        base,
+        ai,
+        physics,
+    } in updated
+    {
+        match humanoids.get_mut(&id) {
+            Some(humanoid) => {
+                if let Some(base) = base {
+                    humanoid.base = base;
+                }
+                if let Some(ai) = ai {
+                    humanoid.ai = ai;
+                }
+                if let Some(physics) = physics {
+                    humanoid.physics = physics;
+                }
+            }
+            None => {
+                let humanoid = Humanoid {
+                    base: base?,
+                    ai: ai?,
+                    input: HumanoidInput::default(),
+                    physics: physics?,
+                };
+                humanoids.insert(id, humanoid);
+            }
+        }
+    }
+    Some(humanoids)
+}
+
 pub fn update_humanoid_physics_x(base: &mut HumanoidBase, physics: &mut HumanoidPhysics, ft: f32) {
     physics.last_x = base.x;
     base.x += 0.5 * physics.ddx * ft * ft + physics.dx * ft;
@@ -273,7 +450,7 @@ pub fn resolve_humanoid_tile_collision_x(
     base: &mut HumanoidBase,
     physics: &mut HumanoidPhysics,
     stride: usize,
-    tiles: &Box<[Tile]>,
+    tiles: &[Tile],
 ) {
     // Calculate (x1..x2) based on distance moved.
     let (x1, x2) = if base.x > physics.last_x {
@@ -295,7 +472,7 @@ pub fn resolve_humanoid_tile_collision_x(
         for x in x1..x2 {
             let src_index = x + y * stride;
             let tile = tiles[src_index];
-            let property = TILE_PHYSICS_PROPERTIES[tile as usize]; // TODO pass this in?
+            let property = TILE_PHYSICS_PROPERTIES[tile.0 as usize]; // TODO pass this in?
 
             // Solid.
             if property.solid {
@@ -321,7 +498,7 @@ pub fn resolve_humanoid_tile_collision_y(
     base: &mut HumanoidBase,
     physics: &mut HumanoidPhysics,
     stride: usize,
-    tiles: &Box<[Tile]>,
+    tiles: &[Tile],
 ) {
     // Calculate (x1..x2).
     let x1 = physics.last_x as usize / TILE_SIZE;
@@ -343,7 +520,7 @@ pub fn resolve_humanoid_tile_collision_y(
         for x in x1..x2 {
             let src_index = x + y * stride;
             let tile = tiles[src_index];
-            let property = TILE_PHYSICS_PROPERTIES[tile as usize]; // TODO pass this in?
+            let property = TILE_PHYSICS_PROPERTIES[tile.0 as usize]; // TODO pass this in?
 
             // Solid.
             if property.solid {
@@ -360,3 +537,88 @@ pub fn resolve_humanoid_tile_collision_y(
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn humanoid(x: f32, ai: HumanoidAi) -> Humanoid {
+        Humanoid {
+            base: HumanoidBase {
+                x,
+                y: 0.,
+                w: 24.,
+                h: 40.,
+                flags: 0,
+            },
+            ai,
+            input: HumanoidInput::default(),
+            physics: HumanoidPhysics::default(),
+        }
+    }
+
+    #[test]
+    fn delta_round_trip() {
+        let mut ids = HumanoidId::new();
+        let (a, b, c) = (ids.next(), ids.next(), ids.next());
+        let baseline = HashMap::from([
+            (a, humanoid(0., HumanoidAi::Player)),
+            (b, humanoid(5., HumanoidAi::Zombie)),
+        ]);
+
+        // a moved, b is gone, c is new.
+        let mut current = baseline.clone();
+        current.get_mut(&a).unwrap().base.x = 1.;
+        current.remove(&b);
+        current.insert(c, humanoid(9., HumanoidAi::Zombie));
+
+        let (updated, removed) = diff_humanoids(&baseline, &current);
+        assert_eq!(removed, vec![b]);
+        assert_eq!(updated.len(), 2);
+        let delta_a = updated.iter().find(|delta| delta.id == a).unwrap();
+        assert!(delta_a.base.is_some() && delta_a.ai.is_none() && delta_a.physics.is_none());
+
+        let rebuilt = apply_humanoid_deltas(&baseline, updated, &removed).unwrap();
+        assert_eq!(rebuilt.len(), 2);
+        assert_eq!(rebuilt[&a].base, current[&a].base);
+        assert_eq!(rebuilt[&c].base, current[&c].base);
+
+        // Nothing changed, nothing sent.
+        let (updated, removed) = diff_humanoids(&current, &current);
+        assert!(updated.is_empty() && removed.is_empty());
+
+        // A partial delta for an unknown humanoid can't be applied.
+        let partial = HumanoidDelta {
+            id: ids.next(),
+            base: None,
+            ai: Some(HumanoidAi::Zombie),
+            physics: None,
+        };
+        assert!(apply_humanoid_deltas(&baseline, vec![partial], &[]).is_none());
+    }
+
+    #[test]
+    fn floats_in_water() {
+        // A pool from row 10 down, no tiles to collide with.
+        let (w, h) = (4, 100);
+        let mut liquids = vec![Liquid::NONE; w * h];
+        let water = Liquid::new(crate::shared::LiquidKind::Water, LIQUID_MAX);
+        liquids[10 * w..].fill(water);
+
+        let id = HumanoidId::new();
+        let mut humanoids = HashMap::from([(id, humanoid(16., HumanoidAi::Player))]);
+        let mut dry = humanoids.clone();
+        for _ in 0..600 {
+            update_humanoid_physics(&mut humanoids, w, &liquids, 1. / 60.);
+            update_humanoid_physics(&mut dry, w, &[], 1. / 60.);
+        }
+
+        // Settles with most of its body under the surface.
+        let base = &humanoids[&id].base;
+        let surface = (10 * TILE_SIZE) as f32;
+        assert!(base.y < surface && base.y + base.h > surface, "{base:?}");
+        assert!(humanoids[&id].physics.dy.abs() < 10.);
+        assert!(base.flags & HUMANOID_IN_LIQUID_BIT != 0);
+        assert!(dry[&id].base.y > (h * TILE_SIZE) as f32);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::shared::*;
+use bitcode::{Decode, Encode};
+
+// Slots in an inventory. The first HOTBAR_SIZE of them make up the hotbar.
+pub const INVENTORY_SIZE: usize = 40;
+pub const HOTBAR_SIZE: usize = 10;
+
+// Most of one item a slot can hold.
+pub const MAX_STACK: u16 = 999;
+
+// Something a player can carry. Tile items place their tile when used.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Encode, Decode)]
+pub enum Item {
+    Tile(Tile),
+}
+
+impl Item {
+    // Whether an item received from elsewhere exists.
+    pub fn is_valid(self) -> bool {
+        match self {
+            Self::Tile(tile) => tile != Tile::None && tile.is_registered(),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Tile(tile) => &tile.def().name,
+        }
+    }
+
+    pub fn find(name: &str) -> Option<Self> {
+        let item = Self::Tile(TILE_REGISTRY.find(name)?);
+        item.is_valid().then_some(item)
+    }
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Encode, Decode)]
+pub struct ItemStack {
+    pub item: Item,
+    pub count: u16,
+}
+
+// A player's items. Lives on the server, which sends a copy whenever it changes.
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub struct Inventory {
+    pub slots: Vec<Option<ItemStack>>,
+}
+
+impl Inventory {
+    pub fn new() -> Self {
+        Self {
+            slots: vec![None; INVENTORY_SIZE],
+        }
+    }
+
+    // Tops up the stacks of the item there already are first, then fills empty slots, hotbar
+    // first. Returns how many didn't fit.
+    pub fn add(&mut self, item: Item, mut count: u32) -> u32 {
+        for stack in self.slots.iter_mut().flatten() {
+            if stack.item == item {
+                let n = count.min((MAX_STACK - stack.count) as u32);
+                stack.count += n as u16;
+                count -= n;
+            }
+        }
+        for slot in self.slots.iter_mut().filter(|slot| slot.is_none()) {
+            if count == 0 {
+                break;
+            }
+            let n = count.min(MAX_STACK as u32);
+            *slot = Some(ItemStack {
+                item,
+                count: n as u16,
+            });
+            count -= n;
+        }
+        count
+    }
+
+    // Whether at least one more of the item fits.
+    pub fn has_room_for(&self, item: Item) -> bool {
+        self.slots.iter().any(|slot| match slot {
+            Some(stack) => stack.item == item && stack.count < MAX_STACK,
+            None => true,
+        })
+    }
+
+    // Takes one item out of a slot, emptying it after the last one.
+    pub fn take_one(&mut self, slot: usize) -> Option<Item> {
+        let stack = self.slots.get_mut(slot)?.as_mut()?;
+        let item = stack.item;
+        stack.count -= 1;
+        if stack.count == 0 {
+            self.slots[slot] = None;
+        }
+        Some(item)
+    }
+
+    // Moves the stack in slot from onto slot to. Stacks of the same item merge (as far as they
+    // fit), different ones swap places.
+    pub fn move_stack(&mut self, from: usize, to: usize) {
+        if from == to || from >= self.slots.len() || to >= self.slots.len() {
+            return;
+        }
+        match (self.slots[from], self.slots[to]) {
+            (Some(a), Some(b)) if a.item == b.item => {
+                let n = a.count.min(MAX_STACK - b.count);
+                self.slots[to] = Some(ItemStack {
+                    count: b.count + n,
+                    ..b
+                });
+                self.slots[from] = (n < a.count).then_some(ItemStack {
+                    count: a.count - n,
+                    ..a
+                });
+            }
+            _ => self.slots.swap(from, to),
+        }
+    }
+
+    pub fn count(&self, item: Item) -> u32 {
+        self.slots
+            .iter()
+            .flatten()
+            .filter(|stack| stack.item == item)
+            .map(|stack| stack.count as u32)
+            .sum()
+    }
+
+    // Whether an inventory received from elsewhere (or loaded) is well formed.
+    pub fn is_valid(&self) -> bool {
+        self.slots.len() == INVENTORY_SIZE
+            && self
+                .slots
+                .iter()
+                .flatten()
+                .all(|stack| stack.item.is_valid() && (1..=MAX_STACK).contains(&stack.count))
+    }
+}
+
+impl Default for Inventory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stacks_and_moves() {
+        let dirt = Item::find("dirt").unwrap();
+        let stone = Item::Tile(Tile::Stone);
+        assert_eq!(dirt, Item::Tile(Tile::Dirt));
+        assert_eq!(Item::find("none"), None);
+        assert_eq!(Item::find("unobtainium"), None);
+
+        // Full stacks spill into the next free slot.
+        let mut inventory = Inventory::new();
+        assert_eq!(inventory.add(dirt, MAX_STACK as u32 + 1), 0);
+        assert_eq!(inventory.add(stone, 5), 0);
+        assert_eq!(inventory.slots[0].unwrap().count, MAX_STACK);
+        assert_eq!(inventory.slots[1].unwrap().count, 1);
+        assert_eq!(inventory.slots[2].unwrap().item, stone);
+        assert_eq!(inventory.add(dirt, 2), 0);
+        assert_eq!(inventory.slots[1].unwrap().count, 3);
+
+        // Same items merge, others swap.
+        inventory.move_stack(1, 0);
+        assert_eq!(inventory.slots[0].unwrap().count, MAX_STACK);
+        assert_eq!(inventory.slots[1].unwrap().count, 3);
+        inventory.move_stack(2, 0);
+        assert_eq!(inventory.slots[0].unwrap().item, stone);
+        assert_eq!(inventory.slots[2].unwrap().item, dirt);
+        inventory.take_one(2);
+        inventory.take_one(2);
+        inventory.move_stack(1, 2);
+        assert_eq!(inventory.slots[1].unwrap().count, 1);
+        assert_eq!(inventory.slots[2].unwrap().count, MAX_STACK);
+        inventory.move_stack(1, 2);
+        assert_eq!(inventory.slots[1].unwrap().count, 1);
+        assert_eq!(inventory.count(dirt), MAX_STACK as u32 + 1);
+
+        // Taking the last one empties the slot.
+        for _ in 0..5 {
+            assert_eq!(inventory.take_one(0), Some(stone));
+        }
+        assert_eq!(inventory.take_one(0), None);
+        assert_eq!(inventory.take_one(INVENTORY_SIZE), None);
+        assert!(inventory.is_valid());
+
+        // Nothing fits in a full inventory.
+        let mut full = Inventory::new();
+        assert_eq!(
+            full.add(stone, INVENTORY_SIZE as u32 * MAX_STACK as u32 + 7),
+            7
+        );
+        assert!(!full.has_room_for(dirt));
+        assert!(inventory.has_room_for(dirt));
+
+        let mut bad = inventory.clone();
+        bad.slots[5] = Some(ItemStack {
+            item: Item::Tile(Tile(255)),
+            count: 1,
+        });
+        assert!(!bad.is_valid());
+        bad.slots[5] = Some(ItemStack {
+            item: dirt,
+            count: 0,
+        });
+        assert!(!bad.is_valid());
+        bad.slots.pop();
+        assert!(!bad.is_valid());
+    }
+}
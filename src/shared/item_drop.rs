@@ -0,0 +1,126 @@
+use crate::shared::*;
+use bitcode::{Decode, Encode};
+use std::collections::HashMap;
+
+// Width and height of a drop (in pixels).
+pub const ITEM_DROP_SIZE: f32 = 8.;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Default, Encode, Decode, Hash)]
+pub struct ItemDropId(pub u32);
+
+// An item lying in the world, like what a broken tile leaves behind. Position is the top left
+// corner (in pixels).
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub struct ItemDrop {
+    pub item: Item,
+    pub x: f32,
+    pub y: f32,
+    pub dx: f32,
+    pub dy: f32,
+}
+
+impl ItemDrop {
+    // Pops out of the middle of the tile at (x, y) (in tiles).
+    pub fn new(item: Item, x: usize, y: usize) -> Self {
+        let offset = (TILE_SIZE as f32 - ITEM_DROP_SIZE) / 2.;
+        Self {
+            item,
+            x: (x * TILE_SIZE) as f32 + offset,
+            y: (y * TILE_SIZE) as f32 + offset,
+            dx: 0.,
+            dy: -150.,
+        }
+    }
+}
+
+// Fastest a drop received from elsewhere may move (in pixels per second).
+const MAX_ITEM_DROP_SPEED: f32 = 4096.;
+
+// Whether a drop received from elsewhere can safely be simulated in a world of this size (in
+// tiles).
+pub fn item_drop_is_valid(drop: &ItemDrop, world_w: usize, world_h: usize) -> bool {
+    let min = TILE_SIZE as f32;
+    let max_x = ((world_w.max(1) - 1) * TILE_SIZE) as f32;
+    let max_y = ((world_h.max(1) - 1) * TILE_SIZE) as f32;
+
+    drop.item.is_valid()
+        && drop.x >= min
+        && drop.y >= min
+        && drop.x + ITEM_DROP_SIZE <= max_x
+        && drop.y + ITEM_DROP_SIZE <= max_y
+        && drop.dx.abs() <= MAX_ITEM_DROP_SPEED
+        && drop.dy.abs() <= MAX_ITEM_DROP_SPEED
+}
+
+// Drops fall and collide like humanoids do, and slide to a stop on the ground.
+pub fn update_item_drops(
+    drops: &mut HashMap<ItemDropId, ItemDrop>,
+    stride: usize,
+    tiles: &[Tile],
+    ft: f32,
+) {
+    for drop in drops.values_mut() {
+        let mut base = HumanoidBase {
+            x: drop.x,
+            y: drop.y,
+            w: ITEM_DROP_SIZE,
+            h: ITEM_DROP_SIZE,
+            flags: 0,
+        };
+        let mut physics = HumanoidPhysics {
+            dx: drop.dx,
+            dy: drop.dy,
+            ddy: 500.,
+            ..Default::default()
+        };
+
+        update_humanoid_physics_y(&mut base, &mut physics, ft);
+        update_humanoid_physics_x(&mut base, &mut physics, ft);
+        resolve_humanoid_tile_collision_x(&mut base, &mut physics, stride, tiles);
+        resolve_humanoid_tile_collision_y(&mut base, &mut physics, stride, tiles);
+        if base.flags & HUMANOID_ON_GROUND_BIT != 0 {
+            physics.dx *= (1. - 8. * ft).max(0.);
+        }
+
+        drop.x = base.x;
+        drop.y = base.y;
+        drop.dx = physics.dx;
+        drop.dy = physics.dy;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn drops_land_on_the_ground() {
+        // Open air over a stone floor, in a stone box.
+        let (w, h) = (8, 8);
+        let tiles: Box<[Tile]> = (0..w * h)
+            .map(|i| match (i % w, i / w) {
+                (x, y) if x == 0 || y == 0 || x == w - 1 || y >= h - 2 => Tile::Stone,
+                _ => Tile::None,
+            })
+            .collect();
+        let mut drops =
+            HashMap::from([(ItemDropId(0), ItemDrop::new(Item::Tile(Tile::Dirt), 3, 2))]);
+        assert!(item_drop_is_valid(&drops[&ItemDropId(0)], w, h));
+
+        for _ in 0..120 {
+            update_item_drops(&mut drops, w, &tiles, FRAMETIME as f32 / 1e6);
+        }
+        let drop = &drops[&ItemDropId(0)];
+        assert_eq!(drop.y + ITEM_DROP_SIZE, ((h - 2) * TILE_SIZE) as f32);
+        assert_eq!(drop.dy, 0.);
+        assert!(item_drop_is_valid(drop, w, h));
+
+        // Outside the world, or with nonsense physics.
+        let mut bad = drop.clone();
+        bad.x = f32::NAN;
+        assert!(!item_drop_is_valid(&bad, w, h));
+        bad = drop.clone();
+        bad.item = Item::Tile(Tile(255));
+        assert!(!item_drop_is_valid(&bad, w, h));
+    }
+}
@@ -0,0 +1,96 @@
+use crate::shared::*;
+use bitcode::{Decode, Encode};
+
+// Level of a full tile.
+pub const LIQUID_MAX: u8 = 16;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Encode, Decode)]
+pub enum LiquidKind {
+    None,
+    Water,
+    Lava,
+}
+
+// The liquid in a tile. Empty tiles are always Liquid::NONE.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Encode, Decode)]
+pub struct Liquid {
+    pub kind: LiquidKind,
+    pub level: u8,
+}
+
+impl Liquid {
+    pub const NONE: Liquid = Liquid {
+        kind: LiquidKind::None,
+        level: 0,
+    };
+
+    pub fn new(kind: LiquidKind, level: u8) -> Self {
+        match level {
+            0 => Self::NONE,
+            level => Self { kind, level },
+        }
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.level == 0
+    }
+
+    // Anything received from a peer or read from disk has to be checked with this before use.
+    pub fn is_valid(self) -> bool {
+        (self.kind == LiquidKind::None) == (self.level == 0) && self.level <= LIQUID_MAX
+    }
+
+    pub fn property(self) -> &'static LiquidProperty {
+        &LIQUID_PROPERTIES[self.kind as usize]
+    }
+
+    // Light given off, scaled by how full the tile is.
+    pub fn light(self) -> (u8, u8, u8) {
+        let (r, g, b) = self.property().light;
+        let scale = |c: u8| (c as u16 * self.level as u16 / LIQUID_MAX as u16) as u8;
+        (scale(r), scale(g), scale(b))
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct LiquidProperty {
+    pub fade: u8,
+    pub light: (u8, u8, u8),
+
+    // Upwards acceleration on a fully submerged humanoid (in pixels per second squared). Gravity
+    // is 500.
+    pub buoyancy: f32,
+
+    // Fraction of a fully submerged humanoid's speed lost per second.
+    pub drag: f32,
+
+    // Ticks between flow steps, i.e. how viscous it is.
+    pub flow_interval: u32,
+}
+
+pub const LIQUID_PROPERTIES: [LiquidProperty; 3] = [
+    // None.
+    LiquidProperty {
+        fade: FADE_MIN,
+        light: (0, 0, 0),
+        buoyancy: 0.,
+        drag: 0.,
+        flow_interval: 0,
+    },
+    // Water.
+    LiquidProperty {
+        fade: FADE_MIN + 1,
+        light: (0, 0, 0),
+        buoyancy: 700.,
+        drag: 3.,
+        flow_interval: 4,
+    },
+    // Lava.
+    LiquidProperty {
+        fade: FADE_MIN + 1,
+        light: (LIGHT_MAX - 4, LIGHT_MAX / 2, 0),
+        buoyancy: 800.,
+        drag: 6.,
+        flow_interval: 16,
+    },
+];
@@ -1,17 +1,40 @@
+mod chunk;
 mod collision;
 mod humanoid;
 mod input;
+mod item;
+mod item_drop;
 mod light;
+mod liquid;
 mod net;
+mod player;
+mod rng;
 mod tile;
 
+pub use chunk::*;
 pub use collision::*;
 pub use humanoid::*;
-
+pub use item::*;
+pub use item_drop::*;
 pub use light::*;
+pub use liquid::*;
 pub use net::*;
+pub use player::*;
+pub use rng::*;
 pub use tile::*;
 
+// Update rate (in usecs), shared so both sides simulate input frames identically.
+pub const FRAMETIME: u64 = 16_666;
+
+// Input frames resent with every PlayerInput, to ride out packet loss.
+pub const INPUT_REDUNDANCY: usize = 8;
+
+// Largest world (in tiles) clients agree to load, and so servers make or load.
+pub const MAX_WORLD_AREA: usize = 1 << 25;
+
+// Smallest side (in tiles) of a world clients agree to load.
+pub const MIN_WORLD_SIZE: usize = 64;
+
 // Chunk.
 pub const CHUNK_SIZE: usize = 8;
 pub const CHUNK_AREA: usize = CHUNK_SIZE * CHUNK_SIZE;
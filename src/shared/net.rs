@@ -1,38 +1,125 @@
-use crate::shared::{Humanoid, HumanoidId, Tile, CHUNK_AREA};
+use crate::shared::{
+    ChunkBatch, HumanoidDelta, HumanoidId, HumanoidInput, Inventory, ItemDrop, ItemDropId, Liquid,
+    PlayerInfo, PlayerToken, Tile, TileLayer,
+};
 use bitcode::{decode, encode, Decode, DecodeOwned, Encode};
-use std::collections::HashMap;
 
 pub trait NetMessage: Encode + DecodeOwned {}
 impl NetMessage for ClientNetMessage {}
 impl NetMessage for ServerNetMessage {}
 
+// Version of everything on the wire: the message types here and the types they carry, packet
+// tags, how SendQueue batches messages and how LaminarTransport splits up large payloads. Bump it
+// with any change to those, so mismatched builds refuse each other in the handshake instead of
+// misreading messages. The wire_format_is_pinned test fails until it is bumped.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+pub(crate) const fn fnv1a(bytes: &[u8]) -> u32 {
+    let mut hash = 0x811C_9DC5_u32;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    hash
+}
+
+// Every packet starts with one of these tags.
+const PACKET_HANDSHAKE: u8 = 0;
+const PACKET_MESSAGES: u8 = 1;
+
+// Encoded apart from the message enums so that it decodes the same in every version.
+// Never add, remove or reorder variants or fields.
+#[derive(Clone, Encode, Decode, Debug)]
+pub enum Handshake {
+    Connect { version: u32 },
+    Accept,
+    Reject { version: u32, reason: String },
+}
+
 #[derive(Clone, Encode, Decode, Debug)]
 pub enum ClientNetMessage {
-    Ping,
+    // Answered with a Pong right away, to measure the link.
+    Ping {
+        seq: u32,
+    },
 
-    Connect { version: (u8, u8) },
+    // Answer to the server's Ping. ts is the client's clock when answering (in usecs).
+    Pong {
+        seq: u32,
+        ts: u64,
+    },
 
-    Join,
+    // View size (in pixels), used to decide which chunks to stream.
+    Join {
+        name: String,
+        token: PlayerToken,
+        view_w: u16,
+        view_h: u16,
+    },
 
     JoinComplete,
 
-    SyncPlayer { player: Humanoid },
+    // The most recent unacknowledged input frames, oldest first. seq is the seq of the last one.
+    // snapshot_ack is the seq of the newest HumanoidSync received.
+    PlayerInput {
+        seq: u32,
+        inputs: Vec<HumanoidInput>,
+        snapshot_ack: u32,
+    },
+
+    // Starts mining a tile, or switches to it. The tile breaks once it has been mined for as long
+    // as its hardness, unless StopMining comes first.
+    MineTile {
+        x: u16,
+        y: u16,
+        layer: TileLayer,
+    },
 
-    RequestChunk { x: u16, y: u16, seq: u32 },
+    StopMining,
+
+    // Uses one of the item in an inventory slot on a tile, placing it for tile items.
+    UseItem {
+        slot: u8,
+        x: u16,
+        y: u16,
+        layer: TileLayer,
+    },
+
+    // Moves a stack to another inventory slot, see Inventory::move_stack.
+    MoveItem {
+        from: u8,
+        to: u8,
+    },
+
+    // Closing the game. The player is gone right away instead of waiting for a reconnect.
+    Leave,
+
+    // Said to everyone, or a command if it starts with a slash.
+    Chat {
+        text: String,
+    },
 }
 
 #[derive(Clone, Encode, Decode, Debug)]
 pub enum ServerNetMessage {
-    Ping,
-
-    // Expect Join.
-    ConnectAccept,
+    Ping {
+        seq: u32,
+    },
 
-    ConnectReject {
-        version: (u8, u8),
+    // Answer to the client's Ping. ts is the server's clock when answering (in usecs), and tick
+    // the server's tick at that time.
+    Pong {
+        seq: u32,
+        ts: u64,
+        tick: u32,
     },
 
     JoinAccept {
+        // Hash of the server's tile registry. The client can't make sense of the world unless it
+        // has the same tiles.
+        tiles: u32,
         width: u16,
         height: u16,
         id: HumanoidId,
@@ -40,25 +127,412 @@ pub enum ServerNetMessage {
         spawn_y: u16,
     },
 
+    JoinReject {
+        reason: String,
+    },
+
+    // Everyone connected, sent whenever someone joins or leaves.
+    Roster {
+        players: Vec<PlayerInfo>,
+    },
+
+    // The player's humanoid is gone for good (left, kicked, or didn't come back in time).
+    PlayerLeft {
+        player: PlayerInfo,
+    },
+
+    // Sent right before the server drops the connection.
+    Kick {
+        reason: String,
+    },
+
+    // From a player, or from the server (command replies, announcements) if from is None.
+    Chat {
+        from: Option<String>,
+        text: String,
+    },
+
+    // Time of day, as a fraction of the day. Sent on join and whenever it is set.
+    Time {
+        time: f32,
+    },
+
+    // The receiving player's whole inventory. Sent on join and whenever it changes.
+    InventorySync {
+        inventory: Inventory,
+    },
+
+    // State messages carry the server tick they are from.
+
+    // Whole chunks, see pack_chunks.
     ChunkSync {
+        tick: u32,
+        chunks: ChunkBatch,
+    },
+
+    // A single tile edit. seq is the chunk's seq after the edit.
+    TileSync {
+        tick: u32,
         x: u16,
         y: u16,
         seq: u32,
-        fg_tiles: [Tile; CHUNK_AREA],
-        bg_tiles: [Tile; CHUNK_AREA],
+        fg_tile: Tile,
+        bg_tile: Tile,
+        liquid: Liquid,
     },
 
+    // How far along someone mining a tile is, from 0 (not at all, or stopped) to CRACK_STAGES.
+    TileCrack {
+        tick: u32,
+        x: u16,
+        y: u16,
+        layer: TileLayer,
+        stage: u8,
+    },
+
+    // Every drop near the receiving player (up to a limit), replacing the ones sent before.
+    ItemDropSync {
+        tick: u32,
+        drops: Vec<(ItemDropId, ItemDrop)>,
+    },
+
+    // The humanoids near the receiving player, as a delta against the snapshot with seq
+    // baseline (0 for none). ack is the seq of the last input frame simulated for the player.
     HumanoidSync {
-        humanoids: HashMap<HumanoidId, Humanoid>,
+        tick: u32,
+        seq: u32,
+        baseline: u32,
+        ack: u32,
+        updated: Vec<HumanoidDelta>,
+        removed: Vec<HumanoidId>,
     },
 
     Start,
 }
 
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum DecodeErr {
+    Empty,
+
+    // Tagged as some other kind of packet.
+    WrongTag(u8),
+
+    // Right tag, but the rest doesn't decode.
+    Malformed,
+}
+
+impl std::fmt::Display for DecodeErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Self::Empty => write!(f, "empty packet"),
+            Self::WrongTag(tag) => write!(f, "unexpected packet tag {tag}"),
+            Self::Malformed => write!(f, "malformed packet"),
+        }
+    }
+}
+
+fn untag(bytes: &[u8], tag: u8) -> Result<&[u8], DecodeErr> {
+    match bytes.split_first() {
+        None => Err(DecodeErr::Empty),
+        Some((&found, bytes)) if found == tag => Ok(bytes),
+        Some((&found, _)) => Err(DecodeErr::WrongTag(found)),
+    }
+}
+
 pub fn serialize(msgs: &[impl NetMessage]) -> Box<[u8]> {
-    encode(msgs).into_boxed_slice()
+    let mut bytes = vec![PACKET_MESSAGES];
+    bytes.extend_from_slice(&encode(msgs));
+    bytes.into_boxed_slice()
 }
 
-pub fn deserialize<T: NetMessage>(bytes: Box<[u8]>) -> Box<[T]> {
-    decode(&bytes).unwrap_or_default()
+pub fn deserialize<T: NetMessage>(bytes: &[u8]) -> Result<Box<[T]>, DecodeErr> {
+    let bytes = untag(bytes, PACKET_MESSAGES)?;
+    decode(bytes).map_err(|_| DecodeErr::Malformed)
+}
+
+pub fn serialize_handshake(handshake: &Handshake) -> Box<[u8]> {
+    let mut bytes = vec![PACKET_HANDSHAKE];
+    bytes.extend_from_slice(&encode(handshake));
+    bytes.into_boxed_slice()
+}
+
+pub fn deserialize_handshake(bytes: &[u8]) -> Result<Handshake, DecodeErr> {
+    let bytes = untag(bytes, PACKET_HANDSHAKE)?;
+    decode(bytes).map_err(|_| DecodeErr::Malformed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::net::fragment_header;
+    use crate::shared::*;
+
+    #[test]
+    fn packets_are_tagged() {
+        let handshake = serialize_handshake(&Handshake::Connect {
+            version: PROTOCOL_VERSION,
+        });
+        let msgs = serialize(&[
+            ClientNetMessage::Ping { seq: 0 },
+            ClientNetMessage::JoinComplete,
+        ]);
+
+        // Handshakes aren't messages, and messages aren't handshakes.
+        assert_eq!(
+            deserialize::<ClientNetMessage>(&handshake).unwrap_err(),
+            DecodeErr::WrongTag(PACKET_HANDSHAKE)
+        );
+        assert_eq!(
+            deserialize_handshake(&msgs).unwrap_err(),
+            DecodeErr::WrongTag(PACKET_MESSAGES)
+        );
+
+        assert!(matches!(
+            deserialize_handshake(&handshake),
+            Ok(Handshake::Connect { version }) if version == PROTOCOL_VERSION
+        ));
+        assert_eq!(deserialize::<ClientNetMessage>(&msgs).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn bad_packets_are_errors() {
+        let msgs = serialize(&[ServerNetMessage::Start]);
+        assert_eq!(
+            deserialize::<ServerNetMessage>(&[]).unwrap_err(),
+            DecodeErr::Empty
+        );
+        assert_eq!(
+            deserialize::<ServerNetMessage>(&[7]).unwrap_err(),
+            DecodeErr::WrongTag(7)
+        );
+        assert_eq!(
+            deserialize::<ServerNetMessage>(&msgs[..1]).unwrap_err(),
+            DecodeErr::Malformed
+        );
+        assert_eq!(
+            deserialize_handshake(&[PACKET_HANDSHAKE, 200]).unwrap_err(),
+            DecodeErr::Malformed
+        );
+    }
+
+    // One of every message, so a change to any of them (or anything they carry) changes the
+    // encodings. The variant numbers are exhaustive, so new variants need a sample to compile.
+    fn client_samples() -> Vec<ClientNetMessage> {
+        let samples = vec![
+            ClientNetMessage::Ping { seq: 1 },
+            ClientNetMessage::Pong { seq: 1, ts: 2 },
+            ClientNetMessage::Join {
+                name: String::from("Player"),
+                token: PlayerToken(3),
+                view_w: 1280,
+                view_h: 720,
+            },
+            ClientNetMessage::JoinComplete,
+            ClientNetMessage::PlayerInput {
+                seq: 4,
+                inputs: vec![HumanoidInput {
+                    jump_queue: 1,
+                    left_queue: 2,
+                    right_queue: 3,
+                }],
+                snapshot_ack: 5,
+            },
+            ClientNetMessage::MineTile {
+                x: 6,
+                y: 7,
+                layer: TileLayer::Bg,
+            },
+            ClientNetMessage::StopMining,
+            ClientNetMessage::UseItem {
+                slot: 8,
+                x: 9,
+                y: 10,
+                layer: TileLayer::Fg,
+            },
+            ClientNetMessage::MoveItem { from: 11, to: 12 },
+            ClientNetMessage::Leave,
+            ClientNetMessage::Chat {
+                text: String::from("hi"),
+            },
+        ];
+        let variants: Vec<_> = samples
+            .iter()
+            .map(|msg| match msg {
+                ClientNetMessage::Ping { .. } => 0,
+                ClientNetMessage::Pong { .. } => 1,
+                ClientNetMessage::Join { .. } => 2,
+                ClientNetMessage::JoinComplete => 3,
+                ClientNetMessage::PlayerInput { .. } => 4,
+                ClientNetMessage::MineTile { .. } => 5,
+                ClientNetMessage::StopMining => 6,
+                ClientNetMessage::UseItem { .. } => 7,
+                ClientNetMessage::MoveItem { .. } => 8,
+                ClientNetMessage::Leave => 9,
+                ClientNetMessage::Chat { .. } => 10,
+            })
+            .collect();
+        assert_eq!(variants, Vec::from_iter(0..=10));
+        samples
+    }
+
+    fn server_samples() -> Vec<ServerNetMessage> {
+        let id = HumanoidId::new().next();
+        let player = PlayerInfo {
+            id,
+            name: String::from("Player"),
+        };
+        let mut inventory = Inventory::new();
+        inventory.add(Item::Tile(Tile::Dirt), 13);
+        let samples = vec![
+            ServerNetMessage::Ping { seq: 1 },
+            ServerNetMessage::Pong {
+                seq: 1,
+                ts: 2,
+                tick: 3,
+            },
+            ServerNetMessage::JoinAccept {
+                tiles: 4,
+                width: 5,
+                height: 6,
+                id,
+                spawn_x: 7,
+                spawn_y: 8,
+            },
+            ServerNetMessage::JoinReject {
+                reason: String::from("no"),
+            },
+            ServerNetMessage::Roster {
+                players: vec![player.clone()],
+            },
+            ServerNetMessage::PlayerLeft { player },
+            ServerNetMessage::Kick {
+                reason: String::from("bye"),
+            },
+            ServerNetMessage::Chat {
+                from: Some(String::from("Player")),
+                text: String::from("hi"),
+            },
+            ServerNetMessage::Time { time: 0.5 },
+            ServerNetMessage::InventorySync { inventory },
+            ServerNetMessage::ChunkSync {
+                tick: 9,
+                chunks: ChunkBatch {
+                    deflated: true,
+                    bytes: Box::new([10, 11]),
+                },
+            },
+            ServerNetMessage::TileSync {
+                tick: 12,
+                x: 13,
+                y: 14,
+                seq: 15,
+                fg_tile: Tile::Stone,
+                bg_tile: Tile::Dirt,
+                liquid: Liquid {
+                    kind: LiquidKind::Lava,
+                    level: 16,
+                },
+            },
+            ServerNetMessage::TileCrack {
+                tick: 17,
+                x: 18,
+                y: 19,
+                layer: TileLayer::Fg,
+                stage: 2,
+            },
+            ServerNetMessage::ItemDropSync {
+                tick: 20,
+                drops: vec![(
+                    ItemDropId(21),
+                    ItemDrop {
+                        item: Item::Tile(Tile::Stone),
+                        x: 22.,
+                        y: 23.,
+                        dx: 24.,
+                        dy: 25.,
+                    },
+                )],
+            },
+            ServerNetMessage::HumanoidSync {
+                tick: 26,
+                seq: 27,
+                baseline: 28,
+                ack: 29,
+                updated: vec![HumanoidDelta {
+                    id,
+                    base: Some(HumanoidBase {
+                        x: 30.,
+                        y: 31.,
+                        w: 24.,
+                        h: 40.,
+                        flags: HUMANOID_ON_GROUND_BIT,
+                    }),
+                    ai: Some(HumanoidAi::Zombie),
+                    physics: Some(HumanoidPhysics {
+                        max_dx: 50.,
+                        dx: 32.,
+                        ..Default::default()
+                    }),
+                }],
+                removed: vec![id],
+            },
+            ServerNetMessage::Start,
+        ];
+        let variants: Vec<_> = samples
+            .iter()
+            .map(|msg| match msg {
+                ServerNetMessage::Ping { .. } => 0,
+                ServerNetMessage::Pong { .. } => 1,
+                ServerNetMessage::JoinAccept { .. } => 2,
+                ServerNetMessage::JoinReject { .. } => 3,
+                ServerNetMessage::Roster { .. } => 4,
+                ServerNetMessage::PlayerLeft { .. } => 5,
+                ServerNetMessage::Kick { .. } => 6,
+                ServerNetMessage::Chat { .. } => 7,
+                ServerNetMessage::Time { .. } => 8,
+                ServerNetMessage::InventorySync { .. } => 9,
+                ServerNetMessage::ChunkSync { .. } => 10,
+                ServerNetMessage::TileSync { .. } => 11,
+                ServerNetMessage::TileCrack { .. } => 12,
+                ServerNetMessage::ItemDropSync { .. } => 13,
+                ServerNetMessage::HumanoidSync { .. } => 14,
+                ServerNetMessage::Start => 15,
+            })
+            .collect();
+        assert_eq!(variants, Vec::from_iter(0..=15));
+        samples
+    }
+
+    #[test]
+    fn wire_format_is_pinned() {
+        let mut packets = vec![
+            serialize_handshake(&Handshake::Connect { version: 1 }),
+            serialize_handshake(&Handshake::Accept),
+            serialize_handshake(&Handshake::Reject {
+                version: 1,
+                reason: String::from("no"),
+            }),
+            serialize(&client_samples()),
+            serialize(&server_samples()),
+            Box::new(fragment_header(1, 2, 3)),
+        ];
+        packets.extend(client_samples().into_iter().map(|msg| serialize(&[msg])));
+        packets.extend(server_samples().into_iter().map(|msg| serialize(&[msg])));
+        let bytes: Vec<u8> = packets
+            .iter()
+            .flat_map(|packet| {
+                (packet.len() as u32)
+                    .to_le_bytes()
+                    .into_iter()
+                    .chain(packet.iter().copied())
+            })
+            .collect();
+
+        // If this fails, the wire format changed: bump PROTOCOL_VERSION, then pin the new pair.
+        assert_eq!(
+            (PROTOCOL_VERSION, fnv1a(&bytes)),
+            (2, 0x0758_2525),
+            "wire format changed, bump PROTOCOL_VERSION"
+        );
+    }
 }
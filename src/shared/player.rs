@@ -0,0 +1,64 @@
+use crate::shared::HumanoidId;
+use bitcode::{Decode, Encode};
+
+// Longest player name (in chars).
+pub const MAX_PLAYER_NAME_LEN: usize = 16;
+
+// Longest chat message (in chars).
+pub const MAX_CHAT_LEN: usize = 256;
+
+// Secret a client presents to claim its player record. Generated once by the client and kept.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Encode, Decode)]
+pub struct PlayerToken(pub u128);
+
+impl PlayerToken {
+    // Hex, for storing in a file.
+    pub fn to_hex(&self) -> String {
+        format!("{:032x}", self.0)
+    }
+
+    pub fn from_hex(s: &str) -> Option<Self> {
+        let s = s.trim();
+        if s.len() != 32 {
+            return None;
+        }
+        u128::from_str_radix(s, 16).ok().map(Self)
+    }
+}
+
+// An entry of the roster of connected players.
+#[derive(Clone, Debug, PartialEq, Encode, Decode)]
+pub struct PlayerInfo {
+    pub id: HumanoidId,
+    pub name: String,
+}
+
+// Names are 1 to MAX_PLAYER_NAME_LEN letters, digits, spaces, '-' and '_', not starting or ending
+// with a space.
+pub fn player_name_is_valid(name: &str) -> bool {
+    let len = name.chars().count();
+    (1..=MAX_PLAYER_NAME_LEN).contains(&len)
+        && name.trim() == name
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == ' ' || c == '-' || c == '_')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn names_and_tokens() {
+        assert!(player_name_is_valid("Steve"));
+        assert!(player_name_is_valid("dig_dug 2"));
+        assert!(!player_name_is_valid(""));
+        assert!(!player_name_is_valid(" Steve"));
+        assert!(!player_name_is_valid("Steve\n"));
+        assert!(!player_name_is_valid("a name that is far too long"));
+
+        let token = PlayerToken(0x0123_4567_89ab_cdef_0011_2233_4455_6677);
+        assert_eq!(PlayerToken::from_hex(&token.to_hex()), Some(token));
+        assert_eq!(PlayerToken::from_hex("xyz"), None);
+    }
+}
@@ -1,35 +1,98 @@
 use crate::shared::*;
-use bitcode::{Decode, Encode};
+use bitcode::{encode, Decode, Encode};
 use lazy_static::lazy_static;
+use std::collections::HashSet;
 
 pub const TILE_SIZE: usize = 16;
 pub const TILE_BORDER_SIZE: usize = 4;
 
-#[repr(u8)]
-#[derive(Copy, Clone, Debug, Eq, PartialEq, Encode, Decode)]
-pub enum Tile {
-    None = 0,
-    Dirt,
-    Stone,
-    DenseStone,
+// Index into the tile registry. Stable: stored in worlds and sent over the network.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Encode, Decode)]
+pub struct Tile(pub u8);
+
+// Tiles the game itself refers to. Everything else only exists in resources/tiles.def.
+#[allow(non_upper_case_globals)]
+impl Tile {
+    pub const None: Tile = Tile(0);
+    pub const Dirt: Tile = Tile(1);
+    pub const Stone: Tile = Tile(2);
+    pub const DenseStone: Tile = Tile(3);
+    //
+    pub const RedTorch: Tile = Tile(4);
+    pub const GreenTorch: Tile = Tile(5);
+    pub const BlueTorch: Tile = Tile(6);
     //
-    RedTorch,
-    GreenTorch,
-    BlueTorch,
+    pub const Sand: Tile = Tile(7);
+    pub const Gravel: Tile = Tile(8);
+}
 
-    // Last element
-    SIZE,
+// Names the built-in tiles must have in the definitions.
+const BUILTIN_TILES: [(Tile, &str); 9] = [
+    (Tile::None, "none"),
+    (Tile::Dirt, "dirt"),
+    (Tile::Stone, "stone"),
+    (Tile::DenseStone, "dense_stone"),
+    (Tile::RedTorch, "red_torch"),
+    (Tile::GreenTorch, "green_torch"),
+    (Tile::BlueTorch, "blue_torch"),
+    (Tile::Sand, "sand"),
+    (Tile::Gravel, "gravel"),
+];
+
+impl Tile {
+    // Anything received from a peer or read from disk has to be checked with this before use.
+    pub fn is_registered(self) -> bool {
+        (self.0 as usize) < TILE_REGISTRY.len()
+    }
+
+    pub fn def(self) -> &'static TileDef {
+        &TILE_REGISTRY.defs[self.0 as usize]
+    }
 }
 
-const TILE_COUNT: usize = Tile::SIZE as usize;
+// Max distance from a player's center to the center of a tile they edit.
+pub const TILE_REACH: f32 = (8 * TILE_SIZE) as f32;
+
+// Tiles being mined crack in this many stages on the way to breaking.
+pub const CRACK_STAGES: u8 = 4;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash, Encode, Decode)]
+pub enum TileLayer {
+    Fg,
+    Bg,
+}
 
 lazy_static! {
-    pub static ref TILE_LIGHT_PROPERTIES: [TileLightProperty; TILE_COUNT] =
-        TileLightProperty::gen();
-    pub static ref TILE_TEXTURE_PROPERTIES: [TileTextureProperty; TILE_COUNT] =
-        TileTextureProperty::gen();
-    pub static ref TILE_PHYSICS_PROPERTIES: [TilePhysicsProperty; TILE_COUNT] =
-        TilePhysicsProperty::gen();
+    pub static ref TILE_REGISTRY: TileRegistry =
+        match TileRegistry::parse(include_str!("../../resources/tiles.def")) {
+            Ok(registry) => registry,
+            Err(err) => panic!("Bad resources/tiles.def: {err}"),
+        };
+    pub static ref TILE_LIGHT_PROPERTIES: Box<[TileLightProperty]> = TILE_REGISTRY
+        .defs
+        .iter()
+        .map(|def| TileLightProperty {
+            fade: def.fade,
+            light: def.light,
+        })
+        .collect();
+    pub static ref TILE_TEXTURE_PROPERTIES: Box<[TileTextureProperty]> = TILE_REGISTRY
+        .defs
+        .iter()
+        .map(|def| TileTextureProperty {
+            u: def.uv.0 as f32,
+            v: def.uv.1 as f32,
+            depth: def.depth,
+        })
+        .collect();
+    pub static ref TILE_PHYSICS_PROPERTIES: Box<[TilePhysicsProperty]> = TILE_REGISTRY
+        .defs
+        .iter()
+        .map(|def| TilePhysicsProperty {
+            solid: def.solid,
+            falls: def.falls,
+        })
+        .collect();
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -38,44 +101,6 @@ pub struct TileLightProperty {
     pub light: (u8, u8, u8),
 }
 
-impl TileLightProperty {
-    fn gen() -> [Self; TILE_COUNT] {
-        // Generate default map.
-        let mut map = [Self {
-            fade: FADE_MIN,
-            light: (0, 0, 0),
-        }; TILE_COUNT];
-
-        // Fill.
-        map[Tile::Dirt as usize] = Self {
-            fade: FADE_SOLID,
-            light: (0, 0, 0),
-        };
-        map[Tile::Stone as usize] = Self {
-            fade: FADE_SOLID,
-            light: (0, 0, 0),
-        };
-        map[Tile::DenseStone as usize] = Self {
-            fade: FADE_DENSE,
-            light: (0, 0, 0),
-        };
-        map[Tile::RedTorch as usize] = Self {
-            fade: FADE_MIN,
-            light: (LIGHT_MAX - 10, 0, 0),
-        };
-        map[Tile::GreenTorch as usize] = Self {
-            fade: FADE_MIN,
-            light: (0, LIGHT_MAX - 10, 0),
-        };
-        map[Tile::BlueTorch as usize] = Self {
-            fade: FADE_MIN,
-            light: (0, 0, LIGHT_MAX - 10),
-        };
-
-        return map;
-    }
-}
-
 #[derive(Copy, Clone, Debug)]
 pub struct TileTextureProperty {
     pub u: f32,
@@ -83,49 +108,262 @@ pub struct TileTextureProperty {
     pub depth: u8,
 }
 
-impl TileTextureProperty {
-    fn gen() -> [Self; TILE_COUNT] {
-        // Generate default map.
-        let mut map = [Self {
-            u: 0.,
-            v: 0.,
+#[derive(Copy, Clone, Debug)]
+pub struct TilePhysicsProperty {
+    pub solid: bool,
+    pub falls: bool,
+}
+
+// One tile, as defined in resources/tiles.def.
+#[derive(Clone, Debug, PartialEq, Encode)]
+pub struct TileDef {
+    pub name: String,
+    pub solid: bool,
+    pub fade: u8,
+    pub light: (u8, u8, u8),
+    // In pixels.
+    pub uv: (u16, u16),
+    pub depth: u8,
+    // Time to mine (in seconds).
+    pub hardness: f32,
+    // Tile::None drops nothing.
+    pub drops: Tile,
+    // Falls when nothing solid is under it.
+    pub falls: bool,
+}
+
+impl TileDef {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            solid: false,
+            fade: FADE_MIN,
+            light: (0, 0, 0),
+            uv: (0, 0),
             depth: 0,
-        }; TILE_COUNT];
+            hardness: 0.,
+            drops: Tile::None,
+            falls: false,
+        }
+    }
+}
 
-        // Fill.
-        map[Tile::Dirt as usize] = Self {
-            u: 16.,
-            v: 0.,
-            depth: 1,
-        };
-        map[Tile::Stone as usize] = Self {
-            u: 32.,
-            v: 0.,
-            depth: 2,
-        };
-        map[Tile::DenseStone as usize] = Self {
-            u: 48.,
-            v: 0.,
-            depth: 3,
-        };
+#[derive(Clone, Debug, PartialEq)]
+pub struct TileRegistryErr {
+    // 1-based, 0 if the problem isn't on any one line.
+    pub line: usize,
+    pub what: String,
+}
 
-        return map;
+impl std::fmt::Display for TileRegistryErr {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.line {
+            0 => write!(f, "{}", self.what),
+            line => write!(f, "line {line}: {}", self.what),
+        }
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-pub struct TilePhysicsProperty {
-    pub solid: bool,
+// Every tile, indexed by id.
+#[derive(Clone, Debug)]
+pub struct TileRegistry {
+    defs: Vec<TileDef>,
+}
+
+impl TileRegistry {
+    // See resources/tiles.def for the format.
+    pub fn parse(src: &str) -> Result<Self, TileRegistryErr> {
+        let mut defs: Vec<TileDef> = Vec::new();
+        let mut keys = HashSet::new();
+        // (line, tile, name), resolved once every name is known.
+        let mut drops = Vec::new();
+
+        for (i, line) in src.lines().enumerate() {
+            let err = |what: String| TileRegistryErr { line: i + 1, what };
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            // New tile.
+            if let Some(rest) = line.strip_prefix("tile ") {
+                let (id, name) = match rest.split_whitespace().collect::<Vec<_>>()[..] {
+                    [id, name] => (id, name),
+                    _ => return Err(err(String::from("expected tile <id> <name>"))),
+                };
+                let id: u8 = id.parse().map_err(|_| err(format!("bad id {id}")))?;
+                if id as usize != defs.len() {
+                    return Err(err(format!("expected id {}, got {id}", defs.len())));
+                }
+                if name.is_empty()
+                    || !name
+                        .chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+                {
+                    return Err(err(format!("bad name {name}")));
+                }
+                if defs.iter().any(|def| def.name == name) {
+                    return Err(err(format!("duplicate name {name}")));
+                }
+                defs.push(TileDef::new(name));
+                keys.clear();
+                continue;
+            }
+
+            // Property of the last tile.
+            let Some((key, value)) = line.split_once('=') else {
+                return Err(err(format!("expected tile or key = value, got {line}")));
+            };
+            let (key, value) = (key.trim(), value.trim());
+            let Some(def) = defs.last_mut() else {
+                return Err(err(format!("{key} outside of a tile")));
+            };
+            if !keys.insert(key.to_string()) {
+                return Err(err(format!("duplicate {key}")));
+            }
+            let bad = || err(format!("bad {key} {value}"));
+            let numbers = |n: usize, max: u16| -> Result<Vec<u16>, TileRegistryErr> {
+                let numbers: Vec<u16> = value
+                    .split_whitespace()
+                    .map(|s| s.parse().ok().filter(|&x| x <= max))
+                    .collect::<Option<_>>()
+                    .ok_or_else(bad)?;
+                if numbers.len() != n {
+                    return Err(bad());
+                }
+                Ok(numbers)
+            };
+            match key {
+                "solid" => def.solid = value.parse().map_err(|_| bad())?,
+                "falls" => def.falls = value.parse().map_err(|_| bad())?,
+                "fade" => {
+                    def.fade = match value {
+                        "min" => FADE_MIN,
+                        "solid" => FADE_SOLID,
+                        "dense" => FADE_DENSE,
+                        _ => match value.parse() {
+                            Ok(fade) if (FADE_MIN..=LIGHT_MAX).contains(&fade) => fade,
+                            _ => return Err(bad()),
+                        },
+                    }
+                }
+                "light" => {
+                    let light = numbers(3, LIGHT_MAX as u16)?;
+                    def.light = (light[0] as u8, light[1] as u8, light[2] as u8);
+                }
+                "uv" => {
+                    let uv = numbers(2, u16::MAX)?;
+                    def.uv = (uv[0], uv[1]);
+                }
+                "depth" => def.depth = numbers(1, u8::MAX as u16)?[0] as u8,
+                "hardness" => match value.parse::<f32>() {
+                    Ok(hardness) if hardness.is_finite() && hardness >= 0. => {
+                        def.hardness = hardness
+                    }
+                    _ => return Err(bad()),
+                },
+                "drops" => drops.push((i + 1, defs.len() - 1, value.to_string())),
+                _ => return Err(err(format!("unknown key {key}"))),
+            }
+        }
+
+        for (line, tile, name) in drops {
+            let Some(id) = defs.iter().position(|def| def.name == name) else {
+                return Err(TileRegistryErr {
+                    line,
+                    what: format!("drops unknown tile {name}"),
+                });
+            };
+            defs[tile].drops = Tile(id as u8);
+        }
+
+        for (tile, name) in BUILTIN_TILES {
+            if defs.get(tile.0 as usize).map(|def| def.name.as_str()) != Some(name) {
+                return Err(TileRegistryErr {
+                    line: 0,
+                    what: format!("tile {} must be {name}", tile.0),
+                });
+            }
+        }
+
+        Ok(Self { defs })
+    }
+
+    pub fn len(&self) -> usize {
+        self.defs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.defs.is_empty()
+    }
+
+    pub fn get(&self, tile: Tile) -> Option<&TileDef> {
+        self.defs.get(tile.0 as usize)
+    }
+
+    pub fn find(&self, name: &str) -> Option<Tile> {
+        let id = self.defs.iter().position(|def| def.name == name)?;
+        Some(Tile(id as u8))
+    }
+
+    // Changes with anything that matters about any tile, comments and layout aside. Peers with
+    // different hashes can't share a world.
+    pub fn hash(&self) -> u32 {
+        fnv1a(&encode(&self.defs))
+    }
 }
 
-impl TilePhysicsProperty {
-    fn gen() -> [Self; TILE_COUNT] {
-        // Generate default map.
-        let mut map = [Self { solid: true }; TILE_COUNT];
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_definitions() {
+        // The bundled ones match what the game used to hardcode.
+        let registry = &*TILE_REGISTRY;
+        assert_eq!(registry.find("dense_stone"), Some(Tile::DenseStone));
+        assert_eq!(Tile::Dirt.def().drops, Tile::Dirt);
+        assert_eq!(
+            TILE_LIGHT_PROPERTIES[Tile::Stone.0 as usize].fade,
+            FADE_SOLID
+        );
+        assert_eq!(
+            TILE_LIGHT_PROPERTIES[Tile::BlueTorch.0 as usize].light,
+            (0, 0, 30)
+        );
+        assert_eq!(TILE_TEXTURE_PROPERTIES[Tile::DenseStone.0 as usize].u, 48.);
+        assert!(!TILE_PHYSICS_PROPERTIES[Tile::None.0 as usize].solid);
+        assert!(TILE_PHYSICS_PROPERTIES[Tile::RedTorch.0 as usize].solid);
+        assert!(!Tile(registry.len() as u8).is_registered());
+        assert!(TILE_PHYSICS_PROPERTIES[Tile::Sand.0 as usize].falls);
+        assert!(!TILE_PHYSICS_PROPERTIES[Tile::Dirt.0 as usize].falls);
 
-        // Fill.
-        map[Tile::None as usize] = Self { solid: false };
+        let builtins: String = BUILTIN_TILES
+            .iter()
+            .map(|(tile, name)| format!("tile {} {name}\n", tile.0))
+            .collect();
+        let parse = |extra: &str| TileRegistry::parse(&format!("{builtins}{extra}"));
+        let added = parse("tile 9 glass # Clear.\nfade = 2\nuv = 64 16\ndrops = none\n").unwrap();
+        assert_eq!(added.get(Tile(9)).unwrap().uv, (64, 16));
+        assert_ne!(added.hash(), parse("").unwrap().hash());
 
-        return map;
+        let line = |extra: &str| parse(extra).unwrap_err().line;
+        assert_eq!(line("tile 10 glass"), 10);
+        assert_eq!(line("tile 8 glass"), 10);
+        assert_eq!(line("tile 9 dirt"), 10);
+        assert_eq!(line("tile 9 glass\nshiny = true"), 11);
+        assert_eq!(line("tile 9 glass\nsolid = yes"), 11);
+        assert_eq!(line("tile 9 glass\nfalls = 1"), 11);
+        assert_eq!(line("tile 9 glass\nlight = 1 2"), 11);
+        assert_eq!(line("tile 9 glass\nlight = 1 2 41"), 11);
+        assert_eq!(line("tile 9 glass\nfade = 0"), 11);
+        assert_eq!(line("tile 9 glass\ndepth = 1\ndepth = 2"), 12);
+        assert_eq!(line("tile 9 glass\n\ndrops = mud"), 12);
+        assert_eq!(
+            TileRegistry::parse("tile 0 none\ntile 1 mud\n")
+                .unwrap_err()
+                .to_string(),
+            "tile 1 must be dirt"
+        );
     }
 }
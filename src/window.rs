@@ -102,16 +102,19 @@ impl EventLoop {
         Self { event_loop }
     }
 
-    pub fn run(self, mut f: impl FnMut(InputEvent)) -> ! {
+    // Feeds events to f until done says so.
+    pub fn run(self, mut f: impl FnMut(InputEvent), done: impl Fn() -> bool) {
         // We have to do our own sleeping.
         self.event_loop
             .set_control_flow(winit::event_loop::ControlFlow::Poll);
 
-        // Never ending loop.
         self.event_loop
-            .run(move |event, _| {
+            .run(move |event, target| {
                 // If we're out of events, sleep for 16ms and try agian.
                 if let winit::event::Event::AboutToWait = event {
+                    if done() {
+                        target.exit();
+                    }
                     std::thread::sleep_ms(5);
                     return;
                 }
@@ -122,8 +125,6 @@ impl EventLoop {
                 }
             })
             .unwrap();
-
-        std::process::exit(-1);
     }
 
     pub fn poll(&mut self) -> Vec<InputEvent> {